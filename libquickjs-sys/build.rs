@@ -8,6 +8,65 @@ fn exists(path: impl AsRef<Path>) -> bool {
 
 const LIB_NAME: &str = "quickjs";
 
+/// Class ids of the `JSClassEnum` in quickjs.c, with `CONFIG_BIGNUM`.
+///
+/// The enum is not part of the public header, so the ids are exported as
+/// constants, and checked when compiling the bundled library.
+const CLASS_IDS: &[(&str, u32)] = &[
+    ("JS_CLASS_OBJECT", 1),
+    ("JS_CLASS_ERROR", 3),
+    ("JS_CLASS_BYTECODE_FUNCTION", 13),
+    ("JS_CLASS_ARRAY_BUFFER", 19),
+    ("JS_CLASS_SHARED_ARRAY_BUFFER", 20),
+    ("JS_CLASS_UINT8C_ARRAY", 21),
+    ("JS_CLASS_INT8_ARRAY", 22),
+    ("JS_CLASS_UINT8_ARRAY", 23),
+    ("JS_CLASS_INT16_ARRAY", 24),
+    ("JS_CLASS_UINT16_ARRAY", 25),
+    ("JS_CLASS_INT32_ARRAY", 26),
+    ("JS_CLASS_UINT32_ARRAY", 27),
+    ("JS_CLASS_BIG_INT64_ARRAY", 28),
+    ("JS_CLASS_BIG_UINT64_ARRAY", 29),
+    ("JS_CLASS_FLOAT32_ARRAY", 30),
+    ("JS_CLASS_FLOAT64_ARRAY", 31),
+    ("JS_CLASS_MAP", 38),
+    ("JS_CLASS_SET", 39),
+];
+
+/// Write the class id constants to `$OUT_DIR/class_ids.rs`.
+fn write_class_ids(out_path: &Path) {
+    let constants = CLASS_IDS
+        .iter()
+        .map(|(name, id)| {
+            format!(
+                "/// `{}` from quickjs.c.\npub const {}: JSClassID = {};\n",
+                name, name, id
+            )
+        })
+        .collect::<String>();
+    std::fs::write(out_path.join("class_ids.rs"), constants).expect("Could not write class_ids.rs");
+}
+
+/// Make compiling quickjs.c fail if a class id does not match.
+#[cfg(feature = "bundled")]
+fn assert_class_ids(code_dir: &Path) {
+    use std::io::Write;
+
+    let mut source = std::fs::OpenOptions::new()
+        .append(true)
+        .open(code_dir.join("quickjs.c"))
+        .expect("Could not open quickjs.c");
+    writeln!(source, "\n/* Class ids exported by libquickjs-sys. */").unwrap();
+    for (name, id) in CLASS_IDS {
+        writeln!(
+            source,
+            "typedef char libquickjs_sys_check_{}[({} == {}) ? 1 : -1];",
+            name, name, id
+        )
+        .unwrap();
+    }
+}
+
 #[cfg(all(not(feature = "system"), not(feature = "bundled")))]
 fn main() {
     panic!("Invalid config for crate libquickjs-sys: must enable either the 'bundled' or the 'system' feature");
@@ -49,6 +108,7 @@ fn main() {
     bindings
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
+    write_class_ids(&out_path);
 
     // Instruct cargo to statically link quickjs.
     println!("cargo:rustc-link-search=native={}", lib);
@@ -69,6 +129,7 @@ fn main() {
 
     #[cfg(feature = "patched")]
     apply_patches(&code_dir);
+    assert_class_ids(&code_dir);
 
     eprintln!("Compiling quickjs...");
     let quickjs_version =
@@ -116,6 +177,7 @@ fn main() {
 
     std::fs::copy(embed_path.join("bindings.rs"), out_path.join("bindings.rs"))
         .expect("Could not copy bindings.rs");
+    write_class_ids(&out_path);
 }
 
 #[cfg(feature = "patched")]
@@ -129,7 +191,7 @@ fn apply_patches(code_dir: &PathBuf) {
         let patch = patch.expect("Could not open patch");
         eprintln!("Applying {:?}...", patch.file_name());
         let status = std::process::Command::new("patch")
-            .current_dir(&code_dir)
+            .current_dir(code_dir)
             .arg("-i")
             .arg(fs::canonicalize(patch.path()).expect("Cannot canonicalize patch path"))
             .spawn()
@@ -6,9 +6,14 @@
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
+#![allow(clippy::redundant_static_lifetimes)]
+#![cfg_attr(test, allow(deref_nullptr))]
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+// Class ids of builtin classes, see `CLASS_IDS` in build.rs.
+include!(concat!(env!("OUT_DIR"), "/class_ids.rs"));
+
 #[cfg(test)]
 mod tests {
     use std::ffi::CStr;
@@ -29,7 +34,7 @@ mod tests {
             let value = JS_Eval(
                 ctx,
                 code.as_ptr(),
-                code_str.len() - 1,
+                (code_str.len() - 1) as _,
                 script.as_ptr(),
                 JS_EVAL_TYPE_GLOBAL as i32,
             );
@@ -37,4 +42,34 @@ mod tests {
             assert_eq!(value.u.int32, 2);
         }
     }
+
+    // The class ids also hold for a system library.
+    #[test]
+    fn test_class_ids() {
+        let cases = [
+            ("new ArrayBuffer(1)", JS_CLASS_ARRAY_BUFFER),
+            ("new Uint8ClampedArray(1)", JS_CLASS_UINT8C_ARRAY),
+            ("new Int16Array(1)", JS_CLASS_INT16_ARRAY),
+            ("new BigUint64Array(1)", JS_CLASS_BIG_UINT64_ARRAY),
+            ("new Float64Array(1)", JS_CLASS_FLOAT64_ARRAY),
+            ("new Map()", JS_CLASS_MAP),
+            ("new Set()", JS_CLASS_SET),
+        ];
+        unsafe {
+            let rt = JS_NewRuntime();
+            let ctx = JS_NewContext(rt);
+            let script = CStr::from_bytes_with_nul(b"script\0").unwrap();
+
+            for (code, class_id) in cases.iter() {
+                let value = JS_Eval(
+                    ctx,
+                    format!("{}\0", code).as_ptr() as *const _,
+                    code.len() as _,
+                    script.as_ptr(),
+                    JS_EVAL_TYPE_GLOBAL as i32,
+                );
+                assert!(!JS_GetOpaque(value, *class_id).is_null(), "{}", code);
+            }
+        }
+    }
 }
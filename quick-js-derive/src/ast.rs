@@ -0,0 +1,381 @@
+//! Parsed representation of the derive input and its `#[js(...)]` attributes.
+
+use syn::{
+    ext::IdentExt, spanned::Spanned, Attribute, Data, DeriveInput, Error, ExprPath, Fields,
+    Generics, Ident, Lit, Member, Meta, NestedMeta, Result, Type,
+};
+
+/// A struct or enum deriving `IntoJs` or `FromJs`.
+pub struct Container<'a> {
+    pub ident: &'a Ident,
+    pub generics: &'a Generics,
+    pub body: Body<'a>,
+}
+
+pub enum Body<'a> {
+    Struct(Style, Vec<Field<'a>>),
+    Enum(Tagging, Vec<Variant<'a>>),
+}
+
+/// How the enum variant is represented in Javascript.
+pub enum Tagging {
+    /// `"Variant"` for unit variants, `{ Variant: content }` otherwise.
+    External,
+    /// `{ [tag]: "Variant", ...fields }`.
+    Internal { tag: String },
+    /// `{ [tag]: "Variant", [content]: content }`.
+    Adjacent { tag: String, content: String },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Named fields, converted to an object.
+    Struct,
+    /// Multiple unnamed fields, converted to an array.
+    Tuple,
+    /// A single unnamed field, converted to the field value.
+    Newtype,
+    /// No fields.
+    Unit,
+}
+
+pub struct Variant<'a> {
+    pub ident: &'a Ident,
+    pub key: String,
+    pub style: Style,
+    pub fields: Vec<Field<'a>>,
+}
+
+pub struct Field<'a> {
+    pub member: Member,
+    pub ty: &'a Type,
+    pub key: String,
+    pub default: Option<FieldDefault>,
+    pub skip: bool,
+}
+
+pub enum FieldDefault {
+    /// `#[js(default)]`
+    Trait,
+    /// `#[js(default = "path")]`
+    Path(ExprPath),
+}
+
+impl<'a> Container<'a> {
+    pub fn from_ast(input: &'a DeriveInput) -> Result<Self> {
+        let mut rename_all = None;
+        let mut tag = None;
+        let mut content = None;
+        for meta in js_metas(&input.attrs)? {
+            match &meta {
+                Meta::NameValue(nv) if nv.path.is_ident("rename_all") => {
+                    rename_all = Some(RenameRule::parse(&nv.lit)?);
+                }
+                Meta::NameValue(nv) if nv.path.is_ident("tag") => {
+                    tag = Some(string_value(&nv.lit)?);
+                }
+                Meta::NameValue(nv) if nv.path.is_ident("content") => {
+                    content = Some(string_value(&nv.lit)?);
+                }
+                _ => return Err(Error::new(meta.span(), "unknown container attribute")),
+            }
+        }
+
+        let body = match &input.data {
+            Data::Struct(data) => {
+                if tag.is_some() || content.is_some() {
+                    return Err(Error::new(
+                        input.ident.span(),
+                        "`tag` and `content` are only supported on enums",
+                    ));
+                }
+                let (style, fields) = fields_from_ast(&data.fields, rename_all)?;
+                Body::Struct(style, fields)
+            }
+            Data::Enum(data) => {
+                let tagging = match (tag, content) {
+                    (None, None) => Tagging::External,
+                    (Some(tag), None) => Tagging::Internal { tag },
+                    (Some(tag), Some(content)) => Tagging::Adjacent { tag, content },
+                    (None, Some(_)) => {
+                        return Err(Error::new(
+                            input.ident.span(),
+                            "`content` requires a `tag` attribute",
+                        ))
+                    }
+                };
+                let variants = data
+                    .variants
+                    .iter()
+                    .map(|variant| {
+                        let mut key = None;
+                        let mut fields_rename_all = None;
+                        for meta in js_metas(&variant.attrs)? {
+                            match &meta {
+                                Meta::NameValue(nv) if nv.path.is_ident("rename") => {
+                                    key = Some(string_value(&nv.lit)?);
+                                }
+                                Meta::NameValue(nv) if nv.path.is_ident("rename_all") => {
+                                    fields_rename_all = Some(RenameRule::parse(&nv.lit)?);
+                                }
+                                _ => {
+                                    return Err(Error::new(
+                                        meta.span(),
+                                        "unknown variant attribute",
+                                    ))
+                                }
+                            }
+                        }
+                        let name = variant.ident.unraw().to_string();
+                        let key = key.unwrap_or_else(|| match rename_all {
+                            Some(rule) => rule.apply_to_variant(&name),
+                            None => name,
+                        });
+                        let (style, fields) = fields_from_ast(&variant.fields, fields_rename_all)?;
+                        if let Tagging::Internal { .. } = tagging {
+                            if style == Style::Tuple || style == Style::Newtype {
+                                return Err(Error::new(
+                                    variant.ident.span(),
+                                    "internally tagged enums only support unit and struct variants",
+                                ));
+                            }
+                        }
+                        Ok(Variant {
+                            ident: &variant.ident,
+                            key,
+                            style,
+                            fields,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Body::Enum(tagging, variants)
+            }
+            Data::Union(_) => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "unions can not be converted to Javascript values",
+                ))
+            }
+        };
+
+        Ok(Container {
+            ident: &input.ident,
+            generics: &input.generics,
+            body,
+        })
+    }
+}
+
+fn fields_from_ast<'a>(
+    fields: &'a Fields,
+    rename_all: Option<RenameRule>,
+) -> Result<(Style, Vec<Field<'a>>)> {
+    let style = match fields {
+        Fields::Named(_) => Style::Struct,
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => Style::Newtype,
+        Fields::Unnamed(_) => Style::Tuple,
+        Fields::Unit => Style::Unit,
+    };
+    let fields = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let attrs = FieldAttrs::parse(&field.attrs)?;
+            if style != Style::Struct && !attrs.is_empty() {
+                return Err(Error::new(
+                    field.span(),
+                    "field attributes are only supported on named fields",
+                ));
+            }
+
+            let (member, key) = match &field.ident {
+                Some(ident) => {
+                    let name = ident.unraw().to_string();
+                    let key = attrs.rename.unwrap_or_else(|| match rename_all {
+                        Some(rule) => rule.apply_to_field(&name),
+                        None => name,
+                    });
+                    (Member::Named(ident.clone()), key)
+                }
+                None => (Member::Unnamed(index.into()), index.to_string()),
+            };
+            Ok(Field {
+                member,
+                ty: &field.ty,
+                key,
+                default: attrs.default,
+                skip: attrs.skip,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((style, fields))
+}
+
+/// The `#[js(...)]` attributes of a field.
+#[derive(Default)]
+pub struct FieldAttrs {
+    pub rename: Option<String>,
+    pub default: Option<FieldDefault>,
+    pub skip: bool,
+}
+
+impl FieldAttrs {
+    pub fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut field = FieldAttrs::default();
+        for meta in js_metas(attrs)? {
+            match &meta {
+                Meta::NameValue(nv) if nv.path.is_ident("rename") => {
+                    field.rename = Some(string_value(&nv.lit)?);
+                }
+                Meta::Path(path) if path.is_ident("default") => {
+                    field.default = Some(FieldDefault::Trait);
+                }
+                Meta::NameValue(nv) if nv.path.is_ident("default") => {
+                    let path = syn::parse_str(&string_value(&nv.lit)?)
+                        .map_err(|e| Error::new(nv.lit.span(), e))?;
+                    field.default = Some(FieldDefault::Path(path));
+                }
+                Meta::Path(path) if path.is_ident("skip") => {
+                    field.skip = true;
+                }
+                _ => return Err(Error::new(meta.span(), "unknown field attribute")),
+            }
+        }
+        Ok(field)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rename.is_none() && self.default.is_none() && !self.skip
+    }
+}
+
+/// Collect the nested items of all `#[js(...)]` attributes.
+fn js_metas(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut metas = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("js")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => metas.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(Error::new(lit.span(), "unexpected literal"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(Error::new(meta.span(), "expected #[js(...)]")),
+        }
+    }
+    Ok(metas)
+}
+
+fn string_value(lit: &Lit) -> Result<String> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        _ => Err(Error::new(lit.span(), "expected a string literal")),
+    }
+}
+
+/// Returns true if the type is written as `Option<T>`.
+pub fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => match path.path.segments.last() {
+            Some(segment) => segment.ident == "Option",
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// A `rename_all` case convention, with the same names as serde.
+#[derive(Clone, Copy)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(lit: &Lit) -> Result<Self> {
+        let rule = match string_value(lit)?.as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            _ => return Err(Error::new(lit.span(), "unknown rename rule")),
+        };
+        Ok(rule)
+    }
+
+    /// Rename a `PascalCase` variant name.
+    fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::Pascal => variant.to_owned(),
+            RenameRule::Lower => variant.to_ascii_lowercase(),
+            RenameRule::Upper => variant.to_ascii_uppercase(),
+            RenameRule::Camel => variant[..1].to_ascii_lowercase() + &variant[1..],
+            RenameRule::Snake => {
+                let mut snake = String::new();
+                for (i, ch) in variant.char_indices() {
+                    if i > 0 && ch.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(ch.to_ascii_lowercase());
+                }
+                snake
+            }
+            RenameRule::ScreamingSnake => RenameRule::Snake
+                .apply_to_variant(variant)
+                .to_ascii_uppercase(),
+            RenameRule::Kebab => RenameRule::Snake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+            RenameRule::ScreamingKebab => RenameRule::ScreamingSnake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+        }
+    }
+
+    /// Rename a `snake_case` field name.
+    fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_owned(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply_to_field(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => pascal,
+                }
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Expansion of `#[derive(FromJs)]`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Result};
+
+use crate::ast::{is_option, Body, Container, Field, FieldDefault, Style, Tagging, Variant};
+use crate::typescript::{self, Direction};
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let container = Container::from_ast(input)?;
+    let ident = container.ident;
+    let generics = crate::with_bounds(container.generics, |param| {
+        quote! {
+            #param: ::std::convert::TryFrom<::quick_js::JsValue>,
+            <#param as ::std::convert::TryFrom<::quick_js::JsValue>>::Error:
+                ::std::convert::Into<::quick_js::ValueError>
+        }
+    });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let ts_type = typescript::expand(&container, Direction::FromJs);
+    let arg_generics = crate::with_bounds(
+        container.generics,
+        |param| quote!(#param: ::quick_js::FromJsArg),
+    );
+    let (arg_impl_generics, _, arg_where_clause) = arg_generics.split_for_impl();
+
+    let body = match &container.body {
+        Body::Struct(Style::Unit, _) => quote! {
+            ::quick_js::derive::unit(value)?;
+            ::std::result::Result::Ok(#ident)
+        },
+        Body::Struct(style, fields) => {
+            let value = construct(quote!(#ident), *style, fields, quote!(value));
+            quote!(::std::result::Result::Ok(#value))
+        }
+        Body::Enum(tagging, variants) => enum_body(ident, tagging, variants),
+    };
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics ::std::convert::TryFrom<::quick_js::JsValue> for #ident #ty_generics
+        #where_clause
+        {
+            type Error = ::quick_js::ValueError;
+
+            fn try_from(value: ::quick_js::JsValue) -> ::std::result::Result<Self, Self::Error> {
+                #body
+            }
+        }
+
+        #[automatically_derived]
+        impl #arg_impl_generics ::quick_js::FromJsArg for #ident #ty_generics
+        #arg_where_clause
+        {
+            #ts_type
+        }
+    })
+}
+
+fn enum_body(ident: &syn::Ident, tagging: &Tagging, variants: &[Variant]) -> TokenStream {
+    let has_content = variants.iter().any(|v| v.style != Style::Unit);
+    let arms = variants.iter().map(|variant| {
+        let variant_ident = variant.ident;
+        let path = quote!(#ident::#variant_ident);
+        let key = &variant.key;
+        let value = match (tagging, variant.style) {
+            (_, Style::Unit) => path,
+            (Tagging::Internal { .. }, style) => named_fields(path, style, &variant.fields),
+            (_, style) => {
+                let value = construct(path, style, &variant.fields, quote!(content));
+                quote! {{
+                    let content = ::quick_js::derive::content(content, #key)?;
+                    #value
+                }}
+            }
+        };
+        quote!(#key => ::std::result::Result::Ok(#value),)
+    });
+    let arms = quote! {
+        #( #arms )*
+        variant => ::std::result::Result::Err(::quick_js::derive::unknown_variant(variant)),
+    };
+
+    match tagging {
+        Tagging::External if has_content => quote! {
+            let (variant, content) = ::quick_js::derive::external_variant(value)?;
+            match variant.as_str() { #arms }
+        },
+        Tagging::External => quote! {
+            let (variant, _) = ::quick_js::derive::external_variant(value)?;
+            match variant.as_str() { #arms }
+        },
+        Tagging::Internal { tag } => quote! {
+            let mut object = ::quick_js::derive::object(value)?;
+            let variant = ::quick_js::derive::tag(&mut object, #tag)?;
+            match variant.as_str() { #arms }
+        },
+        Tagging::Adjacent { tag, content } => {
+            let content = if has_content {
+                quote!(let content = object.remove(#content);)
+            } else {
+                quote!()
+            };
+            quote! {
+                let mut object = ::quick_js::derive::object(value)?;
+                let variant = ::quick_js::derive::tag(&mut object, #tag)?;
+                #content
+                match variant.as_str() { #arms }
+            }
+        }
+    }
+}
+
+/// Build a struct or variant from the Javascript value in `source`.
+fn construct(
+    path: TokenStream,
+    style: Style,
+    fields: &[Field],
+    source: TokenStream,
+) -> TokenStream {
+    match style {
+        Style::Struct => {
+            let value = named_fields(path, style, fields);
+            quote! {{
+                #[allow(unused_mut, unused_variables)]
+                let mut object = ::quick_js::derive::object(#source)?;
+                #value
+            }}
+        }
+        Style::Tuple => {
+            let len = fields.len();
+            let elements =
+                (0..len).map(|index| quote!(::quick_js::derive::element(&mut values, #index)?));
+            quote! {{
+                let mut values = ::quick_js::derive::array(#source, #len)?;
+                #path( #( #elements ),* )
+            }}
+        }
+        Style::Newtype => quote!(#path(::quick_js::derive::convert(#source)?)),
+        Style::Unit => path,
+    }
+}
+
+/// Build a struct or variant with named fields from the local `object`.
+fn named_fields(path: TokenStream, style: Style, fields: &[Field]) -> TokenStream {
+    if style == Style::Unit {
+        return path;
+    }
+    let fields = fields.iter().map(|field| {
+        let member = &field.member;
+        let key = &field.key;
+        let default = match &field.default {
+            Some(FieldDefault::Path(path)) => quote!(#path),
+            _ => quote!(::std::default::Default::default),
+        };
+        let value = if field.skip {
+            quote!(#default())
+        } else if is_option(field.ty) {
+            match &field.default {
+                Some(FieldDefault::Path(_)) => quote! {
+                    ::quick_js::derive::optional_field(&mut object, #key)?
+                        .map_or_else(#default, ::std::option::Option::Some)
+                },
+                _ => quote!(::quick_js::derive::optional_field(&mut object, #key)?),
+            }
+        } else if field.default.is_some() {
+            quote!(::quick_js::derive::default_field(&mut object, #key, #default)?)
+        } else {
+            quote!(::quick_js::derive::field(&mut object, #key)?)
+        };
+        quote!(#member: #value)
+    });
+    quote!(#path { #( #fields, )* })
+}
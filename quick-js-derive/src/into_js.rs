@@ -0,0 +1,170 @@
+//! Expansion of `#[derive(IntoJs)]`.
+
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+use syn::{DeriveInput, Result};
+
+use crate::ast::{Body, Container, Field, Style, Tagging};
+use crate::typescript::{self, Direction};
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let container = Container::from_ast(input)?;
+    let ident = container.ident;
+    let generics = crate::with_bounds(
+        container.generics,
+        |param| quote!(::quick_js::JsValue: ::std::convert::From<#param>),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let ts_type = typescript::expand(&container, Direction::IntoJs);
+    let return_generics = crate::with_bounds(container.generics, |param| {
+        quote! {
+            #param: ::quick_js::IntoJsReturn,
+            ::quick_js::JsValue: ::std::convert::From<#param>
+        }
+    });
+    let (return_impl_generics, _, return_where_clause) = return_generics.split_for_impl();
+
+    let body = match &container.body {
+        Body::Struct(style, fields) => {
+            let pattern = pattern(quote!(#ident), *style, fields);
+            let value = content(*style, fields);
+            quote! {
+                let #pattern = value;
+                #value
+            }
+        }
+        Body::Enum(tagging, variants) => {
+            let arms = variants.iter().map(|variant| {
+                let variant_ident = variant.ident;
+                let pattern = pattern(
+                    quote!(#ident::#variant_ident),
+                    variant.style,
+                    &variant.fields,
+                );
+                let key = &variant.key;
+                let value = match (tagging, variant.style) {
+                    (Tagging::External, Style::Unit) => variant_name(key),
+                    (Tagging::External, style) => {
+                        object(vec![(quote!(#key), content(style, &variant.fields))])
+                    }
+                    (Tagging::Internal { tag }, _)
+                    | (Tagging::Adjacent { tag, .. }, Style::Unit) => {
+                        let tag_entry = (quote!(#tag), variant_name(key));
+                        object(std::iter::once(tag_entry).chain(entries(&variant.fields)))
+                    }
+                    (
+                        Tagging::Adjacent {
+                            tag,
+                            content: content_key,
+                        },
+                        style,
+                    ) => object(vec![
+                        (quote!(#tag), variant_name(key)),
+                        (quote!(#content_key), content(style, &variant.fields)),
+                    ]),
+                };
+                quote!(#pattern => #value,)
+            });
+            quote! {
+                match value {
+                    #( #arms )*
+                }
+            }
+        }
+    };
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics ::std::convert::From<#ident #ty_generics> for ::quick_js::JsValue
+        #where_clause
+        {
+            fn from(value: #ident #ty_generics) -> Self {
+                #body
+            }
+        }
+
+        #[automatically_derived]
+        impl #return_impl_generics ::quick_js::IntoJsReturn for #ident #ty_generics
+        #return_where_clause
+        {
+            #ts_type
+        }
+    })
+}
+
+// Not `JsValue::from`, which would be ambiguous with the `JsValue: From<T>`
+// bounds on generic enums.
+fn variant_name(key: &str) -> TokenStream {
+    quote!(::quick_js::JsValue::String(::std::string::String::from(#key)))
+}
+
+/// The local variable a field is bound to when destructuring.
+fn binding(index: usize) -> Ident {
+    Ident::new(&format!("__field{}", index), Span::call_site())
+}
+
+/// A pattern that binds all fields that are not skipped.
+fn pattern(path: TokenStream, style: Style, fields: &[Field]) -> TokenStream {
+    match style {
+        Style::Struct => {
+            let members = fields
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| !f.skip)
+                .map(|(i, f)| {
+                    let member = &f.member;
+                    let binding = binding(i);
+                    quote!(#member: #binding)
+                });
+            quote!(#path { #( #members, )* .. })
+        }
+        Style::Tuple | Style::Newtype => {
+            let bindings = (0..fields.len()).map(binding);
+            quote!(#path( #( #bindings ),* ))
+        }
+        Style::Unit => path,
+    }
+}
+
+/// The Javascript value for the bound fields.
+fn content(style: Style, fields: &[Field]) -> TokenStream {
+    match style {
+        Style::Struct => object(entries(fields)),
+        Style::Tuple => {
+            let bindings = (0..fields.len()).map(binding);
+            quote! {
+                ::quick_js::JsValue::Array(vec![
+                    #( ::quick_js::JsValue::from(#bindings) ),*
+                ])
+            }
+        }
+        Style::Newtype => {
+            let binding = binding(0);
+            quote!(::quick_js::JsValue::from(#binding))
+        }
+        Style::Unit => quote!(::quick_js::JsValue::Null),
+    }
+}
+
+/// The object properties for named fields that are not skipped.
+fn entries<'a>(fields: &'a [Field]) -> impl Iterator<Item = (TokenStream, TokenStream)> + 'a {
+    fields
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !f.skip)
+        .map(|(i, f)| {
+            let key = &f.key;
+            let binding = binding(i);
+            (quote!(#key), quote!(::quick_js::JsValue::from(#binding)))
+        })
+}
+
+fn object(entries: impl IntoIterator<Item = (TokenStream, TokenStream)>) -> TokenStream {
+    let (keys, values): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+    quote! {{
+        #[allow(unused_mut)]
+        let mut object = ::quick_js::JsObjectMap::new();
+        #( object.insert(::std::string::String::from(#keys), #values); )*
+        ::quick_js::JsValue::Object(object)
+    }}
+}
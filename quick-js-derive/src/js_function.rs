@@ -0,0 +1,228 @@
+//! Expansion of `#[js_function]`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    spanned::Spanned, AttributeArgs, Error, FnArg, ItemFn, Lit, Meta, NestedMeta, Pat, Result, Type,
+};
+
+use crate::ast::{is_option, FieldAttrs};
+
+pub fn expand(args: AttributeArgs, mut function: ItemFn) -> Result<TokenStream> {
+    let mut name = None;
+    let mut options = false;
+    for arg in args {
+        match &arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match &nv.lit {
+                Lit::Str(s) => name = Some(s.value()),
+                lit => return Err(Error::new(lit.span(), "expected a string literal")),
+            },
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("options") => options = true,
+            _ => return Err(Error::new(arg.span(), "unknown js_function argument")),
+        }
+    }
+
+    let sig = &function.sig;
+    if let Some(FnArg::Receiver(receiver)) = sig.inputs.first() {
+        return Err(Error::new(
+            receiver.span(),
+            "js_function does not support methods",
+        ));
+    }
+    if let Some(param) = sig.generics.params.first() {
+        return Err(Error::new(
+            param.span(),
+            "js_function does not support generic functions",
+        ));
+    }
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(Error::new(
+            asyncness.span(),
+            "js_function does not support async functions",
+        ));
+    }
+
+    let ident = sig.ident.clone();
+    let vis = function.vis.clone();
+    let name = name.unwrap_or_else(|| syn::ext::IdentExt::unraw(&ident).to_string());
+    let doc = doc_comment(&function.attrs);
+
+    let (callback, signature) = if options {
+        options_callback(&mut function)?
+    } else {
+        if let Some(attr) = function.sig.inputs.iter().flat_map(fn_arg_attrs).next() {
+            return Err(Error::new(
+                attr.span(),
+                "parameter attributes require #[js_function(options)]",
+            ));
+        }
+        let names = function
+            .sig
+            .inputs
+            .iter()
+            .filter(|input| !is_call_context(input))
+            .map(|input| match input {
+                FnArg::Typed(input) => match &*input.pat {
+                    Pat::Ident(pat) => syn::ext::IdentExt::unraw(&pat.ident).to_string(),
+                    _ => String::new(),
+                },
+                FnArg::Receiver(_) => unreachable!(),
+            });
+        let signature = quote! {
+            ::quick_js::derive::describe(signature, Self::DOC, &[ #( #names ),* ])
+        };
+        (quote!(#ident), signature)
+    };
+
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, dead_code)]
+        #vis struct #ident {}
+
+        #[automatically_derived]
+        impl ::quick_js::JsFunctionDef for #ident {
+            const NAME: &'static str = #name;
+            const DOC: &'static str = #doc;
+
+            fn register(
+                context: &::quick_js::Context,
+            ) -> ::std::result::Result<(), ::quick_js::ExecutionError> {
+                let callback = #callback;
+                let signature = ::quick_js::Callback::signature(&callback);
+                context.add_callback(Self::NAME, callback)?;
+                context.declare_function(Self::NAME, #signature);
+                ::std::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+/// Build a closure that reads the parameters from a single options object,
+/// and its Typescript signature.
+///
+/// The parameters become the fields of a struct deriving `FromJs`, so they
+/// accept the same `#[js(...)]` field attributes.
+fn options_callback(function: &mut ItemFn) -> Result<(TokenStream, TokenStream)> {
+    let ident = &function.sig.ident;
+    let mut call_context = None;
+    let mut fields = Vec::new();
+    let mut names = Vec::new();
+    let mut properties = Vec::new();
+    for (index, input) in function.sig.inputs.iter_mut().enumerate() {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(_) => unreachable!(),
+        };
+        // A leading reference is the `&CallContext`, which is passed through.
+        if index == 0 {
+            if let Type::Reference(ty) = &*input.ty {
+                call_context = Some(ty.clone());
+                continue;
+            }
+        }
+        let name = match &*input.pat {
+            Pat::Ident(pat) => pat.ident.clone(),
+            pat => {
+                return Err(Error::new(
+                    pat.span(),
+                    "options parameters must be plain identifiers",
+                ))
+            }
+        };
+        let (attrs, rest) = input
+            .attrs
+            .drain(..)
+            .partition::<Vec<_>, _>(|attr| attr.path.is_ident("js"));
+        input.attrs = rest;
+        let ty = &input.ty;
+        let field = FieldAttrs::parse(&attrs)?;
+        // Skipped fields are not read, so they don't make the object required.
+        let optional = is_option(ty) || field.default.is_some() || field.skip;
+        if !field.skip {
+            let key = field
+                .rename
+                .unwrap_or_else(|| syn::ext::IdentExt::unraw(&name).to_string());
+            properties.push(
+                quote!((#key, <#ty as ::quick_js::derive::FromJsField>::ts_type(), #optional)),
+            );
+        }
+        fields.push((quote!(#( #attrs )* #name: #ty), optional));
+        names.push(name);
+    }
+
+    let all_optional = fields.iter().all(|(_, optional)| *optional);
+    let fields = fields.iter().map(|(field, _)| field);
+    let (derive, param, unwrap) = if all_optional {
+        (
+            quote!(#[derive(::quick_js::FromJs)]),
+            quote!(::std::option::Option<Options>),
+            quote!(let options = options.unwrap_or_else(::quick_js::derive::omitted_options);),
+        )
+    } else {
+        (
+            quote!(#[derive(::quick_js::FromJs)]),
+            quote!(Options),
+            quote!(),
+        )
+    };
+    let (context_param, context_arg) = match call_context {
+        Some(ty) => (quote!(context: #ty,), quote!(context,)),
+        None => (quote!(), quote!()),
+    };
+
+    let callback = quote! {{
+        #derive
+        struct Options {
+            #( #fields, )*
+        }
+
+        move |#context_param options: #param| {
+            #unwrap
+            #ident(#context_arg #( options.#names ),*)
+        }
+    }};
+    let signature = quote! {
+        ::quick_js::derive::describe_options(
+            signature,
+            Self::DOC,
+            &[ #( #properties ),* ],
+            #all_optional,
+        )
+    };
+    Ok((callback, signature))
+}
+
+/// Whether the parameter is a leading `&CallContext`.
+fn is_call_context(input: &FnArg) -> bool {
+    match input {
+        FnArg::Typed(input) => matches!(&*input.ty, Type::Reference(_)),
+        FnArg::Receiver(_) => false,
+    }
+}
+
+fn fn_arg_attrs(input: &FnArg) -> impl Iterator<Item = &syn::Attribute> {
+    let attrs = match input {
+        FnArg::Typed(input) => &input.attrs[..],
+        FnArg::Receiver(receiver) => &receiver.attrs[..],
+    };
+    attrs.iter().filter(|attr| attr.path.is_ident("js"))
+}
+
+/// Join the `///` doc comment lines of an item.
+fn doc_comment(attrs: &[syn::Attribute]) -> String {
+    let lines = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(s) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').map(str::to_owned).unwrap_or(line))
+        .collect::<Vec<_>>();
+    lines.join("\n")
+}
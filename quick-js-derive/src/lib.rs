@@ -0,0 +1,182 @@
+//! Derive macros for converting Rust types to and from `quick_js::JsValue`,
+//! and the `#[js_function]` attribute for exposing Rust functions.
+//!
+//! The macros are re-exported by `quick-js` when its `derive` feature is enabled,
+//! and should be used through that crate.
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+
+mod ast;
+mod from_js;
+mod into_js;
+mod js_function;
+mod typescript;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, parse_quote, AttributeArgs, DeriveInput, Generics, ItemFn};
+
+/// Derive `From<T> for JsValue`.
+///
+/// Structs with named fields become objects, tuple structs become arrays,
+/// newtype structs become their inner value and unit structs become `null`.
+///
+/// Enums are externally tagged by default: unit variants become the variant
+/// name, other variants become an object with the variant name as the only key.
+/// `#[js(tag = "type")]` stores the variant name in the `type` property of
+/// struct variants instead, and `#[js(tag = "t", content = "c")]` stores the
+/// variant content in a separate `c` property.
+///
+/// Supported attributes:
+///
+/// * `#[js(rename_all = "camelCase")]` on a struct or enum renames all fields
+///   or variants. The rules have the same names as in serde.
+/// * `#[js(rename = "name")]` on a field or variant uses a different name.
+/// * `#[js(rename_all = "...")]` on a variant renames its fields.
+/// * `#[js(skip)]` on a field leaves it out.
+///
+/// Also implements `IntoJsReturn`, so the type can be returned from
+/// callbacks, with the Typescript type of the value.
+///
+/// ```rust
+/// use quick_js::{Context, IntoJs};
+///
+/// #[derive(IntoJs)]
+/// #[js(rename_all = "camelCase")]
+/// struct User {
+///     user_name: String,
+///     age: i32,
+/// }
+///
+/// let context = Context::new().unwrap();
+/// context
+///     .add_callback("getUser", || User { user_name: "ada".into(), age: 36 })
+///     .unwrap();
+/// let value = context
+///     .eval_as::<String>("const user = getUser(); `${user.userName}: ${user.age}`")
+///     .unwrap();
+/// assert_eq!(value, "ada: 36");
+/// ```
+#[proc_macro_derive(IntoJs, attributes(js))]
+pub fn derive_into_js(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_js::expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Derive `TryFrom<JsValue> for T`.
+///
+/// Uses the same representation and attributes as [`IntoJs`](derive.IntoJs.html).
+/// Conversion fails with a `ValueError` that names the field or variant
+/// that could not be converted.
+///
+/// Fields of type `Option<T>` are optional: a missing, `null` or `undefined`
+/// property becomes `None`. Additional field attributes:
+///
+/// * `#[js(default)]` uses `Default::default()` if the property is missing
+///   or `undefined`.
+/// * `#[js(default = "path")]` calls the function at `path` instead.
+/// * `#[js(skip)]` fields are always set to their default.
+///
+/// Also implements `FromJsArg`, so the type can be taken as a callback
+/// argument, with the Typescript type of the accepted values, where optional
+/// fields and fields with defaults are optional.
+///
+/// ```rust
+/// use quick_js::{Context, FromJs};
+///
+/// #[derive(FromJs, Debug, PartialEq)]
+/// #[js(tag = "kind", rename_all = "lowercase")]
+/// enum Shape {
+///     Circle { radius: i32 },
+///     Rect {
+///         width: i32,
+///         #[js(default)]
+///         height: i32,
+///     },
+/// }
+///
+/// let context = Context::new().unwrap();
+/// let shape: Shape = context.eval_as("({ kind: 'rect', width: 2 })").unwrap();
+/// assert_eq!(shape, Shape::Rect { width: 2, height: 0 });
+/// ```
+#[proc_macro_derive(FromJs, attributes(js))]
+pub fn derive_from_js(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_js::expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Expose a Rust function to Javascript.
+///
+/// Implements `JsFunctionDef` for a hidden type with the same name as the
+/// function, so it can be registered with `register_functions!`. Arguments
+/// are converted like the arguments of `Context::add_callback`. The doc
+/// comment of the function is available as `JsFunctionDef::DOC`.
+///
+/// * `#[js_function(name = "jsName")]` uses a different Javascript name.
+/// * `#[js_function(options)]` reads the parameters from the properties of
+///   a single options object instead of from positional arguments.
+///   Parameters support the field attributes of [`FromJs`](derive.FromJs.html),
+///   and `Option<T>` and `#[js(default)]` parameters may be omitted. If all
+///   parameters are optional, the options object may be omitted too. A leading
+///   `&CallContext` parameter is passed through.
+///
+/// ```rust
+/// use quick_js::{js_function, register_functions, Context};
+///
+/// /// Add two numbers.
+/// #[js_function]
+/// fn add(a: i32, b: i32) -> i32 {
+///     a + b
+/// }
+///
+/// #[js_function(name = "greet", options)]
+/// fn greeting(name: String, #[js(default)] excited: bool, suffix: Option<String>) -> String {
+///     let greeting = format!("Hello {}{}", name, suffix.unwrap_or_default());
+///     if excited {
+///         greeting + "!"
+///     } else {
+///         greeting
+///     }
+/// }
+///
+/// let context = Context::new().unwrap();
+/// register_functions!(context, [add, greeting]).unwrap();
+///
+/// assert_eq!(context.eval_as::<i32>("add(1, 2)").unwrap(), 3);
+/// let value = context
+///     .eval_as::<String>("greet({ name: 'Ada', excited: true })")
+///     .unwrap();
+/// assert_eq!(value, "Hello Ada!");
+/// ```
+#[proc_macro_attribute]
+pub fn js_function(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let function = parse_macro_input!(input as ItemFn);
+    js_function::expand(args, function)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Add the where clause predicates returned by `bound` for every type parameter.
+fn with_bounds(
+    generics: &Generics,
+    bound: impl Fn(&syn::Ident) -> proc_macro2::TokenStream,
+) -> Generics {
+    let mut generics = generics.clone();
+    let params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        let predicates = bound(&param);
+        let clause: syn::WhereClause = parse_quote!(where #predicates);
+        where_clause.predicates.extend(clause.predicates);
+    }
+    generics
+}
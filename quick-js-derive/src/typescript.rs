@@ -0,0 +1,113 @@
+//! Expansion of the `ts_type` methods of the `FromJsArg` and `IntoJsReturn`
+//! implementations generated by `#[derive(FromJs)]` and `#[derive(IntoJs)]`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::ast::{is_option, Body, Container, Field, Style, Tagging};
+
+/// The direction of a conversion, which decides how field types are looked
+/// up and whether fields with defaults are optional.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    FromJs,
+    IntoJs,
+}
+
+impl Direction {
+    /// The trait that provides the Typescript type of a field.
+    fn field_trait_path(self) -> TokenStream {
+        match self {
+            // Also implemented for `JsValue` fields, which are not callback
+            // arguments.
+            Direction::FromJs => quote!(::quick_js::derive::FromJsField),
+            Direction::IntoJs => quote!(::quick_js::IntoJsReturn),
+        }
+    }
+}
+
+/// The `ts_type` method of the container's `FromJsArg` or `IntoJsReturn`
+/// implementation.
+pub fn expand(container: &Container, direction: Direction) -> TokenStream {
+    let ty = match &container.body {
+        Body::Struct(style, fields) => content(direction, *style, fields),
+        Body::Enum(tagging, variants) => {
+            let variants = variants.iter().map(|variant| {
+                let name = string_literal(&variant.key);
+                match (tagging, variant.style) {
+                    (Tagging::External, Style::Unit) => quote!(#name.to_string()),
+                    (Tagging::External, style) => {
+                        let key = &variant.key;
+                        let content = content(direction, style, &variant.fields);
+                        object(vec![quote!((#key, #content, false))])
+                    }
+                    (Tagging::Internal { tag }, _)
+                    | (Tagging::Adjacent { tag, .. }, Style::Unit) => {
+                        let tag = quote!((#tag, #name.to_string(), false));
+                        object(std::iter::once(tag).chain(properties(direction, &variant.fields)))
+                    }
+                    (Tagging::Adjacent { tag, content: key }, style) => {
+                        let content = content(direction, style, &variant.fields);
+                        object(vec![
+                            quote!((#tag, #name.to_string(), false)),
+                            quote!((#key, #content, false)),
+                        ])
+                    }
+                }
+            });
+            quote!(::quick_js::derive::union_type(&[ #( #variants ),* ]))
+        }
+    };
+
+    quote! {
+        fn ts_type() -> ::std::string::String {
+            #ty
+        }
+    }
+}
+
+/// A Typescript string literal type.
+fn string_literal(value: &str) -> String {
+    format!("{:?}", value)
+}
+
+/// The type of a struct or variant content.
+fn content(direction: Direction, style: Style, fields: &[Field]) -> TokenStream {
+    match style {
+        Style::Struct => object(properties(direction, fields)),
+        Style::Tuple => {
+            let elements = fields.iter().map(|field| field_type(direction, field));
+            quote!(::quick_js::derive::tuple_type(&[ #( #elements ),* ]))
+        }
+        Style::Newtype => field_type(direction, &fields[0]),
+        Style::Unit => quote!(::std::string::String::from("null")),
+    }
+}
+
+/// The properties of named fields that are not skipped. Fields that may be
+/// missing when converting from Javascript are optional.
+fn properties<'a>(
+    direction: Direction,
+    fields: &'a [Field],
+) -> impl Iterator<Item = TokenStream> + 'a {
+    fields.iter().filter(|f| !f.skip).map(move |field| {
+        let key = &field.key;
+        let ty = field_type(direction, field);
+        let optional = match direction {
+            Direction::FromJs => is_option(field.ty) || field.default.is_some(),
+            Direction::IntoJs => false,
+        };
+        quote!((#key, #ty, #optional))
+    })
+}
+
+fn field_type(direction: Direction, field: &Field) -> TokenStream {
+    let ty = field.ty;
+    let trait_path = direction.field_trait_path();
+    quote!(<#ty as #trait_path>::ts_type())
+}
+
+fn object(properties: impl IntoIterator<Item = TokenStream>) -> TokenStream {
+    let properties = properties.into_iter();
+    quote!(::quick_js::derive::object_type(&[ #( #properties ),* ]))
+}
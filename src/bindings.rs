@@ -1,7 +1,10 @@
 use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
     collections::HashMap,
     ffi::CString,
     os::raw::{c_char, c_int, c_void},
+    rc::{Rc, Weak},
     sync::Mutex,
 };
 
@@ -10,29 +13,35 @@ use libquickjs_sys as q;
 #[cfg(feature = "bigint")]
 use crate::value::{bigint::BigIntOrI64, BigInt};
 use crate::{
-    callback::{Arguments, Callback},
+    callback::{Arguments, CallContext, Callback, CallbackMut},
+    class::{self, ClassBuilder, JsClass},
     console::ConsoleBackend,
     droppable_value::DroppableValue,
-    ContextError, ExecutionError, JsValue, ValueError,
+    exception::{ExceptionHandle, JsException, ReleasedExceptions},
+    host::{self, HostObject},
+    typescript::Declarations,
+    ContextError, ExecutionError, JsDate, JsObjectMap, JsString, JsSymbol, JsThrow, JsValue,
+    SymbolKeys, TypedArray, ValueError,
 };
 
 // JS_TAG_* constants from quickjs.
 // For some reason bindgen does not pick them up.
-#[cfg(feature = "bigint")]
-const TAG_BIG_INT: i64 = -10;
-const TAG_STRING: i64 = -7;
-const TAG_OBJECT: i64 = -1;
-const TAG_INT: i64 = 0;
-const TAG_BOOL: i64 = 1;
-const TAG_NULL: i64 = 2;
-const TAG_UNDEFINED: i64 = 3;
-const TAG_EXCEPTION: i64 = 6;
-const TAG_FLOAT64: i64 = 7;
+#[cfg(any(feature = "bigint", feature = "serde"))]
+pub(crate) const TAG_BIG_INT: i64 = -10;
+pub(crate) const TAG_SYMBOL: i64 = -8;
+pub(crate) const TAG_STRING: i64 = -7;
+pub(crate) const TAG_OBJECT: i64 = -1;
+pub(crate) const TAG_INT: i64 = 0;
+pub(crate) const TAG_BOOL: i64 = 1;
+pub(crate) const TAG_NULL: i64 = 2;
+pub(crate) const TAG_UNDEFINED: i64 = 3;
+pub(crate) const TAG_EXCEPTION: i64 = 6;
+pub(crate) const TAG_FLOAT64: i64 = 7;
 
 /// Free a JSValue.
 /// This function is the equivalent of JS_FreeValue from quickjs, which can not
 /// be used due to being `static inline`.
-unsafe fn free_value(context: *mut q::JSContext, value: q::JSValue) {
+pub(crate) unsafe fn free_value(context: *mut q::JSContext, value: q::JSValue) {
     // All tags < 0 are garbage collected and need to be freed.
     if value.tag < 0 {
         // This transmute is OK since if tag < 0, the union will be a refcount
@@ -46,23 +55,16 @@ unsafe fn free_value(context: *mut q::JSContext, value: q::JSValue) {
     }
 }
 
-#[cfg(feature = "chrono")]
-fn js_date_constructor(context: *mut q::JSContext) -> q::JSValue {
-    let global = unsafe { q::JS_GetGlobalObject(context) };
-    assert_eq!(global.tag, TAG_OBJECT);
-
-    let date_constructor = unsafe {
-        q::JS_GetPropertyStr(
-            context,
-            global,
-            std::ffi::CStr::from_bytes_with_nul(b"Date\0")
-                .unwrap()
-                .as_ptr(),
-        )
-    };
-    assert_eq!(date_constructor.tag, TAG_OBJECT);
-    unsafe { free_value(context, global) };
-    date_constructor
+/// Increment the reference count of a JSValue.
+/// This function is the equivalent of JS_DupValue from quickjs, which can not
+/// be used due to being `static inline`.
+pub(crate) unsafe fn dup_value(value: q::JSValue) -> q::JSValue {
+    if value.tag < 0 {
+        let ptr = value.u.ptr as *mut q::JSRefCountHeader;
+        let pref: &mut q::JSRefCountHeader = &mut *ptr;
+        pref.ref_count += 1;
+    }
+    value
 }
 
 #[cfg(feature = "bigint")]
@@ -84,40 +86,30 @@ fn js_create_bigint_function(context: *mut q::JSContext) -> q::JSValue {
     bigint_function
 }
 
-/// Serialize a Rust value into a quickjs runtime value.
-fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSValue, ValueError> {
-    let v = match value {
-        JsValue::Null => q::JSValue {
-            u: q::JSValueUnion { int32: 0 },
-            tag: TAG_NULL,
-        },
-        JsValue::Bool(flag) => q::JSValue {
-            u: q::JSValueUnion {
-                int32: if flag { 1 } else { 0 },
-            },
-            tag: TAG_BOOL,
-        },
-        JsValue::Int(val) => q::JSValue {
-            u: q::JSValueUnion { int32: val },
-            tag: TAG_INT,
-        },
-        JsValue::Float(val) => q::JSValue {
-            u: q::JSValueUnion { float64: val },
-            tag: TAG_FLOAT64,
-        },
-        JsValue::String(val) => {
-            let qval = unsafe {
-                q::JS_NewStringLen(context, val.as_ptr() as *const c_char, val.len() as _)
-            };
+#[cfg(test)]
+thread_local! {
+    /// The number of values converted with `serialize_value` and
+    /// `deserialize_value`, which tests use to check that callbacks convert
+    /// primitives directly.
+    pub(crate) static VALUE_CONVERSIONS: Cell<usize> = const { Cell::new(0) };
+}
 
-            if qval.tag == TAG_EXCEPTION {
-                return Err(ValueError::Internal(
-                    "Could not create string in runtime".into(),
-                ));
-            }
+/// Serialize a Rust value into a quickjs runtime value.
+pub(crate) fn serialize_value(
+    context: *mut q::JSContext,
+    value: JsValue,
+) -> Result<q::JSValue, ValueError> {
+    #[cfg(test)]
+    VALUE_CONVERSIONS.with(|count| count.set(count.get() + 1));
 
-            qval
-        }
+    let v = match value {
+        JsValue::Null => js_null_value(),
+        JsValue::Undefined => js_undefined_value(),
+        JsValue::Bool(flag) => js_bool_value(flag),
+        JsValue::Int(val) => js_int_value(val),
+        JsValue::Float(val) => js_float_value(val),
+        JsValue::String(val) => new_string(context, val.as_bytes())?,
+        JsValue::IllFormedString(val) => new_string(context, val.as_wtf8())?,
         JsValue::Array(values) => {
             // Allocate a new array in the runtime.
             let arr = unsafe { q::JS_NewArray(context) };
@@ -169,21 +161,28 @@ fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSVa
             }
 
             for (key, value) in map {
-                let ckey = make_cstring(key)?;
+                let atom = match Atom::new(context, &key) {
+                    Ok(atom) => atom,
+                    Err(e) => {
+                        unsafe { free_value(context, obj) };
+                        return Err(e);
+                    }
+                };
 
-                let qvalue = serialize_value(context, value).map_err(|e| {
-                    // Free the object if a property failed.
-                    unsafe {
-                        free_value(context, obj);
+                let qvalue = match serialize_value(context, value) {
+                    Ok(qvalue) => qvalue,
+                    Err(e) => {
+                        // Free the object if a property failed.
+                        unsafe { free_value(context, obj) };
+                        return Err(e);
                     }
-                    e
-                })?;
+                };
 
                 let ret = unsafe {
-                    q::JS_DefinePropertyValueStr(
+                    q::JS_DefinePropertyValue(
                         context,
                         obj,
-                        ckey.as_ptr(),
+                        atom.atom,
                         qvalue,
                         q::JS_PROP_C_W_E as i32,
                     )
@@ -201,11 +200,41 @@ fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSVa
 
             obj
         }
-        #[cfg(feature = "chrono")]
-        JsValue::Date(datetime) => {
-            let date_constructor = js_date_constructor(context);
+        JsValue::ArrayBuffer(bytes) => new_array_buffer(context, bytes)?,
+        JsValue::Map(entries) => new_collection(
+            context,
+            |intrinsics| &intrinsics.map,
+            |intrinsics| &intrinsics.map_set,
+            entries.into_iter().map(|(key, value)| vec![key, value]),
+        )?,
+        JsValue::Set(values) => new_collection(
+            context,
+            |intrinsics| &intrinsics.set,
+            |intrinsics| &intrinsics.set_add,
+            values.into_iter().map(|value| vec![value]),
+        )?,
+        JsValue::Symbol(symbol) => new_symbol(context, symbol)?,
+        JsValue::TypedArray(array) => {
+            let name = array.name();
+            let buffer = match array {
+                TypedArray::Int8(v) => new_array_buffer(context, v),
+                TypedArray::Uint8(v) | TypedArray::Uint8Clamped(v) => new_array_buffer(context, v),
+                TypedArray::Int16(v) => new_array_buffer(context, v),
+                TypedArray::Uint16(v) => new_array_buffer(context, v),
+                TypedArray::Int32(v) => new_array_buffer(context, v),
+                TypedArray::Uint32(v) => new_array_buffer(context, v),
+                TypedArray::BigInt64(v) => new_array_buffer(context, v),
+                TypedArray::BigUint64(v) => new_array_buffer(context, v),
+                TypedArray::Float32(v) => new_array_buffer(context, v),
+                TypedArray::Float64(v) => new_array_buffer(context, v),
+            }?;
+            new_typed_array(context, name, buffer)?
+        }
+        JsValue::Date(date) => {
+            let wrapper = unsafe { ContextWrapper::from_context(context) };
+            let date_constructor = wrapper.intrinsic(|intrinsics| &intrinsics.date)?;
 
-            let f = datetime.timestamp_millis() as f64;
+            let f = date.time_value();
 
             let timestamp = q::JSValue {
                 u: q::JSValueUnion { float64: f },
@@ -222,11 +251,12 @@ fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSVa
                     args.as_mut_ptr(),
                 )
             };
-            unsafe {
-                free_value(context, date_constructor);
-            }
 
             if value.tag != TAG_OBJECT {
+                unsafe {
+                    free_value(context, value);
+                    free_value(context, q::JS_GetException(context));
+                }
                 return Err(ValueError::Internal(
                     "Could not construct Date object".into(),
                 ));
@@ -248,7 +278,7 @@ fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSVa
                 let s = DroppableValue::new(s, |&mut s| unsafe {
                     free_value(context, s);
                 });
-                if (*s).tag != TAG_STRING {
+                if s.tag != TAG_STRING {
                     return Err(ValueError::Internal(
                         "Could not construct String object needed to create BigInt object".into(),
                     ));
@@ -288,8 +318,11 @@ fn serialize_value(context: *mut q::JSContext, value: JsValue) -> Result<q::JSVa
 fn deserialize_array(
     context: *mut q::JSContext,
     raw_value: &q::JSValue,
+    state: &mut DeserializeState,
 ) -> Result<JsValue, ValueError> {
-    assert_eq!(raw_value.tag, TAG_OBJECT);
+    if raw_value.tag != TAG_OBJECT {
+        return Err(ValueError::UnexpectedType);
+    }
 
     let length_name = make_cstring("length")?;
 
@@ -306,13 +339,17 @@ fn deserialize_array(
         }
     };
 
+    state.check_length(len as usize)?;
+
     let mut values = Vec::new();
     for index in 0..(len as usize) {
         let value_raw = unsafe { q::JS_GetPropertyUint32(context, *raw_value, index as u32) };
         if value_raw.tag == TAG_EXCEPTION {
             return Err(ValueError::Internal("Could not build array".into()));
         }
-        let value_res = deserialize_value(context, &value_raw);
+        let value_res = state
+            .count_element()
+            .and_then(|_| deserialize_with_state(context, &value_raw, state));
         unsafe { free_value(context, value_raw) };
 
         let value = value_res?;
@@ -322,7 +359,11 @@ fn deserialize_array(
     Ok(JsValue::Array(values))
 }
 
-fn deserialize_object(context: *mut q::JSContext, obj: &q::JSValue) -> Result<JsValue, ValueError> {
+fn deserialize_object(
+    context: *mut q::JSContext,
+    obj: &q::JSValue,
+    state: &mut DeserializeState,
+) -> Result<JsValue, ValueError> {
     assert_eq!(obj.tag, TAG_OBJECT);
 
     let mut properties: *mut q::JSPropertyEnum = std::ptr::null_mut();
@@ -350,282 +391,1008 @@ fn deserialize_object(context: *mut q::JSContext, obj: &q::JSValue) -> Result<Js
         }
     });
 
-    let mut map = HashMap::new();
+    let mut map = JsObjectMap::new();
     for index in 0..count {
         let prop = unsafe { (*properties).offset(index as isize) };
-        let raw_value = unsafe { q::JS_GetPropertyInternal(context, *obj, (*prop).atom, *obj, 0) };
-        if raw_value.tag == TAG_EXCEPTION {
-            return Err(ValueError::Internal("Could not get object property".into()));
-        }
-
-        let value_res = deserialize_value(context, &raw_value);
-        unsafe {
-            free_value(context, raw_value);
-        }
-        let value = value_res?;
 
-        let key_value = unsafe { q::JS_AtomToString(context, (*prop).atom) };
+        let key_value = unsafe { q::JS_AtomToValue(context, (*prop).atom) };
         if key_value.tag == TAG_EXCEPTION {
             return Err(ValueError::Internal(
                 "Could not get object property name".into(),
             ));
         }
-
         let key_res = deserialize_value(context, &key_value);
         unsafe {
             free_value(context, key_value);
         }
         let key = match key_res? {
             JsValue::String(s) => s,
+            JsValue::IllFormedString(s) => {
+                return Err(ValueError::Custom(format!(
+                    "Property name {:?} contains a lone surrogate",
+                    s.to_string_lossy()
+                )));
+            }
+            JsValue::Symbol(symbol) => match state.symbol_keys {
+                SymbolKeys::Skip => continue,
+                SymbolKeys::Error => return Err(ValueError::SymbolKey(symbol)),
+            },
             _ => {
                 return Err(ValueError::Internal("Could not get property name".into()));
             }
         };
+
+        state.count_element()?;
+        let raw_value = unsafe { q::JS_GetPropertyInternal(context, *obj, (*prop).atom, *obj, 0) };
+        if raw_value.tag == TAG_EXCEPTION {
+            return Err(ValueError::Internal("Could not get object property".into()));
+        }
+
+        let value_res = deserialize_with_state(context, &raw_value, state);
+        unsafe {
+            free_value(context, raw_value);
+        }
+        let value = value_res?;
+
         map.insert(key, value);
     }
 
     Ok(JsValue::Object(map))
 }
 
-fn deserialize_value(
+/// Selects a builtin function from the intrinsics.
+type Intrinsic = for<'a> fn(&'a Intrinsics) -> &'a PersistentRef;
+
+/// Call a builtin function from the intrinsics.
+fn call_intrinsic(
     context: *mut q::JSContext,
-    value: &q::JSValue,
-) -> Result<JsValue, ValueError> {
-    let r = value;
+    intrinsic: Intrinsic,
+    this: q::JSValue,
+    args: &mut [q::JSValue],
+    name: &str,
+) -> Result<q::JSValue, ValueError> {
+    let wrapper = unsafe { ContextWrapper::from_context(context) };
+    let function = wrapper.intrinsic(intrinsic)?;
+    let value = unsafe {
+        q::JS_Call(
+            context,
+            function,
+            this,
+            args.len() as c_int,
+            args.as_mut_ptr(),
+        )
+    };
+    if value.tag == TAG_EXCEPTION {
+        unsafe { free_value(context, q::JS_GetException(context)) };
+        return Err(ValueError::Internal(format!("Could not call {}", name)));
+    }
+    Ok(value)
+}
 
-    match r.tag {
-        // Int.
-        TAG_INT => {
-            let val = unsafe { r.u.int32 };
-            Ok(JsValue::Int(val))
-        }
-        // Bool.
-        TAG_BOOL => {
-            let raw = unsafe { r.u.int32 };
-            let val = raw > 0;
-            Ok(JsValue::Bool(val))
-        }
-        // Null.
-        TAG_NULL => Ok(JsValue::Null),
-        // Undefined.
-        TAG_UNDEFINED => Ok(JsValue::Null),
-        // Float.
-        TAG_FLOAT64 => {
-            let val = unsafe { r.u.float64 };
-            Ok(JsValue::Float(val))
+/// Call a builtin constructor from the intrinsics, like `Map`.
+fn construct_intrinsic(
+    context: *mut q::JSContext,
+    intrinsic: impl FnOnce(&Intrinsics) -> &PersistentRef,
+    args: &mut [q::JSValue],
+    name: &str,
+) -> Result<q::JSValue, ValueError> {
+    let wrapper = unsafe { ContextWrapper::from_context(context) };
+    let constructor = wrapper.intrinsic(intrinsic)?;
+    let value = unsafe {
+        q::JS_CallConstructor(context, constructor, args.len() as c_int, args.as_mut_ptr())
+    };
+    if value.tag != TAG_OBJECT {
+        unsafe {
+            free_value(context, value);
+            free_value(context, q::JS_GetException(context));
         }
-        // String.
-        TAG_STRING => {
-            let ptr = unsafe { q::JS_ToCStringLen2(context, std::ptr::null_mut(), *r, 0) };
+        return Err(ValueError::Internal(format!(
+            "Could not construct {}",
+            name
+        )));
+    }
+    Ok(value)
+}
 
-            if ptr.is_null() {
-                return Err(ValueError::Internal(
-                    "Could not convert string: got a null pointer".into(),
-                ));
+/// Create a `Map` or a `Set`, and add the entries with the builtin `set` or
+/// `add` method.
+fn new_collection(
+    context: *mut q::JSContext,
+    constructor: Intrinsic,
+    add: Intrinsic,
+    entries: impl Iterator<Item = Vec<JsValue>>,
+) -> Result<q::JSValue, ValueError> {
+    let free = |&mut value: &mut q::JSValue| unsafe { free_value(context, value) };
+    let collection = construct_intrinsic(context, constructor, &mut [], "collection")?;
+    let collection = DroppableValue::new(collection, free);
+    for entry in entries {
+        let mut args = DroppableValue::new(Vec::new(), |args: &mut Vec<q::JSValue>| {
+            for arg in args.drain(..) {
+                unsafe { free_value(context, arg) };
             }
-
-            let cstr = unsafe { std::ffi::CStr::from_ptr(ptr) };
-
-            let s = cstr
-                .to_str()
-                .map_err(ValueError::InvalidString)?
-                .to_string();
-
-            // Free the c string.
-            unsafe { q::JS_FreeCString(context, ptr) };
-
-            Ok(JsValue::String(s))
+        });
+        for value in entry {
+            args.push(serialize_value(context, value)?);
         }
-        // Object.
-        TAG_OBJECT => {
-            let is_array = unsafe { q::JS_IsArray(context, *r) } > 0;
-            if is_array {
-                deserialize_array(context, r)
-            } else {
-                #[cfg(feature = "chrono")]
-                {
-                    use chrono::offset::TimeZone;
-
-                    let date_constructor = js_date_constructor(context);
-                    let is_date = unsafe { q::JS_IsInstanceOf(context, *r, date_constructor) > 0 };
-
-                    if is_date {
-                        let getter = unsafe {
-                            q::JS_GetPropertyStr(
-                                context,
-                                *r,
-                                std::ffi::CStr::from_bytes_with_nul(b"getTime\0")
-                                    .unwrap()
-                                    .as_ptr(),
-                            )
-                        };
-                        assert_eq!(getter.tag, TAG_OBJECT);
+        let result = call_intrinsic(context, add, *collection, &mut args, "add")?;
+        unsafe { free_value(context, result) };
+    }
+    Ok(unsafe { dup_value(*collection) })
+}
 
-                        let timestamp_raw =
-                            unsafe { q::JS_Call(context, getter, *r, 0, std::ptr::null_mut()) };
-                        unsafe {
-                            free_value(context, getter);
-                            free_value(context, date_constructor);
-                        };
-
-                        let res = if timestamp_raw.tag != TAG_FLOAT64 {
-                            Err(ValueError::Internal(
-                                "Could not convert 'Date' instance to timestamp".into(),
-                            ))
-                        } else {
-                            let f = unsafe { timestamp_raw.u.float64 } as i64;
-                            let datetime = chrono::Utc.timestamp_millis(f);
-                            Ok(JsValue::Date(datetime))
-                        };
-                        return res;
-                    } else {
-                        unsafe { free_value(context, date_constructor) };
-                    }
-                }
+/// Copy the entries of a `Map` or the values of a `Set` into an array.
+///
+/// The collection is iterated with the builtin `entries` or `values` method
+/// and iterator `next`, so replacing them or `Symbol.iterator` in Javascript
+/// does not change the result.
+pub(crate) fn collection_to_array(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+    class_id: q::JSClassID,
+) -> Result<q::JSValue, ValueError> {
+    let (iterate, next): (Intrinsic, Intrinsic) = if class_id == q::JS_CLASS_MAP {
+        (
+            |intrinsics| &intrinsics.map_entries,
+            |intrinsics| &intrinsics.map_iterator_next,
+        )
+    } else {
+        (
+            |intrinsics| &intrinsics.set_values,
+            |intrinsics| &intrinsics.set_iterator_next,
+        )
+    };
+    let free = |&mut value: &mut q::JSValue| unsafe { free_value(context, value) };
+    let iterator = call_intrinsic(context, iterate, *value, &mut [], "iterator")?;
+    let iterator = DroppableValue::new(iterator, free);
+    let array = DroppableValue::new(unsafe { q::JS_NewArray(context) }, free);
+    if array.tag != TAG_OBJECT {
+        unsafe { free_value(context, q::JS_GetException(context)) };
+        return Err(ValueError::Internal("Could not create array".into()));
+    }
 
-                deserialize_object(context, r)
+    for index in 0.. {
+        let result = call_intrinsic(context, next, *iterator, &mut [], "next")?;
+        let result = DroppableValue::new(result, free);
+        // The builtin `next` returns a new object with own `done` and
+        // `value` properties.
+        let done = unsafe { q::JS_GetPropertyStr(context, *result, b"done\0".as_ptr() as _) };
+        if done.tag != TAG_BOOL {
+            unsafe {
+                free_value(context, done);
+                free_value(context, q::JS_GetException(context));
             }
+            return Err(ValueError::Internal("Invalid iterator result".into()));
         }
-        // BigInt
-        #[cfg(feature = "bigint")]
-        TAG_BIG_INT => {
-            let mut int: i64 = 0;
-            let ret = unsafe { q::JS_ToBigInt64(context, &mut int, *r) };
-            if ret == 0 {
-                Ok(JsValue::BigInt(BigInt {
-                    inner: BigIntOrI64::Int(int),
-                }))
-            } else {
-                let ptr = unsafe { q::JS_ToCStringLen2(context, std::ptr::null_mut(), *r, 0) };
-
-                if ptr.is_null() {
-                    return Err(ValueError::Internal(
-                        "Could not convert BigInt to string: got a null pointer".into(),
-                    ));
-                }
-
-                let cstr = unsafe { std::ffi::CStr::from_ptr(ptr) };
-                let bigint = num_bigint::BigInt::parse_bytes(cstr.to_bytes(), 10).unwrap();
+        if unsafe { done.u.int32 } != 0 {
+            break;
+        }
+        let element = unsafe { q::JS_GetPropertyStr(context, *result, b"value\0".as_ptr() as _) };
+        // Takes ownership of the element.
+        let ret = unsafe {
+            q::JS_DefinePropertyValueUint32(
+                context,
+                *array,
+                index,
+                element,
+                q::JS_PROP_C_W_E as c_int,
+            )
+        };
+        if ret < 0 {
+            unsafe { free_value(context, q::JS_GetException(context)) };
+            return Err(ValueError::Internal("Could not add array element".into()));
+        }
+    }
+    Ok(unsafe { dup_value(*array) })
+}
 
-                // Free the c string.
-                unsafe { q::JS_FreeCString(context, ptr) };
+/// Deserialize the entries of a `Map` or the values of a `Set`.
+fn deserialize_collection(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+    class_id: q::JSClassID,
+    state: &mut DeserializeState,
+) -> Result<Vec<JsValue>, ValueError> {
+    let array = collection_to_array(context, value, class_id)?;
+    let array = DroppableValue::new(array, |&mut array| unsafe {
+        free_value(context, array);
+    });
+    match deserialize_array(context, &array, state)? {
+        JsValue::Array(values) => Ok(values),
+        _ => Err(ValueError::UnexpectedType),
+    }
+}
 
-                Ok(JsValue::BigInt(BigInt {
-                    inner: BigIntOrI64::BigInt(bigint),
-                }))
+/// Deserialize a `Map`.
+fn deserialize_map(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+    state: &mut DeserializeState,
+) -> Result<JsValue, ValueError> {
+    let entries = deserialize_collection(context, value, q::JS_CLASS_MAP, state)?
+        .into_iter()
+        .map(|entry| match entry {
+            JsValue::Array(entry) if entry.len() == 2 => {
+                let mut entry = entry.into_iter();
+                Ok((entry.next().unwrap(), entry.next().unwrap()))
             }
-        }
-        x => Err(ValueError::Internal(format!(
-            "Unhandled JS_TAG value: {}",
-            x
-        ))),
-    }
+            _ => Err(ValueError::Internal("Invalid Map entry".into())),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(JsValue::Map(entries))
 }
 
-/// Helper for creating CStrings.
-fn make_cstring(value: impl Into<Vec<u8>>) -> Result<CString, ValueError> {
-    CString::new(value).map_err(ValueError::StringWithZeroBytes)
+/// Create a symbol from its description.
+fn new_symbol(context: *mut q::JSContext, symbol: JsSymbol) -> Result<q::JSValue, ValueError> {
+    let mut args = match symbol.description() {
+        Some(description) => vec![serialize_value(
+            context,
+            JsValue::String(description.to_string()),
+        )?],
+        None => vec![],
+    };
+    let function: Intrinsic = if symbol.is_registered() {
+        |intrinsics| &intrinsics.symbol_for
+    } else {
+        |intrinsics| &intrinsics.symbol
+    };
+    let value = call_intrinsic(context, function, js_undefined_value(), &mut args, "Symbol");
+    for arg in args {
+        unsafe { free_value(context, arg) };
+    }
+    value
 }
 
-/// Helper to construct null JsValue
-fn js_null_value() -> q::JSValue {
-    q::JSValue {
-        u: q::JSValueUnion { int32: 0 },
-        tag: TAG_NULL,
+/// Deserialize a symbol to its description.
+fn deserialize_symbol(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+) -> Result<JsValue, ValueError> {
+    let mut args = [*value];
+    let key = call_intrinsic(
+        context,
+        |intrinsics| &intrinsics.symbol_key_for,
+        js_undefined_value(),
+        &mut args,
+        "keyFor",
+    )?;
+    let key = DroppableValue::new(key, |&mut key| unsafe {
+        free_value(context, key);
+    });
+    if let JsValue::String(key) = deserialize_value(context, &key)? {
+        return Ok(JsValue::Symbol(JsSymbol::registered(key)));
     }
+
+    let description = call_intrinsic(
+        context,
+        |intrinsics| &intrinsics.symbol_description,
+        *value,
+        &mut [],
+        "description",
+    )?;
+    let description = DroppableValue::new(description, |&mut description| unsafe {
+        free_value(context, description);
+    });
+    Ok(JsValue::Symbol(JsSymbol::new(
+        deserialize_value(context, &description)?.into_string(),
+    )))
 }
 
-type WrappedCallback = dyn Fn(c_int, *mut q::JSValue) -> q::JSValue;
+/// Free function for array buffers created with `new_array_buffer`.
+unsafe extern "C" fn free_array_buffer(
+    _rt: *mut q::JSRuntime,
+    opaque: *mut c_void,
+    _ptr: *mut c_void,
+) {
+    drop(Box::from_raw(opaque as *mut Box<dyn Any>));
+}
 
-/// Taken from: https://s3.amazonaws.com/temp.michaelfbryan.com/callbacks/index.html
-///
-/// Create a C wrapper function for a Rust closure to enable using it as a
-/// callback function in the Quickjs runtime.
-///
-/// Both the boxed closure and the boxed data are returned and must be stored
-/// by the caller to guarantee they stay alive.
-unsafe fn build_closure_trampoline<F>(
-    closure: F,
-) -> ((Box<WrappedCallback>, Box<q::JSValue>), q::JSCFunctionData)
-where
-    F: Fn(c_int, *mut q::JSValue) -> q::JSValue + 'static,
-{
-    unsafe extern "C" fn trampoline<F>(
-        _ctx: *mut q::JSContext,
-        _this: q::JSValue,
-        argc: c_int,
-        argv: *mut q::JSValue,
-        _magic: c_int,
-        data: *mut q::JSValue,
-    ) -> q::JSValue
-    where
-        F: Fn(c_int, *mut q::JSValue) -> q::JSValue,
-    {
-        let closure_ptr = (*data).u.ptr;
-        let closure: &mut F = &mut *(closure_ptr as *mut F);
-        (*closure)(argc, argv)
+/// Create an `ArrayBuffer` that takes ownership of a vector, without copying
+/// the data.
+pub(crate) fn new_array_buffer<T: Copy + 'static>(
+    context: *mut q::JSContext,
+    mut data: Vec<T>,
+) -> Result<q::JSValue, ValueError> {
+    let ptr = data.as_mut_ptr() as *mut u8;
+    let len = data.len() * std::mem::size_of::<T>();
+    // The vector is dropped by the free function once the buffer is
+    // garbage collected. Moving it does not move the data.
+    let opaque: Box<Box<dyn Any>> = Box::new(Box::new(data));
+    let opaque = Box::into_raw(opaque);
+
+    let value = unsafe {
+        q::JS_NewArrayBuffer(
+            context,
+            ptr,
+            len as _,
+            Some(free_array_buffer),
+            opaque as *mut c_void,
+            0,
+        )
+    };
+    if value.tag == TAG_EXCEPTION {
+        // The free function is not called if the buffer could not be created.
+        unsafe { drop(Box::from_raw(opaque)) };
+        return Err(ValueError::Internal("Could not create ArrayBuffer".into()));
     }
+    Ok(value)
+}
 
-    let boxed_f = Box::new(closure);
-
-    let data = Box::new(q::JSValue {
-        u: q::JSValueUnion {
-            ptr: (&*boxed_f) as *const F as *mut c_void,
-        },
-        tag: TAG_NULL,
+/// Create a typed array with the given constructor for an array buffer.
+fn new_typed_array(
+    context: *mut q::JSContext,
+    name: &str,
+    buffer: q::JSValue,
+) -> Result<q::JSValue, ValueError> {
+    let mut buffer = DroppableValue::new(buffer, |&mut buffer| unsafe {
+        free_value(context, buffer);
     });
-
-    ((boxed_f, data), Some(trampoline::<F>))
+    construct_intrinsic(
+        context,
+        |intrinsics| &intrinsics.typed_arrays[name],
+        std::slice::from_mut(&mut *buffer),
+        name,
+    )
 }
 
-/// OwnedValueRef wraps a Javascript value from the quickjs runtime.
-/// It prevents leaks by ensuring that the inner value is deallocated on drop.
-pub struct OwnedValueRef<'a> {
-    context: &'a ContextWrapper,
-    value: q::JSValue,
+/// Get the class id of an array buffer or typed array.
+///
+/// Returns `None` for other objects.
+pub(crate) fn buffer_class_id(value: &q::JSValue) -> Option<q::JSClassID> {
+    // The opaque pointer of these classes is never null, so it can be used
+    // to check the class. Buffer and typed array ids are consecutive.
+    (q::JS_CLASS_ARRAY_BUFFER..=q::JS_CLASS_FLOAT64_ARRAY)
+        .find(|&class_id| !unsafe { q::JS_GetOpaque(*value, class_id) }.is_null())
 }
 
-impl<'a> Drop for OwnedValueRef<'a> {
-    fn drop(&mut self) {
-        unsafe {
-            free_value(self.context.context, self.value);
-        }
+/// Get the contents of an array buffer.
+///
+/// The slice is valid as long as the buffer is alive and not detached.
+unsafe fn array_buffer_bytes<'a>(
+    context: *mut q::JSContext,
+    buffer: q::JSValue,
+) -> Result<&'a [u8], ValueError> {
+    let mut len = 0;
+    let ptr = q::JS_GetArrayBuffer(context, &mut len, buffer);
+    if ptr.is_null() {
+        // Detached buffers raise an exception.
+        free_value(context, q::JS_GetException(context));
+        return Err(ValueError::Internal("ArrayBuffer is detached".into()));
     }
+    Ok(std::slice::from_raw_parts(ptr, len as usize))
 }
 
-impl<'a> std::fmt::Debug for OwnedValueRef<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self.value.tag {
-            TAG_EXCEPTION => write!(f, "Exception(?)"),
-            TAG_NULL => write!(f, "NULL"),
-            TAG_UNDEFINED => write!(f, "UNDEFINED"),
-            TAG_BOOL => write!(f, "Bool(?)",),
-            TAG_INT => write!(f, "Int(?)"),
-            TAG_FLOAT64 => write!(f, "Float(?)"),
-            TAG_STRING => write!(f, "String(?)"),
-            TAG_OBJECT => write!(f, "Object(?)"),
-            _ => write!(f, "?"),
-        }
+/// Copy bytes into a vector of numbers.
+fn read_elements<T: Copy>(bytes: &[u8]) -> Vec<T> {
+    let count = bytes.len() / std::mem::size_of::<T>();
+    let mut values = Vec::<T>::with_capacity(count);
+    unsafe {
+        // The vector may be aligned differently than the bytes.
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            values.as_mut_ptr() as *mut u8,
+            count * std::mem::size_of::<T>(),
+        );
+        values.set_len(count);
     }
+    values
 }
 
-impl<'a> OwnedValueRef<'a> {
-    pub fn new(context: &'a ContextWrapper, value: q::JSValue) -> Self {
-        Self { context, value }
+/// Deserialize an array buffer or a typed array.
+pub(crate) fn deserialize_buffer(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+    class_id: q::JSClassID,
+) -> Result<JsValue, ValueError> {
+    if class_id == q::JS_CLASS_ARRAY_BUFFER || class_id == q::JS_CLASS_SHARED_ARRAY_BUFFER {
+        let bytes = unsafe { array_buffer_bytes(context, *value)? };
+        return Ok(JsValue::ArrayBuffer(bytes.to_vec()));
+    }
+
+    let (mut offset, mut length, mut element_size) = (0, 0, 0);
+    let buffer = unsafe {
+        q::JS_GetTypedArrayBuffer(context, *value, &mut offset, &mut length, &mut element_size)
+    };
+    if buffer.tag == TAG_EXCEPTION {
+        unsafe { free_value(context, q::JS_GetException(context)) };
+        return Err(ValueError::Internal(
+            "Could not get typed array buffer".into(),
+        ));
+    }
+    let buffer = DroppableValue::new(buffer, |&mut buffer| unsafe {
+        free_value(context, buffer);
+    });
+    let bytes = unsafe { array_buffer_bytes(context, *buffer)? };
+    let bytes = bytes
+        .get(offset as usize..(offset + length) as usize)
+        .ok_or_else(|| ValueError::Internal("Typed array is out of bounds".into()))?;
+
+    let array = match class_id {
+        q::JS_CLASS_UINT8C_ARRAY => TypedArray::Uint8Clamped(bytes.to_vec()),
+        q::JS_CLASS_INT8_ARRAY => TypedArray::Int8(read_elements(bytes)),
+        q::JS_CLASS_UINT8_ARRAY => TypedArray::Uint8(bytes.to_vec()),
+        q::JS_CLASS_INT16_ARRAY => TypedArray::Int16(read_elements(bytes)),
+        q::JS_CLASS_UINT16_ARRAY => TypedArray::Uint16(read_elements(bytes)),
+        q::JS_CLASS_INT32_ARRAY => TypedArray::Int32(read_elements(bytes)),
+        q::JS_CLASS_UINT32_ARRAY => TypedArray::Uint32(read_elements(bytes)),
+        q::JS_CLASS_BIG_INT64_ARRAY => TypedArray::BigInt64(read_elements(bytes)),
+        q::JS_CLASS_BIG_UINT64_ARRAY => TypedArray::BigUint64(read_elements(bytes)),
+        q::JS_CLASS_FLOAT32_ARRAY => TypedArray::Float32(read_elements(bytes)),
+        q::JS_CLASS_FLOAT64_ARRAY => TypedArray::Float64(read_elements(bytes)),
+        _ => return Err(ValueError::Internal("Unknown typed array class".into())),
+    };
+    Ok(JsValue::TypedArray(array))
+}
+
+/// Deserialize an object, which may be an array, a buffer, a collection or a
+/// date.
+fn deserialize_any_object(
+    context: *mut q::JSContext,
+    r: &q::JSValue,
+    state: &mut DeserializeState,
+) -> Result<JsValue, ValueError> {
+    let is_array = unsafe { q::JS_IsArray(context, *r) } > 0;
+    if is_array {
+        deserialize_array(context, r, state)
+    } else if let Some(class_id) = buffer_class_id(r) {
+        deserialize_buffer(context, r, class_id)
+    } else if !unsafe { q::JS_GetOpaque(*r, q::JS_CLASS_MAP) }.is_null() {
+        deserialize_map(context, r, state)
+    } else if !unsafe { q::JS_GetOpaque(*r, q::JS_CLASS_SET) }.is_null() {
+        Ok(JsValue::Set(deserialize_collection(
+            context,
+            r,
+            q::JS_CLASS_SET,
+            state,
+        )?))
+    } else {
+        let wrapper = unsafe { ContextWrapper::from_context(context) };
+        let date_constructor = wrapper.intrinsic(|intrinsics| &intrinsics.date)?;
+        // Runs `Symbol.hasInstance` and proxy traps, which may throw.
+        let is_date = unsafe { q::JS_IsInstanceOf(context, *r, date_constructor) };
+        if is_date < 0 {
+            unsafe { free_value(context, q::JS_GetException(context)) };
+            return Err(ValueError::Internal(
+                "Could not check if the object is a Date".into(),
+            ));
+        }
+
+        if is_date > 0 {
+            return deserialize_date(context, r);
+        }
+
+        deserialize_object(context, r, state)
+    }
+}
+
+/// Deserialize a `Date` with the builtin `getTime`, which is `NaN` for
+/// invalid dates.
+fn deserialize_date(context: *mut q::JSContext, r: &q::JSValue) -> Result<JsValue, ValueError> {
+    let wrapper = unsafe { ContextWrapper::from_context(context) };
+    let get_time = wrapper.intrinsic(|intrinsics| &intrinsics.date_get_time)?;
+    let timestamp_raw = unsafe { q::JS_Call(context, get_time, *r, 0, std::ptr::null_mut()) };
+
+    let time_value = match timestamp_raw.tag {
+        TAG_FLOAT64 => unsafe { timestamp_raw.u.float64 },
+        TAG_INT => f64::from(unsafe { timestamp_raw.u.int32 }),
+        _ => {
+            unsafe {
+                free_value(context, timestamp_raw);
+                // Objects that inherit from `Date.prototype` but are not
+                // dates throw a `TypeError`.
+                free_value(context, q::JS_GetException(context));
+            }
+            return Err(ValueError::Internal(
+                "Could not convert 'Date' instance to timestamp".into(),
+            ));
+        }
+    };
+    Ok(JsValue::Date(JsDate::from_time_value(time_value)))
+}
+
+/// Limits for the deserialization of values, see `ContextBuilder`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DeserializeLimits {
+    pub max_depth: usize,
+    pub max_elements: usize,
+}
+
+impl Default for DeserializeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            max_elements: 1_000_000,
+        }
+    }
+}
+
+/// State of a single value deserialization.
+#[derive(Default)]
+pub(crate) struct DeserializeState {
+    /// The objects that are currently being deserialized, used to detect
+    /// cycles and to limit the depth.
+    parents: Vec<*mut c_void>,
+    /// The number of array elements, properties and entries seen so far.
+    elements: usize,
+    limits: DeserializeLimits,
+    pub symbol_keys: SymbolKeys,
+}
+
+impl DeserializeState {
+    /// Create the state with the settings of the context.
+    pub fn new(context: *mut q::JSContext) -> Self {
+        let wrapper = unsafe { ContextWrapper::from_context(context) };
+        Self {
+            parents: Vec::new(),
+            elements: 0,
+            limits: wrapper.deserialize_limits,
+            symbol_keys: wrapper.symbol_keys,
+        }
+    }
+
+    pub fn enter(&mut self, object: &q::JSValue) -> Result<(), ValueError> {
+        let ptr = unsafe { object.u.ptr };
+        if self.parents.contains(&ptr) {
+            return Err(ValueError::CyclicValue);
+        }
+        if self.parents.len() >= self.limits.max_depth {
+            return Err(ValueError::MaxDepthExceeded);
+        }
+        self.parents.push(ptr);
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.parents.pop();
+    }
+
+    /// Check the length of an array before reading its elements, so sparse
+    /// arrays with a huge length fail without iterating.
+    pub fn check_length(&self, len: usize) -> Result<(), ValueError> {
+        if self.elements.saturating_add(len) > self.limits.max_elements {
+            return Err(ValueError::MaxElementsExceeded);
+        }
+        Ok(())
+    }
+
+    pub fn count_element(&mut self) -> Result<(), ValueError> {
+        self.elements += 1;
+        if self.elements > self.limits.max_elements {
+            return Err(ValueError::MaxElementsExceeded);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn deserialize_value(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+) -> Result<JsValue, ValueError> {
+    #[cfg(test)]
+    VALUE_CONVERSIONS.with(|count| count.set(count.get() + 1));
+
+    // Only objects need the settings of the context, so primitives are
+    // converted without looking them up.
+    let mut state = match value.tag {
+        TAG_OBJECT => DeserializeState::new(context),
+        _ => DeserializeState::default(),
+    };
+    deserialize_with_state(context, value, &mut state)
+}
+
+fn deserialize_with_state(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+    state: &mut DeserializeState,
+) -> Result<JsValue, ValueError> {
+    let r = value;
+
+    match r.tag {
+        // Int.
+        TAG_INT => {
+            let val = unsafe { r.u.int32 };
+            Ok(JsValue::Int(val))
+        }
+        // Bool.
+        TAG_BOOL => {
+            let raw = unsafe { r.u.int32 };
+            let val = raw > 0;
+            Ok(JsValue::Bool(val))
+        }
+        // Null.
+        TAG_NULL => Ok(JsValue::Null),
+        // Undefined.
+        TAG_UNDEFINED => Ok(JsValue::Undefined),
+        // Float.
+        TAG_FLOAT64 => {
+            let val = unsafe { r.u.float64 };
+            Ok(JsValue::Float(val))
+        }
+        // String.
+        TAG_STRING => Ok(deserialize_string(context, r)?.into()),
+        // Object.
+        TAG_OBJECT => {
+            state.enter(r)?;
+            let result = deserialize_any_object(context, r, state);
+            state.leave();
+            result
+        }
+        TAG_SYMBOL => deserialize_symbol(context, r),
+        // BigInt
+        #[cfg(feature = "bigint")]
+        TAG_BIG_INT => {
+            let mut int: i64 = 0;
+            let ret = unsafe { q::JS_ToBigInt64(context, &mut int, *r) };
+            if ret == 0 {
+                Ok(JsValue::BigInt(BigInt {
+                    inner: BigIntOrI64::Int(int),
+                }))
+            } else {
+                let ptr = unsafe { q::JS_ToCStringLen2(context, std::ptr::null_mut(), *r, 0) };
+
+                if ptr.is_null() {
+                    return Err(ValueError::Internal(
+                        "Could not convert BigInt to string: got a null pointer".into(),
+                    ));
+                }
+
+                let cstr = unsafe { std::ffi::CStr::from_ptr(ptr) };
+                let bigint = num_bigint::BigInt::parse_bytes(cstr.to_bytes(), 10).unwrap();
+
+                // Free the c string.
+                unsafe { q::JS_FreeCString(context, ptr) };
+
+                Ok(JsValue::BigInt(BigInt {
+                    inner: BigIntOrI64::BigInt(bigint),
+                }))
+            }
+        }
+        x => Err(ValueError::Internal(format!(
+            "Unhandled JS_TAG value: {}",
+            x
+        ))),
+    }
+}
+
+/// Helper for creating CStrings.
+pub(crate) fn make_cstring(value: impl Into<Vec<u8>>) -> Result<CString, ValueError> {
+    CString::new(value).map_err(ValueError::StringWithZeroBytes)
+}
+
+/// Copy source code into a buffer for the quickjs parser.
+///
+/// The parser reads one byte past the end of the input, which must be \0.
+/// Unlike with `make_cstring`, the input itself may contain \0 bytes.
+fn make_source(value: &str) -> Vec<u8> {
+    let mut source = Vec::with_capacity(value.len() + 1);
+    source.extend_from_slice(value.as_bytes());
+    source.push(0);
+    source
+}
+
+/// Create a string from UTF-8 or WTF-8 bytes, which may contain \0 bytes.
+fn new_string(context: *mut q::JSContext, bytes: &[u8]) -> Result<q::JSValue, ValueError> {
+    let value =
+        unsafe { q::JS_NewStringLen(context, bytes.as_ptr() as *const c_char, bytes.len() as _) };
+    if value.tag == TAG_EXCEPTION {
+        return Err(ValueError::Internal(
+            "Could not create string in runtime".into(),
+        ));
+    }
+    Ok(value)
+}
+
+/// Read a string, which may contain \0 bytes and lone surrogates.
+pub(crate) fn deserialize_string(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+) -> Result<JsString, ValueError> {
+    let mut len = 0;
+    let ptr = unsafe { q::JS_ToCStringLen2(context, &mut len, *value, 0) };
+    if ptr.is_null() {
+        return Err(ValueError::Internal(
+            "Could not convert string: got a null pointer".into(),
+        ));
+    }
+    // Lone surrogates are kept as WTF-8.
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }.to_vec();
+    unsafe { q::JS_FreeCString(context, ptr) };
+    Ok(JsString::from_wtf8(bytes))
+}
+
+/// An atom for a property name, which may contain \0 bytes.
+///
+/// The atom is freed on drop.
+pub(crate) struct Atom {
+    context: *mut q::JSContext,
+    pub atom: q::JSAtom,
+}
+
+impl Atom {
+    pub fn new(context: *mut q::JSContext, name: &str) -> Result<Self, ValueError> {
+        let atom =
+            unsafe { q::JS_NewAtomLen(context, name.as_ptr() as *const c_char, name.len() as _) };
+        // JS_ATOM_NULL
+        if atom == 0 {
+            unsafe { free_value(context, q::JS_GetException(context)) };
+            return Err(ValueError::Internal("Could not create atom".into()));
+        }
+        Ok(Self { context, atom })
+    }
+}
+
+impl Drop for Atom {
+    fn drop(&mut self) {
+        unsafe { q::JS_FreeAtom(self.context, self.atom) };
+    }
+}
+
+/// Helper to construct null JsValue
+pub(crate) fn js_null_value() -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: 0 },
+        tag: TAG_NULL,
+    }
+}
+
+/// Helper to construct undefined JsValue
+pub(crate) fn js_undefined_value() -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: 0 },
+        tag: TAG_UNDEFINED,
+    }
+}
+
+/// Helper to construct bool JsValue
+pub(crate) fn js_bool_value(value: bool) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion {
+            int32: if value { 1 } else { 0 },
+        },
+        tag: TAG_BOOL,
+    }
+}
+
+/// Helper to construct int JsValue
+pub(crate) fn js_int_value(value: i32) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: value },
+        tag: TAG_INT,
+    }
+}
+
+/// Helper to construct float JsValue
+pub(crate) fn js_float_value(value: f64) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { float64: value },
+        tag: TAG_FLOAT64,
+    }
+}
+
+/// Job function that calls the function passed as the single job argument.
+///
+/// Used for both `queueMicrotask` and `ContextWrapper::enqueue_job`.
+unsafe extern "C" fn js_call_job(
+    context: *mut q::JSContext,
+    _argc: c_int,
+    argv: *mut q::JSValue,
+) -> q::JSValue {
+    q::JS_Call(
+        context,
+        *argv,
+        js_undefined_value(),
+        0,
+        std::ptr::null_mut(),
+    )
+}
+
+/// Implementation of the global `queueMicrotask(callback)` function.
+/// See: https://html.spec.whatwg.org/multipage/timers-and-user-prompts.html#dom-queuemicrotask
+unsafe extern "C" fn js_queue_microtask(
+    context: *mut q::JSContext,
+    _this: q::JSValue,
+    argc: c_int,
+    argv: *mut q::JSValue,
+) -> q::JSValue {
+    if argc < 1 || q::JS_IsFunction(context, *argv) == 0 {
+        return q::JS_ThrowTypeError(
+            context,
+            b"queueMicrotask: argument must be a function\0".as_ptr() as *const c_char,
+        );
+    }
+    if q::JS_EnqueueJob(context, Some(js_call_job), 1, argv) < 0 {
+        return q::JSValue {
+            u: q::JSValueUnion { int32: 0 },
+            tag: TAG_EXCEPTION,
+        };
+    }
+    js_undefined_value()
+}
+
+type WrappedCallback = dyn Fn(q::JSValue, c_int, *mut q::JSValue) -> q::JSValue;
+
+/// The Rust closure of a function created with `ContextWrapper::new_function`.
+///
+/// It is stored as the opaque value of the function object, and dropped by
+/// the class finalizer once Javascript no longer references the function.
+struct RustFunction {
+    call: Box<WrappedCallback>,
+    constructor: bool,
+}
+
+/// Class `call` hook for Rust functions.
+///
+/// Unlike C functions, the hook receives the function object, which holds
+/// the closure, and the flags that tell constructor calls apart.
+unsafe extern "C" fn rust_function_call(
+    context: *mut q::JSContext,
+    func_obj: q::JSValue,
+    this: q::JSValue,
+    argc: c_int,
+    argv: *mut q::JSValue,
+    flags: c_int,
+) -> q::JSValue {
+    let function =
+        q::JS_GetOpaque(func_obj, class::class_id::<RustFunction>()) as *const RustFunction;
+    // The function object is alive while it is being called.
+    let function = &*function;
+    if function.constructor && flags & q::JS_CALL_FLAG_CONSTRUCTOR as c_int == 0 {
+        return q::JS_ThrowTypeError(
+            context,
+            b"Constructor requires 'new'\0".as_ptr() as *const c_char,
+        );
+    }
+    (function.call)(this, argc, argv)
+}
+
+unsafe extern "C" fn rust_function_finalize(_rt: *mut q::JSRuntime, value: q::JSValue) {
+    let ptr = q::JS_GetOpaque(value, class::class_id::<RustFunction>()) as *mut RustFunction;
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Marker type for the class id of errors returned by callbacks.
+///
+/// The Rust error is stored as the opaque value of the thrown Javascript
+/// error, so it can be recovered if the exception is not caught.
+struct HostError;
+
+/// Create a Javascript `Error` that holds the given Rust error.
+unsafe fn new_host_error(
+    context: *mut q::JSContext,
+    error: BoxedError,
+) -> Result<q::JSValue, ExecutionError> {
+    let class_id = class::class_id::<HostError>();
+    let runtime = q::JS_GetRuntime(context);
+    if q::JS_IsRegisteredClass(runtime, class_id) == 0 {
+        let def = q::JSClassDef {
+            class_name: b"Error\0".as_ptr() as *const c_char,
+            finalizer: Some(host_error_finalize),
+            gc_mark: None,
+            call: None,
+            exotic: std::ptr::null_mut(),
+        };
+        if q::JS_NewClass(runtime, class_id, &def) < 0 {
+            return Err(ExecutionError::Internal(
+                "Could not register error class".into(),
+            ));
+        }
+    }
+
+    let proto = q::JS_GetClassProto(context, q::JS_CLASS_ERROR);
+    let value = q::JS_NewObjectProtoClass(context, proto, class_id);
+    free_value(context, proto);
+    if value.tag != TAG_OBJECT {
+        return Err(ExecutionError::Internal("Could not create error".into()));
+    }
+
+    let message = match serialize_value(context, JsValue::String(error.to_string())) {
+        Ok(message) => message,
+        Err(e) => {
+            free_value(context, value);
+            return Err(e.into());
+        }
+    };
+    let name = make_cstring("message")?;
+    // The message is not enumerable, like for errors created in Javascript.
+    q::JS_DefinePropertyValueStr(
+        context,
+        value,
+        name.as_ptr(),
+        message,
+        (q::JS_PROP_WRITABLE | q::JS_PROP_CONFIGURABLE) as c_int,
+    );
+
+    let error: Box<Option<BoxedError>> = Box::new(Some(error));
+    q::JS_SetOpaque(value, Box::into_raw(error) as *mut c_void);
+    Ok(value)
+}
+
+/// Take the Rust error out of an error created with `new_host_error`.
+///
+/// Returns `None` for any other value.
+unsafe fn take_host_error(value: q::JSValue) -> Option<BoxedError> {
+    let ptr = q::JS_GetOpaque(value, class::class_id::<HostError>()) as *mut Option<BoxedError>;
+    ptr.as_mut().and_then(Option::take)
+}
+
+unsafe extern "C" fn host_error_finalize(_rt: *mut q::JSRuntime, value: q::JSValue) {
+    let ptr = q::JS_GetOpaque(value, class::class_id::<HostError>()) as *mut Option<BoxedError>;
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+/// OwnedValueRef wraps a Javascript value from the quickjs runtime.
+/// It prevents leaks by ensuring that the inner value is deallocated on drop.
+pub struct OwnedValueRef<'a> {
+    context: &'a ContextWrapper,
+    value: q::JSValue,
+}
+
+impl<'a> Drop for OwnedValueRef<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            free_value(self.context.context, self.value);
+        }
+    }
+}
+
+impl<'a> std::fmt::Debug for OwnedValueRef<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.value.tag {
+            TAG_EXCEPTION => write!(f, "Exception(?)"),
+            TAG_NULL => write!(f, "NULL"),
+            TAG_UNDEFINED => write!(f, "UNDEFINED"),
+            TAG_BOOL => write!(f, "Bool(?)",),
+            TAG_INT => write!(f, "Int(?)"),
+            TAG_FLOAT64 => write!(f, "Float(?)"),
+            TAG_STRING => write!(f, "String(?)"),
+            TAG_OBJECT => write!(f, "Object(?)"),
+            _ => write!(f, "?"),
+        }
+    }
+}
+
+impl<'a> OwnedValueRef<'a> {
+    pub fn new(context: &'a ContextWrapper, value: q::JSValue) -> Self {
+        Self { context, value }
+    }
+
+    /// Take ownership of a value returned by quickjs.
+    ///
+    /// Fails if the value is an exception, which is cleared.
+    #[cfg(feature = "serde")]
+    pub(crate) fn checked(
+        context: *mut q::JSContext,
+        value: q::JSValue,
+        what: &str,
+    ) -> Result<Self, ValueError> {
+        if value.tag == TAG_EXCEPTION {
+            unsafe { free_value(context, q::JS_GetException(context)) };
+            return Err(ValueError::Internal(format!("Could not {}", what)));
+        }
+        Ok(Self::new(
+            unsafe { ContextWrapper::from_context(context) },
+            value,
+        ))
+    }
+
+    /// The raw value, which stays owned by the reference.
+    #[cfg(feature = "serde")]
+    pub(crate) fn raw(&self) -> q::JSValue {
+        self.value
+    }
+
+    /// Get the inner JSValue without freeing in drop.
+    ///
+    /// The caller is responsible for freeing the value.
+    pub fn into_inner(mut self) -> q::JSValue {
+        std::mem::replace(&mut self.value, js_null_value())
     }
 
-    /// Get the inner JSValue without freeing in drop.
-    ///
-    /// Unsafe because the caller is responsible for freeing the value.
-    //unsafe fn into_inner(mut self) -> q::JSValue {
-    //let v = self.value;
-    //self.value = q::JSValue {
-    //u: q::JSValueUnion { int32: 0 },
-    //tag: TAG_NULL,
-    //};
-    //v
-    //}
-
     pub fn is_null(&self) -> bool {
         self.value.tag == TAG_NULL
     }
@@ -668,6 +1435,11 @@ impl<'a> OwnedValueRef<'a> {
         self.context.to_value(&self.value)
     }
 
+    /// Convert the value to a JSON string, see `ContextWrapper::json_stringify`.
+    pub fn json_stringify(&self, indent: Option<usize>) -> Result<String, ExecutionError> {
+        self.context.json_stringify(&self.value, indent)
+    }
+
     pub fn to_bool(&self) -> Result<bool, ValueError> {
         match self.to_value()? {
             JsValue::Bool(b) => Ok(b),
@@ -719,9 +1491,10 @@ impl<'a> OwnedObjectRef<'a> {
     }
 
     pub fn property(&self, name: &str) -> Result<OwnedValueRef<'a>, ExecutionError> {
-        let cname = make_cstring(name)?;
+        let context = self.value.context.context;
+        let atom = Atom::new(context, name)?;
         let raw = unsafe {
-            q::JS_GetPropertyStr(self.value.context.context, self.value.value, cname.as_ptr())
+            q::JS_GetPropertyInternal(context, self.value.value, atom.atom, self.value.value, 0)
         };
 
         if raw.tag == TAG_EXCEPTION {
@@ -740,12 +1513,20 @@ impl<'a> OwnedObjectRef<'a> {
     }
 
     unsafe fn set_property_raw(&self, name: &str, value: q::JSValue) -> Result<(), ExecutionError> {
-        let cname = make_cstring(name)?;
-        let ret = q::JS_SetPropertyStr(
-            self.value.context.context,
+        let context = self.value.context.context;
+        let atom = match Atom::new(context, name) {
+            Ok(atom) => atom,
+            Err(e) => {
+                free_value(context, value);
+                return Err(e.into());
+            }
+        };
+        let ret = q::JS_SetPropertyInternal(
+            context,
             self.value.value,
-            cname.as_ptr(),
+            atom.atom,
             value,
+            q::JS_PROP_THROW as i32,
         );
         if ret < 0 {
             Err(ExecutionError::Exception("Could not set property".into()))
@@ -754,10 +1535,150 @@ impl<'a> OwnedObjectRef<'a> {
         }
     }
 
-    // pub fn set_property(&self, name: &str, value: JsValue) -> Result<(), ExecutionError> {
-    //     let qval = self.value.context.serialize_value(value)?;
-    //     unsafe { self.set_property_raw(name, qval.value) }
-    // }
+    pub fn set_property(&self, name: &str, value: JsValue) -> Result<(), ExecutionError> {
+        let qval = serialize_value(self.value.context.context, value)?;
+        unsafe { self.set_property_raw(name, qval) }
+    }
+}
+
+/// The value slot shared by all clones of a `PersistentRef`.
+///
+/// The value is freed when the last reference is dropped, or when the
+/// context is freed, whichever happens first.
+struct PersistentSlot {
+    context: *mut q::JSContext,
+    value: Cell<Option<q::JSValue>>,
+}
+
+impl PersistentSlot {
+    /// Free the value, if it was not released already.
+    fn release(&self) {
+        if let Some(value) = self.value.take() {
+            unsafe {
+                free_value(self.context, value);
+            }
+        }
+    }
+}
+
+impl Drop for PersistentSlot {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// PersistentRef wraps a Javascript value from the quickjs runtime.
+///
+/// In contrast to `OwnedValueRef`, it is not bound to the lifetime of the
+/// `ContextWrapper`. The wrapper keeps track of all persistent references and
+/// releases their values when the context is freed, so a reference that
+/// outlives its context becomes invalid instead of dangling.
+#[derive(Clone)]
+pub struct PersistentRef {
+    slot: Rc<PersistentSlot>,
+}
+
+impl PersistentRef {
+    /// Create a new persistent reference.
+    ///
+    /// Takes ownership of the given value.
+    fn new(wrapper: &ContextWrapper, value: q::JSValue) -> Self {
+        let slot = Rc::new(PersistentSlot {
+            context: wrapper.context,
+            value: Cell::new(Some(value)),
+        });
+
+        let mut persistent = wrapper.persistent.lock().unwrap();
+        persistent.retain(|slot| slot.strong_count() > 0);
+        persistent.push(Rc::downgrade(&slot));
+
+        Self { slot }
+    }
+
+    /// Get the context wrapper and the referenced value.
+    ///
+    /// Fails if the context was already freed.
+    fn get(&self) -> Result<(&ContextWrapper, q::JSValue), ExecutionError> {
+        let value = self.slot.value.get().ok_or_else(|| {
+            ExecutionError::Internal("Value belongs to a context that was freed".into())
+        })?;
+        // The wrapper is alive as long as the value was not released.
+        let wrapper = unsafe { ContextWrapper::from_context(self.slot.context) };
+        Ok((wrapper, value))
+    }
+
+    /// Get a property of the referenced object.
+    pub fn property(&self, name: &str) -> Result<JsValue, ExecutionError> {
+        let (wrapper, object) = self.get()?;
+        let atom = Atom::new(wrapper.context, name)?;
+        let raw =
+            unsafe { q::JS_GetPropertyInternal(wrapper.context, object, atom.atom, object, 0) };
+        let value = OwnedValueRef::new(wrapper, raw);
+        if value.is_exception() {
+            let err = wrapper
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Unknown exception".into()));
+            return Err(err);
+        }
+        Ok(value.to_value()?)
+    }
+
+    /// Set a property of the referenced object.
+    pub fn set_property(&self, name: &str, value: JsValue) -> Result<(), ExecutionError> {
+        let (wrapper, object) = self.get()?;
+        let atom = Atom::new(wrapper.context, name)?;
+        let qval = serialize_value(wrapper.context, value)?;
+        let ret = unsafe {
+            q::JS_SetPropertyInternal(
+                wrapper.context,
+                object,
+                atom.atom,
+                qval,
+                q::JS_PROP_THROW as i32,
+            )
+        };
+        if ret < 0 {
+            let err = wrapper
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Could not set property".into()));
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Convert the referenced value to a `JsValue`.
+    pub fn to_value(&self) -> Result<JsValue, ExecutionError> {
+        let (wrapper, value) = self.get()?;
+        Ok(wrapper.to_value(&value)?)
+    }
+
+    /// Convert the referenced value to a JSON string, see
+    /// `ContextWrapper::json_stringify`.
+    pub fn json_stringify(&self, indent: Option<usize>) -> Result<String, ExecutionError> {
+        let (wrapper, value) = self.get()?;
+        wrapper.json_stringify(&value, indent)
+    }
+
+    /// Deserialize the referenced value with serde.
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExecutionError> {
+        let (wrapper, value) = self.get()?;
+        Ok(crate::serde_js::from_js(wrapper.context, &value)?)
+    }
+
+    /// Call the referenced value as a function.
+    pub fn call(&self, args: Vec<JsValue>) -> Result<JsValue, ExecutionError> {
+        let (wrapper, function) = self.get()?;
+
+        let qargs = args
+            .into_iter()
+            .map(|arg| wrapper.serialize_value(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let function = OwnedValueRef::new(wrapper, unsafe { dup_value(function) });
+
+        let value = wrapper.call_function(function, qargs)?.to_value()?;
+        Ok(value)
+    }
 }
 
 /*
@@ -785,15 +1706,204 @@ unsafe extern "C" fn native_module_init(
 pub struct ContextWrapper {
     runtime: *mut q::JSRuntime,
     context: *mut q::JSContext,
-    /// Stores callback closures and quickjs data pointers.
-    /// This array is write-only and only exists to ensure the lifetime of
-    /// the closure.
+    /// Typed user data, see `Context::set_data`. Values are stored as
+    /// `RefCell<T>`.
     // A Mutex is used over a RefCell because it needs to be unwind-safe.
-    callbacks: Mutex<Vec<(Box<WrappedCallback>, Box<q::JSValue>)>>,
+    data: Mutex<HashMap<TypeId, Rc<dyn Any>>>,
+    /// Slots of all persistent references that were handed out.
+    /// Their values are released before the context is freed.
+    persistent: Mutex<Vec<Weak<PersistentSlot>>>,
+    /// Whether callback panics are resumed, see `set_resume_panics`.
+    resume_panics: bool,
+    /// How symbol-keyed properties are deserialized.
+    pub(crate) symbol_keys: SymbolKeys,
+    /// Limits for deserializing values.
+    pub(crate) deserialize_limits: DeserializeLimits,
+    /// Typescript declarations of the registered globals.
+    pub(crate) declarations: Mutex<Declarations>,
+    /// The payload of a callback panic that is resumed once Javascript
+    /// execution returns.
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    /// The original values of exceptions that were converted to an
+    /// `ExecutionError`, so that callbacks returning the error rethrow them.
+    exceptions: Mutex<Exceptions>,
+    /// Ids of exceptions whose `ExecutionError` was dropped.
+    released_exceptions: ReleasedExceptions,
+    /// Builtins used for conversions, captured when the context is created.
+    intrinsics: Mutex<Option<Intrinsics>>,
+}
+
+/// The original values of exceptions, by the id of their handle.
+#[derive(Default)]
+struct Exceptions {
+    next_id: u64,
+    values: HashMap<u64, PersistentRef>,
+}
+
+impl Exceptions {
+    /// Free the values whose handle was dropped.
+    fn release(&mut self, released: &ReleasedExceptions) {
+        for id in released.lock().unwrap().drain(..) {
+            self.values.remove(&id);
+        }
+    }
+}
+
+/// Builtin functions that are captured before any Javascript code runs, so
+/// that conversions keep working if the globals are replaced.
+struct Intrinsics {
+    /// `Date`
+    date: PersistentRef,
+    /// `Date.prototype.getTime`
+    date_get_time: PersistentRef,
+    /// `Map`
+    map: PersistentRef,
+    /// `Map.prototype.set`
+    map_set: PersistentRef,
+    /// `Map.prototype.entries`
+    map_entries: PersistentRef,
+    /// `next` of map iterators
+    map_iterator_next: PersistentRef,
+    /// `Set`
+    set: PersistentRef,
+    /// `Set.prototype.add`
+    set_add: PersistentRef,
+    /// `Set.prototype.values`
+    set_values: PersistentRef,
+    /// `next` of set iterators
+    set_iterator_next: PersistentRef,
+    /// `Symbol`
+    symbol: PersistentRef,
+    /// `Symbol.for`
+    symbol_for: PersistentRef,
+    /// `Symbol.keyFor`
+    symbol_key_for: PersistentRef,
+    /// The getter of `Symbol.prototype.description`
+    symbol_description: PersistentRef,
+    /// Typed array constructors by name
+    typed_arrays: HashMap<&'static str, PersistentRef>,
+}
+
+/// The names of the typed array constructors.
+const TYPED_ARRAYS: [&str; 11] = [
+    "Int8Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "Int16Array",
+    "Uint16Array",
+    "Int32Array",
+    "Uint32Array",
+    "BigInt64Array",
+    "BigUint64Array",
+    "Float32Array",
+    "Float64Array",
+];
+
+impl Intrinsics {
+    fn new(wrapper: &ContextWrapper) -> Result<Self, ExecutionError> {
+        let global = wrapper.global()?;
+        let date = OwnedObjectRef::new(global.property("Date")?)?;
+        let date_prototype = OwnedObjectRef::new(date.property("prototype")?)?;
+        let map = OwnedObjectRef::new(global.property("Map")?)?;
+        let map_prototype = OwnedObjectRef::new(map.property("prototype")?)?;
+        let map_entries = map_prototype.property("entries")?;
+        let set = OwnedObjectRef::new(global.property("Set")?)?;
+        let set_prototype = OwnedObjectRef::new(set.property("prototype")?)?;
+        let set_values = set_prototype.property("values")?;
+        let symbol = OwnedObjectRef::new(global.property("Symbol")?)?;
+        let symbol_prototype = OwnedObjectRef::new(symbol.property("prototype")?)?;
+        let typed_arrays = TYPED_ARRAYS
+            .iter()
+            .map(|&name| Ok((name, wrapper.persistent_owned(global.property(name)?))))
+            .collect::<Result<_, ExecutionError>>()?;
+        Ok(Self {
+            date_get_time: wrapper.persistent_owned(date_prototype.property("getTime")?),
+            date: wrapper.persistent_owned(date.into_value()),
+            map_set: wrapper.persistent_owned(map_prototype.property("set")?),
+            map_iterator_next: Self::iterator_next(wrapper, &map, &map_entries)?,
+            map_entries: wrapper.persistent_owned(map_entries),
+            map: wrapper.persistent_owned(map.into_value()),
+            set_add: wrapper.persistent_owned(set_prototype.property("add")?),
+            set_iterator_next: Self::iterator_next(wrapper, &set, &set_values)?,
+            set_values: wrapper.persistent_owned(set_values),
+            set: wrapper.persistent_owned(set.into_value()),
+            symbol_for: wrapper.persistent_owned(symbol.property("for")?),
+            symbol_key_for: wrapper.persistent_owned(symbol.property("keyFor")?),
+            symbol_description: Self::getter(wrapper, &symbol_prototype, "description")?,
+            symbol: wrapper.persistent_owned(symbol.into_value()),
+            typed_arrays,
+        })
+    }
+
+    /// Get `next` of the iterator that `method` returns for a new instance of
+    /// `constructor`.
+    fn iterator_next(
+        wrapper: &ContextWrapper,
+        constructor: &OwnedObjectRef,
+        method: &OwnedValueRef,
+    ) -> Result<PersistentRef, ExecutionError> {
+        let context = wrapper.context;
+        let instance = OwnedValueRef::new(wrapper, unsafe {
+            q::JS_CallConstructor(context, constructor.value.value, 0, std::ptr::null_mut())
+        });
+        let iterator = OwnedValueRef::new(wrapper, unsafe {
+            q::JS_Call(
+                context,
+                method.value,
+                instance.value,
+                0,
+                std::ptr::null_mut(),
+            )
+        });
+        let iterator = OwnedObjectRef::new(iterator)?;
+        Ok(wrapper.persistent_owned(iterator.property("next")?))
+    }
+
+    /// Get the getter of an own accessor property.
+    fn getter(
+        wrapper: &ContextWrapper,
+        object: &OwnedObjectRef,
+        name: &str,
+    ) -> Result<PersistentRef, ExecutionError> {
+        let context = wrapper.context;
+        let atom = Atom::new(context, name)?;
+        let mut descriptor = q::JSPropertyDescriptor {
+            flags: 0,
+            value: js_undefined_value(),
+            getter: js_undefined_value(),
+            setter: js_undefined_value(),
+        };
+        let found = unsafe {
+            q::JS_GetOwnProperty(context, &mut descriptor, object.value.value, atom.atom)
+        };
+        if found <= 0 {
+            return Err(ExecutionError::Internal(format!(
+                "Could not get the getter of '{}'",
+                name
+            )));
+        }
+        unsafe {
+            free_value(context, descriptor.value);
+            free_value(context, descriptor.setter);
+        }
+        Ok(wrapper.persistent_owned(OwnedValueRef::new(wrapper, descriptor.getter)))
+    }
+}
+
+/// Interrupt handler that stops Javascript execution after a callback
+/// panicked, see `ContextWrapper::set_resume_panics`.
+unsafe extern "C" fn interrupt_handler(_rt: *mut q::JSRuntime, opaque: *mut c_void) -> c_int {
+    let wrapper = &*(opaque as *const ContextWrapper);
+    wrapper
+        .panic
+        .try_lock()
+        .map(|panic| panic.is_some())
+        .unwrap_or(false) as c_int
 }
 
 impl Drop for ContextWrapper {
     fn drop(&mut self) {
+        self.release_persistent();
         unsafe {
             q::JS_FreeContext(self.context);
             q::JS_FreeRuntime(self.runtime);
@@ -803,8 +1913,25 @@ impl Drop for ContextWrapper {
 
 impl ContextWrapper {
     /// Initialize a wrapper by creating a JSRuntime and JSContext.
-    pub fn new(memory_limit: Option<usize>) -> Result<Self, ContextError> {
+    ///
+    /// The wrapper is boxed because its address is stored as the context
+    /// opaque, which allows retrieving it from callbacks.
+    pub fn new(memory_limit: Option<usize>) -> Result<Box<Self>, ContextError> {
+        // QuickJS records the current stack position as the top of the stack
+        // when creating the runtime, and reports a stack overflow if it is
+        // later used from a shallower frame (unless the stack-overflow-signed
+        // patch is applied with the `patched` feature).
+        // The runtime is therefore created before anything else, and the
+        // remaining setup happens in a separate function to keep this
+        // stack frame small.
         let runtime = unsafe { q::JS_NewRuntime() };
+        Self::with_runtime(runtime, memory_limit)
+    }
+
+    fn with_runtime(
+        runtime: *mut q::JSRuntime,
+        memory_limit: Option<usize>,
+    ) -> Result<Box<Self>, ContextError> {
         if runtime.is_null() {
             return Err(ContextError::RuntimeCreationFailed);
         }
@@ -826,15 +1953,73 @@ impl ContextWrapper {
 
         // Initialize the promise resolver helper code.
         // This code is needed by Self::resolve_value
-        let wrapper = Self {
+        let wrapper = Box::new(Self {
             runtime,
             context,
-            callbacks: Mutex::new(Vec::new()),
-        };
+            data: Mutex::new(HashMap::new()),
+            persistent: Mutex::new(Vec::new()),
+            resume_panics: false,
+            symbol_keys: SymbolKeys::default(),
+            deserialize_limits: DeserializeLimits::default(),
+            declarations: Mutex::new(Declarations::default()),
+            panic: Mutex::new(None),
+            exceptions: Mutex::new(Exceptions::default()),
+            released_exceptions: ReleasedExceptions::default(),
+            intrinsics: Mutex::new(None),
+        });
+        wrapper.init_context()?;
 
         Ok(wrapper)
     }
 
+    /// Get the wrapper of a context.
+    ///
+    /// Unsafe because the context must belong to a live `ContextWrapper`, and
+    /// the returned reference must not be used after it was dropped.
+    pub(crate) unsafe fn from_context<'a>(context: *mut q::JSContext) -> &'a Self {
+        &*(q::JS_GetContextOpaque(context) as *const Self)
+    }
+
+    /// Prepare a freshly created JSContext.
+    fn init_context(&self) -> Result<(), ContextError> {
+        unsafe {
+            q::JS_SetContextOpaque(self.context, self as *const Self as *mut c_void);
+        }
+        let intrinsics = Intrinsics::new(self).map_err(ContextError::Execution)?;
+        *self.intrinsics.lock().unwrap() = Some(intrinsics);
+        self.init_globals().map_err(ContextError::Execution)
+    }
+
+    /// Release the values of all persistent references.
+    fn release_persistent(&self) {
+        let persistent = std::mem::take(&mut *self.persistent.lock().unwrap());
+        for slot in persistent.iter().filter_map(Weak::upgrade) {
+            slot.release();
+        }
+    }
+
+    /// Register the builtin global functions provided by this crate.
+    fn init_globals(&self) -> Result<(), ExecutionError> {
+        let name = make_cstring("queueMicrotask")?;
+        let func = unsafe {
+            q::JS_NewCFunction2(
+                self.context,
+                Some(js_queue_microtask),
+                name.as_ptr(),
+                1,
+                q::JSCFunctionEnum_JS_CFUNC_generic,
+                0,
+            )
+        };
+        if func.tag != TAG_OBJECT {
+            return Err(ExecutionError::Internal(
+                "Could not create queueMicrotask function".into(),
+            ));
+        }
+        let global = self.global()?;
+        unsafe { global.set_property_raw("queueMicrotask", func) }
+    }
+
     // See console standard: https://console.spec.whatwg.org
     pub fn set_console(&self, backend: Box<dyn ConsoleBackend>) -> Result<(), ExecutionError> {
         use crate::console::Level;
@@ -889,12 +2074,57 @@ impl ContextWrapper {
         Ok(())
     }
 
+    /// Resume callback panics once Javascript execution returns, instead of
+    /// converting them to exceptions.
+    ///
+    /// Javascript execution is interrupted after a panic, so the exception
+    /// can not be caught for long.
+    pub fn set_resume_panics(&mut self) {
+        self.resume_panics = true;
+        let opaque = self as *mut Self as *mut c_void;
+        unsafe { q::JS_SetInterruptHandler(self.runtime, Some(interrupt_handler), opaque) };
+    }
+
+    /// Handle the payload of a panic that was caught in a callback.
+    ///
+    /// The payload is stored to be resumed later if panics are resumed.
+    pub(crate) fn caught_panic(
+        context: *mut q::JSContext,
+        payload: Box<dyn Any + Send>,
+    ) -> ExecutionError {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>")
+            .to_string();
+
+        let wrapper = unsafe { Self::from_context(context) };
+        if wrapper.resume_panics {
+            let mut panic = wrapper.panic.lock().unwrap();
+            // Only the first panic is resumed.
+            if panic.is_none() {
+                *panic = Some(payload);
+            }
+        }
+        ExecutionError::Internal(format!("Callback panicked: {}", message))
+    }
+
+    /// Resume unwinding of a panic that was caught in a callback.
+    fn resume_panic(&self) {
+        // The lock must be released before unwinding.
+        let payload = self.panic.lock().unwrap().take();
+        if let Some(payload) = payload {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
     /// Reset the wrapper by creating a new context.
-    pub fn reset(self) -> Result<Self, ContextError> {
+    pub fn reset(self: Box<Self>) -> Result<Box<Self>, ContextError> {
+        self.release_persistent();
         unsafe {
             q::JS_FreeContext(self.context);
         };
-        self.callbacks.lock().unwrap().clear();
         let context = unsafe { q::JS_NewContext(self.runtime) };
         if context.is_null() {
             return Err(ContextError::ContextCreationFailed);
@@ -902,6 +2132,9 @@ impl ContextWrapper {
 
         let mut s = self;
         s.context = context;
+        s.exceptions.lock().unwrap().values.clear();
+        *s.declarations.lock().unwrap() = Declarations::default();
+        s.init_context()?;
         Ok(s)
     }
 
@@ -910,11 +2143,74 @@ impl ContextWrapper {
         Ok(OwnedValueRef::new(self, serialized))
     }
 
+    /// Check if a value is `null` or `undefined`.
+    pub fn is_nullish(&self, value: &q::JSValue) -> bool {
+        value.tag == TAG_NULL || value.tag == TAG_UNDEFINED
+    }
+
+    /// Check if a value is an object.
+    pub fn is_object(&self, value: &q::JSValue) -> bool {
+        value.tag == TAG_OBJECT
+    }
+
+    /// Check if a value is a function.
+    pub fn is_function(&self, value: &q::JSValue) -> bool {
+        unsafe { q::JS_IsFunction(self.context, *value) != 0 }
+    }
+
+    /// Create a persistent reference to a value.
+    pub fn persistent(&self, value: &q::JSValue) -> PersistentRef {
+        let value = unsafe { dup_value(*value) };
+        PersistentRef::new(self, value)
+    }
+
+    /// Create a persistent reference that takes ownership of a value.
+    pub fn persistent_owned(&self, value: OwnedValueRef<'_>) -> PersistentRef {
+        PersistentRef::new(self, value.into_inner())
+    }
+
+    /// Serialize a Rust value with serde, see `Context::to_js`.
+    #[cfg(feature = "serde")]
+    pub fn to_js<T: serde::Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<PersistentRef, ValueError> {
+        let value = crate::serde_js::to_js(self.context, value)?;
+        Ok(PersistentRef::new(self, value))
+    }
+
+    /// Set a global variable to a referenced value.
+    pub fn set_global(&self, name: &str, value: &PersistentRef) -> Result<(), ExecutionError> {
+        let (wrapper, raw) = value.get()?;
+        if wrapper.context != self.context {
+            return Err(ExecutionError::Internal(
+                "Value belongs to a different context".into(),
+            ));
+        }
+        let global = self.global()?;
+        unsafe { global.set_property_raw(name, dup_value(raw)) }
+    }
+
     // Deserialize a quickjs runtime value into a Rust value.
-    fn to_value(&self, value: &q::JSValue) -> Result<JsValue, ValueError> {
+    pub(crate) fn to_value(&self, value: &q::JSValue) -> Result<JsValue, ValueError> {
         deserialize_value(self.context, value)
     }
 
+    /// Serialize a Rust value into an owned quickjs runtime value.
+    pub(crate) fn to_raw(&self, value: JsValue) -> Result<q::JSValue, ValueError> {
+        serialize_value(self.context, value)
+    }
+
+    /// Read a string value.
+    pub(crate) fn to_js_string(&self, value: &q::JSValue) -> Result<JsString, ValueError> {
+        deserialize_string(self.context, value)
+    }
+
+    /// Create an owned string value from WTF-8 bytes.
+    pub(crate) fn new_string(&self, bytes: &[u8]) -> Result<q::JSValue, ValueError> {
+        new_string(self.context, bytes)
+    }
+
     /// Get the global object.
     pub fn global(&self) -> Result<OwnedObjectRef<'_>, ExecutionError> {
         let global_raw = unsafe { q::JS_GetGlobalObject(self.context) };
@@ -930,6 +2226,9 @@ impl ContextWrapper {
 
         if value.is_null() {
             None
+        } else if let Some(err) = unsafe { take_host_error(value.value) } {
+            // A callback error that was not caught by Javascript.
+            Some(ExecutionError::Host(err))
         } else {
             let err = if value.is_exception() {
                 ExecutionError::Internal("Could get exception from runtime".into())
@@ -939,7 +2238,9 @@ impl ContextWrapper {
                         if strval.contains("out of memory") {
                             ExecutionError::OutOfMemory
                         } else {
-                            ExecutionError::Exception(JsValue::String(strval))
+                            ExecutionError::Exception(
+                                self.thrown_exception(JsValue::String(strval), &value.value),
+                            )
                         }
                     }
                     Err(_) => ExecutionError::Internal("Unknown exception".into()),
@@ -955,6 +2256,7 @@ impl ContextWrapper {
         &'a self,
         value: OwnedValueRef<'a>,
     ) -> Result<OwnedValueRef<'a>, ExecutionError> {
+        self.resume_panic();
         if value.is_exception() {
             let err = self
                 .get_exception()
@@ -994,17 +2296,7 @@ impl ContextWrapper {
                 self.call_function(resolver, vec![obj.into_value()])?;
 
                 loop {
-                    let flag = unsafe {
-                        let wrapper_mut = self as *const Self as *mut Self;
-                        let ctx_mut = &mut (*wrapper_mut).context;
-                        q::JS_ExecutePendingJob(self.runtime, ctx_mut)
-                    };
-                    if flag < 0 {
-                        let e = self.get_exception().unwrap_or_else(|| {
-                            ExecutionError::Exception("Unknown exception".into())
-                        });
-                        return Err(e);
-                    }
+                    self.execute_pending_job()?;
 
                     // Check if promise is finished.
                     let res_val = global.property("__promiseResult")?;
@@ -1012,32 +2304,90 @@ impl ContextWrapper {
                         let ok = res_val.to_bool()?;
                         let value = global.property("__promiseValue")?;
 
-                        if ok {
-                            return self.resolve_value(value);
-                        } else {
-                            let err_msg = value.to_string()?;
-                            return Err(ExecutionError::Exception(JsValue::String(err_msg)));
-                        }
-                    }
-                }
-            } else {
-                Ok(obj.into_value())
+                        if ok {
+                            return self.resolve_value(value);
+                        } else {
+                            let err_msg = value.to_string()?;
+                            return Err(ExecutionError::Exception(
+                                self.thrown_exception(JsValue::String(err_msg), &value.value),
+                            ));
+                        }
+                    }
+                }
+            } else {
+                Ok(obj.into_value())
+            }
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Execute a single job from the job queue.
+    ///
+    /// Returns `Ok(false)` if the job queue was empty.
+    fn execute_pending_job(&self) -> Result<bool, ExecutionError> {
+        let mut context = self.context;
+        let flag = unsafe { q::JS_ExecutePendingJob(self.runtime, &mut context) };
+        self.resume_panic();
+        if flag < 0 {
+            let e = self
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Unknown exception".into()));
+            Err(e)
+        } else {
+            Ok(flag > 0)
+        }
+    }
+
+    /// Check if the job queue contains pending jobs.
+    pub fn has_pending_jobs(&self) -> bool {
+        unsafe { q::JS_IsJobPending(self.runtime) > 0 }
+    }
+
+    /// Execute pending jobs until the queue is empty or `limit` jobs were
+    /// executed.
+    ///
+    /// Returns the number of executed jobs.
+    pub fn run_pending_jobs(&self, limit: Option<usize>) -> Result<usize, ExecutionError> {
+        let mut count = 0;
+        while Some(count) != limit && self.execute_pending_job()? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Add a Rust closure to the job queue.
+    pub fn enqueue_job<F>(&self, job: F) -> Result<(), ExecutionError>
+    where
+        F: FnOnce() + 'static,
+    {
+        // Callbacks must be `Fn`, so the job is moved out on the first call.
+        let job = Mutex::new(Some(job));
+        let func = self.create_callback(move |_args: Arguments| {
+            if let Some(job) = job.lock().unwrap().take() {
+                job();
             }
-        } else {
-            Ok(value)
+        })?;
+        let mut func = OwnedValueRef::new(self, func);
+
+        // JS_EnqueueJob takes its own reference to the arguments.
+        let ret = unsafe { q::JS_EnqueueJob(self.context, Some(js_call_job), 1, &mut func.value) };
+        if ret < 0 {
+            return Err(ExecutionError::Internal("Could not enqueue job".into()));
         }
+        Ok(())
     }
 
     /// Evaluate javascript code.
     pub fn eval<'a>(&'a self, code: &str) -> Result<OwnedValueRef<'a>, ExecutionError> {
         let filename = "script.js";
         let filename_c = make_cstring(filename)?;
-        let code_c = make_cstring(code)?;
+        let code_c = make_source(code);
 
         let value_raw = unsafe {
             q::JS_Eval(
                 self.context,
-                code_c.as_ptr(),
+                code_c.as_ptr() as *const c_char,
                 code.len() as _,
                 filename_c.as_ptr(),
                 q::JS_EVAL_TYPE_GLOBAL as i32,
@@ -1047,6 +2397,57 @@ impl ContextWrapper {
         self.resolve_value(value)
     }
 
+    /// Parse a JSON string with the quickjs parser.
+    pub fn parse_json(&self, json: &str) -> Result<OwnedValueRef<'_>, ExecutionError> {
+        let filename_c = make_cstring("input.json")?;
+        let json_c = make_source(json);
+
+        let value_raw = unsafe {
+            q::JS_ParseJSON(
+                self.context,
+                json_c.as_ptr() as *const c_char,
+                json.len() as _,
+                filename_c.as_ptr(),
+            )
+        };
+        let value = OwnedValueRef::new(self, value_raw);
+        if value.is_exception() {
+            let err = self
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Unknown exception".into()));
+            return Err(err);
+        }
+        Ok(value)
+    }
+
+    /// Convert a value to a JSON string, like `JSON.stringify`.
+    ///
+    /// `indent` is the number of spaces to indent with, at most 10.
+    pub fn json_stringify(
+        &self,
+        value: &q::JSValue,
+        indent: Option<usize>,
+    ) -> Result<String, ExecutionError> {
+        let space = match indent {
+            Some(indent) => self.serialize_value(JsValue::Int(indent.min(10) as i32))?,
+            None => OwnedValueRef::new(self, js_undefined_value()),
+        };
+        let raw =
+            unsafe { q::JS_JSONStringify(self.context, *value, js_undefined_value(), space.value) };
+        let json = OwnedValueRef::new(self, raw);
+        if json.is_exception() {
+            let err = self
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Unknown exception".into()));
+            return Err(err);
+        }
+        match json.to_value()? {
+            JsValue::String(json) => Ok(json),
+            // `undefined`, functions and symbols have no JSON representation.
+            _ => Err(ValueError::UnexpectedType.into()),
+        }
+    }
+
     /*
     /// Call a constructor function.
     fn call_constructor<'a>(
@@ -1097,91 +2498,615 @@ impl ContextWrapper {
         self.resolve_value(qres)
     }
 
+    /// Call a global function with the given arguments.
+    pub fn call_global_function(
+        &self,
+        function_name: &str,
+        args: Vec<JsValue>,
+    ) -> Result<JsValue, ExecutionError> {
+        let qargs = args
+            .into_iter()
+            .map(|arg| self.serialize_value(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let global = self.global()?;
+        let func_obj = global.property(function_name)?;
+
+        if !func_obj.is_object() {
+            return Err(ExecutionError::Internal(format!(
+                "Could not find function '{}' in global scope: does not exist, or not an object",
+                function_name
+            )));
+        }
+
+        let value = self.call_function(func_obj, qargs)?.to_value()?;
+        Ok(value)
+    }
+
     /// Helper for executing a callback closure.
-    fn exec_callback<F>(
+    pub(crate) fn exec_callback<R>(
+        context: *mut q::JSContext,
+        this: q::JSValue,
+        argc: c_int,
+        argv: *mut q::JSValue,
+        callback: impl FnOnce(&CallContext) -> Result<Result<R, ExecutionError>, ValueError>,
+    ) -> Result<R, ExecutionError> {
+        // Callbacks are required to be RefUnwindSafe.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            // argv may be a null pointer if no arguments were passed.
+            let arg_slice = if argc > 0 {
+                unsafe { std::slice::from_raw_parts(argv, argc as usize) }
+            } else {
+                &[]
+            };
+
+            let wrapper = unsafe { Self::from_context(context) };
+            let call_context = CallContext::new(wrapper, this, arg_slice);
+
+            match callback(&call_context) {
+                Ok(Ok(result)) => Ok(result),
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(e.into()),
+            }
+        }));
+
+        match result {
+            Ok(r) => r,
+            Err(payload) => Err(Self::caught_panic(context, payload)),
+        }
+    }
+
+    /// Helper for executing a constructor closure.
+    ///
+    /// Creates the new object and runs the callback with the object as `this`.
+    /// If the callback returns an object, its properties are assigned to the
+    /// new object.
+    fn exec_constructor<F>(
         context: *mut q::JSContext,
+        new_target: q::JSValue,
         argc: c_int,
         argv: *mut q::JSValue,
         callback: &impl Callback<F>,
     ) -> Result<q::JSValue, ExecutionError> {
-        let result = std::panic::catch_unwind(|| {
-            let arg_slice = unsafe { std::slice::from_raw_parts(argv, argc as usize) };
-
-            let args = arg_slice
-                .iter()
-                .map(|raw| deserialize_value(context, raw))
-                .collect::<Result<Vec<_>, _>>()?;
-
-            match callback.call(args) {
-                Ok(Ok(result)) => {
-                    let serialized = serialize_value(context, result)?;
-                    Ok(serialized)
+        let wrapper = unsafe { Self::from_context(context) };
+        let this = OwnedObjectRef::new(wrapper.new_instance(new_target, q::JS_CLASS_OBJECT)?)?;
+
+        let value = Self::exec_callback(context, this.value.value, argc, argv, |ctx| {
+            callback.call(ctx)
+        })?;
+        if let JsValue::Object(properties) = value {
+            for (key, value) in properties {
+                this.set_property(&key, value)?;
+            }
+        }
+
+        Ok(this.into_value().into_inner())
+    }
+
+    /// Create a new object of the given class for a constructor call.
+    ///
+    /// The prototype is taken from `new_target`, so that subclasses work.
+    pub(crate) fn new_instance(
+        &self,
+        new_target: q::JSValue,
+        class_id: q::JSClassID,
+    ) -> Result<OwnedValueRef<'_>, ExecutionError> {
+        let name = make_cstring("prototype")?;
+        let proto = unsafe { q::JS_GetPropertyStr(self.context, new_target, name.as_ptr()) };
+        let mut proto = OwnedValueRef::new(self, proto);
+
+        if proto.is_exception() {
+            return Err(self
+                .get_exception()
+                .unwrap_or_else(|| ExecutionError::Exception("Unknown exception".into())));
+        } else if !proto.is_object() {
+            proto =
+                OwnedValueRef::new(self, unsafe { q::JS_GetClassProto(self.context, class_id) });
+        }
+
+        let raw = unsafe { q::JS_NewObjectProtoClass(self.context, proto.value, class_id) };
+        let value = OwnedValueRef::new(self, raw);
+        if !value.is_object() {
+            return Err(ExecutionError::Internal("Could not create object".into()));
+        }
+        Ok(value)
+    }
+
+    /// Raise the given error as a JS exception.
+    ///
+    /// Returns the exception value that must be returned from the C function.
+    pub(crate) fn throw(context: *mut q::JSContext, error: ExecutionError) -> q::JSValue {
+        let js_exception = match error {
+            ExecutionError::Exception(e) => {
+                let wrapper = unsafe { Self::from_context(context) };
+                match wrapper.take_exception(&e) {
+                    Some(original) => Ok(original),
+                    None => serialize_value(context, e.into_value()),
+                }
+            }
+            ExecutionError::Host(e) => match e.downcast::<ExecutionError>() {
+                // An error of a nested call, forwarded with `?`.
+                Ok(e) => return Self::throw(context, *e),
+                Err(e) => match e.downcast::<JsThrow>() {
+                    Ok(e) => return unsafe { e.throw(context) },
+                    Err(e) => unsafe { new_host_error(context, e) }
+                        .or_else(|e| serialize_value(context, e.to_string().into())),
+                },
+            },
+            other => serialize_value(context, other.to_string().into()),
+        };
+        match js_exception {
+            Ok(js_exception) => unsafe {
+                q::JS_Throw(context, js_exception);
+            },
+            // This is reached from extern "C" callbacks, so it must not
+            // panic if the exception can not be created, for example when
+            // running out of memory.
+            Err(e) => {
+                let message = make_cstring(e.to_string()).unwrap_or_default();
+                unsafe {
+                    q::JS_ThrowInternalError(
+                        context,
+                        b"%s\0".as_ptr() as *const c_char,
+                        message.as_ptr(),
+                    );
+                }
+            }
+        }
+
+        q::JSValue {
+            u: q::JSValueUnion { int32: 0 },
+            tag: TAG_EXCEPTION,
+        }
+    }
+
+    /// Get a builtin function, see `Intrinsics`.
+    ///
+    /// The value is borrowed, it stays alive as long as the context.
+    fn intrinsic(
+        &self,
+        get: impl FnOnce(&Intrinsics) -> &PersistentRef,
+    ) -> Result<q::JSValue, ValueError> {
+        let intrinsics = self.intrinsics.lock().unwrap();
+        let intrinsics = intrinsics
+            .as_ref()
+            .ok_or_else(|| ValueError::Internal("Context is not initialized".into()))?;
+        get(intrinsics)
+            .get()
+            .map(|(_, value)| value)
+            .map_err(|e| ValueError::Internal(e.to_string()))
+    }
+
+    /// Create an exception that keeps the original thrown value.
+    fn thrown_exception(&self, converted: JsValue, original: &q::JSValue) -> JsException {
+        let mut exceptions = self.exceptions.lock().unwrap();
+        exceptions.release(&self.released_exceptions);
+        let id = exceptions.next_id;
+        exceptions.next_id += 1;
+        exceptions.values.insert(id, self.persistent(original));
+        JsException::thrown(
+            converted,
+            ExceptionHandle::new(id, &self.released_exceptions),
+        )
+    }
+
+    /// Take the original value of an exception that was thrown in this
+    /// context.
+    fn take_exception(&self, exception: &JsException) -> Option<q::JSValue> {
+        let handle = exception.handle()?;
+        if !handle.belongs_to(&self.released_exceptions) {
+            return None;
+        }
+        let mut exceptions = self.exceptions.lock().unwrap();
+        exceptions.release(&self.released_exceptions);
+        let original = exceptions.values.remove(&handle.id)?;
+        let (_, raw) = original.get().ok()?;
+        Some(unsafe { dup_value(raw) })
+    }
+
+    /// Create a JS function object that is backed by a wrapped closure.
+    fn new_function(
+        &self,
+        name: &str,
+        argument_count: usize,
+        constructor: bool,
+        call: Box<WrappedCallback>,
+    ) -> Result<q::JSValue, ExecutionError> {
+        let class_id = class::class_id::<RustFunction>();
+        unsafe {
+            if q::JS_IsRegisteredClass(self.runtime, class_id) == 0 {
+                let def = q::JSClassDef {
+                    class_name: b"Function\0".as_ptr() as *const c_char,
+                    finalizer: Some(rust_function_finalize),
+                    gc_mark: None,
+                    call: Some(rust_function_call),
+                    exotic: std::ptr::null_mut(),
+                };
+                if q::JS_NewClass(self.runtime, class_id, &def) < 0 {
+                    return Err(ExecutionError::Internal(
+                        "Could not register function class".into(),
+                    ));
                 }
-                // TODO: better error reporting.
-                Ok(Err(e)) => Err(ExecutionError::Exception(JsValue::String(e))),
-                Err(e) => Err(e.into()),
             }
+        }
+
+        let proto = OwnedValueRef::new(self, unsafe {
+            q::JS_GetClassProto(self.context, q::JS_CLASS_BYTECODE_FUNCTION)
         });
+        let func = OwnedValueRef::new(self, unsafe {
+            q::JS_NewObjectProtoClass(self.context, proto.value, class_id)
+        });
+        if !func.is_object() {
+            return Err(ExecutionError::Internal("Could not create function".into()));
+        }
 
-        match result {
-            Ok(r) => r,
-            Err(_e) => Err(ExecutionError::Internal("Callback panicked!".to_string())),
+        let function = Box::new(RustFunction { call, constructor });
+        unsafe {
+            q::JS_SetOpaque(func.value, Box::into_raw(function) as *mut c_void);
+        }
+
+        let length = serialize_value(self.context, JsValue::Int(argument_count as i32))?;
+        let name = serialize_value(self.context, JsValue::String(name.to_string()))?;
+        unsafe {
+            let flags = q::JS_PROP_CONFIGURABLE as c_int;
+            q::JS_DefinePropertyValueStr(
+                self.context,
+                func.value,
+                b"length\0".as_ptr() as *const c_char,
+                length,
+                flags,
+            );
+            q::JS_DefinePropertyValueStr(
+                self.context,
+                func.value,
+                b"name\0".as_ptr() as *const c_char,
+                name,
+                flags,
+            );
+            if constructor {
+                q::JS_SetConstructorBit(self.context, func.value, 1);
+            }
         }
+
+        Ok(func.into_inner())
+    }
+
+    /// Create a JS function that runs the given closure.
+    ///
+    /// The closure receives the call context, with `this` and the arguments
+    /// of the call, and returns an owned value.
+    pub(crate) fn create_raw_callback(
+        &self,
+        argument_count: usize,
+        callback: impl Fn(&CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError>
+            + 'static,
+    ) -> Result<q::JSValue, ExecutionError> {
+        let context = self.context;
+        let wrapper = move |this: q::JSValue, argc: c_int, argv: *mut q::JSValue| -> q::JSValue {
+            match Self::exec_callback(context, this, argc, argv, &callback) {
+                Ok(value) => value,
+                // TODO: better error reporting.
+                Err(e) => Self::throw(context, e),
+            }
+        };
+
+        self.new_function("", argument_count, false, Box::new(wrapper))
     }
 
     /// Add a global JS function that is backed by a Rust function or closure.
-    pub fn create_callback<'a, F>(
-        &'a self,
+    pub fn create_callback<F>(
+        &self,
         callback: impl Callback<F> + 'static,
     ) -> Result<q::JSValue, ExecutionError> {
-        let argcount = callback.argument_count() as i32;
+        self.create_raw_callback(callback.argument_count(), move |ctx| callback.call_raw(ctx))
+    }
+
+    /// Add a global JS function that is backed by a Rust `FnMut` function or
+    /// closure.
+    pub fn create_callback_mut<F>(
+        &self,
+        callback: impl CallbackMut<F> + 'static,
+    ) -> Result<q::JSValue, ExecutionError> {
+        let argument_count = callback.argument_count();
+        let callback = RefCell::new(callback);
+        self.create_raw_callback(argument_count, move |ctx| {
+            match callback.try_borrow_mut() {
+                Ok(mut callback) => callback.call_mut_raw(ctx),
+                // The callback was called recursively.
+                Err(_) => Ok(Err(ExecutionError::Exception(
+                    "Callback is already running".into(),
+                ))),
+            }
+        })
+    }
 
+    /// Create a JS constructor function that runs the given closure.
+    ///
+    /// The closure receives the `new.target` of the call and the arguments,
+    /// and must return the new object.
+    /// The `prototype` of the constructor is set to `proto`.
+    pub(crate) fn create_raw_constructor(
+        &self,
+        name: &str,
+        argument_count: usize,
+        proto: q::JSValue,
+        constructor: impl Fn(q::JSValue, c_int, *mut q::JSValue) -> Result<q::JSValue, ExecutionError>
+            + 'static,
+    ) -> Result<q::JSValue, ExecutionError> {
         let context = self.context;
-        let wrapper = move |argc: c_int, argv: *mut q::JSValue| -> q::JSValue {
-            match Self::exec_callback(context, argc, argv, &callback) {
-                Ok(value) => value,
-                // TODO: better error reporting.
+        let wrapper =
+            move |new_target: q::JSValue, argc: c_int, argv: *mut q::JSValue| -> q::JSValue {
+                match constructor(new_target, argc, argv) {
+                    Ok(value) => value,
+                    Err(e) => Self::throw(context, e),
+                }
+            };
+
+        let cfunc = self.new_function(name, argument_count, true, Box::new(wrapper))?;
+        unsafe { q::JS_SetConstructor(self.context, cfunc, proto) };
+
+        Ok(cfunc)
+    }
+
+    /// Create a JS constructor function that is backed by a Rust function or
+    /// closure.
+    pub fn create_constructor<F>(
+        &self,
+        name: &str,
+        callback: impl Callback<F> + 'static,
+    ) -> Result<q::JSValue, ExecutionError> {
+        // Rust functions have no prototype object by default.
+        let proto = OwnedValueRef::new(self, unsafe { q::JS_NewObject(self.context) });
+        if !proto.is_object() {
+            return Err(ExecutionError::Internal(
+                "Could not create constructor prototype".into(),
+            ));
+        }
+
+        let context = self.context;
+        self.create_raw_constructor(
+            name,
+            callback.argument_count(),
+            proto.value,
+            move |new_target, argc, argv| {
+                Self::exec_constructor(context, new_target, argc, argv, &callback)
+            },
+        )
+    }
+
+    pub fn add_callback<F>(
+        &self,
+        name: &str,
+        callback: impl Callback<F> + 'static,
+    ) -> Result<(), ExecutionError> {
+        let signature = callback.signature();
+        let cfunc = self.create_callback(callback)?;
+        let global = self.global()?;
+        unsafe {
+            global.set_property_raw(name, cfunc)?;
+        }
+        self.declare(|d| d.add_function(name, signature));
+        Ok(())
+    }
+
+    pub fn add_callback_mut<F>(
+        &self,
+        name: &str,
+        callback: impl CallbackMut<F> + 'static,
+    ) -> Result<(), ExecutionError> {
+        let signature = callback.signature();
+        let cfunc = self.create_callback_mut(callback)?;
+        let global = self.global()?;
+        unsafe {
+            global.set_property_raw(name, cfunc)?;
+        }
+        self.declare(|d| d.add_function(name, signature));
+        Ok(())
+    }
+
+    /// Store user data of type `T`, replacing previous data of the same type.
+    pub fn set_data<T: 'static>(&self, value: T) {
+        self.data
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Rc::new(RefCell::new(value)));
+    }
+
+    /// Get the user data of type `T`.
+    pub fn data<T: 'static>(&self) -> Option<Rc<RefCell<T>>> {
+        let data = self.data.lock().unwrap().get(&TypeId::of::<T>())?.clone();
+        data.downcast::<RefCell<T>>().ok()
+    }
+
+    /// Remove a global callback.
+    ///
+    /// The closure is dropped once Javascript no longer references the
+    /// function.
+    pub fn remove_callback(&self, name: &str) -> Result<(), ExecutionError> {
+        let global = self.global()?;
+        let function = global.property(name)?;
+        let is_callback = unsafe {
+            !q::JS_GetOpaque(function.value, class::class_id::<RustFunction>()).is_null()
+        };
+        if !is_callback {
+            return Err(ExecutionError::Internal(format!(
+                "Global '{}' is not a callback",
+                name
+            )));
+        }
+        drop(function);
+
+        let cname = make_cstring(name)?;
+        unsafe {
+            let atom = q::JS_NewAtom(self.context, cname.as_ptr());
+            let ret = q::JS_DeleteProperty(self.context, global.value.value, atom, 0);
+            q::JS_FreeAtom(self.context, atom);
+            if ret < 0 {
+                return Err(self.get_exception().unwrap_or_else(|| {
+                    ExecutionError::Internal("Could not remove callback".into())
+                }));
+            }
+        }
+        self.declare(|d| d.remove(name));
+        Ok(())
+    }
+
+    pub fn add_constructor<F>(
+        &self,
+        name: &str,
+        callback: impl Callback<F> + 'static,
+    ) -> Result<(), ExecutionError> {
+        let signature = callback.signature();
+        let cfunc = self.create_constructor(name, callback)?;
+        let global = self.global()?;
+        unsafe {
+            global.set_property_raw(name, cfunc)?;
+        }
+        self.declare(|d| d.add_constructor(name, signature));
+        Ok(())
+    }
+
+    /// Register a Rust type as a JS class and add its constructor to the
+    /// global object.
+    pub fn add_class<T: JsClass>(&self) -> Result<(), ExecutionError> {
+        let class_id = class::class_id::<T>();
+        let class_name = make_cstring(T::NAME)?;
+
+        // Classes are registered with the runtime, so they survive a reset.
+        if unsafe { q::JS_IsRegisteredClass(self.runtime, class_id) } == 0 {
+            let def = q::JSClassDef {
+                class_name: class_name.as_ptr(),
+                finalizer: Some(class::finalize::<T>),
+                gc_mark: None,
+                call: None,
+                exotic: std::ptr::null_mut(),
+            };
+            if unsafe { q::JS_NewClass(self.runtime, class_id, &def) } < 0 {
+                return Err(ExecutionError::Internal("Could not register class".into()));
+            }
+        }
+
+        let mut builder = ClassBuilder::<T>::new();
+        T::define(&mut builder);
+        let declaration = std::mem::take(&mut builder.declaration);
+
+        let proto = OwnedValueRef::new(self, unsafe { q::JS_NewObject(self.context) });
+        let proto = OwnedObjectRef::new(proto)?;
+
+        // Unwrap the Rust value of `this` for methods and accessors.
+        let with_this = |method: Box<class::MethodFn<T>>| {
+            move |ctx: &CallContext| match unsafe { class::instance_value::<T>(ctx.raw_this()) } {
+                Some(this) => method(this, ctx),
+                None => Ok(Err(ExecutionError::Exception(
+                    format!("Expected this to be a {}", T::NAME).into(),
+                ))),
+            }
+        };
+
+        for (name, argument_count, method) in builder.methods {
+            let func = self.create_raw_callback(argument_count, with_this(method))?;
+            let name = make_cstring(name)?;
+            // Methods are not enumerable, like in Javascript classes.
+            let flags = (q::JS_PROP_CONFIGURABLE | q::JS_PROP_WRITABLE) as i32;
+            unsafe {
+                q::JS_DefinePropertyValueStr(
+                    self.context,
+                    proto.value.value,
+                    name.as_ptr(),
+                    func,
+                    flags,
+                );
+            }
+        }
+
+        for (name, getter, setter) in builder.properties {
+            let getter = self.create_raw_callback(0, with_this(getter))?;
+            let setter = match setter {
+                Some(setter) => match self.create_raw_callback(1, with_this(setter)) {
+                    Ok(setter) => setter,
+                    Err(e) => {
+                        unsafe { free_value(self.context, getter) };
+                        return Err(e);
+                    }
+                },
+                None => js_undefined_value(),
+            };
+            let name = match make_cstring(name) {
+                Ok(name) => name,
                 Err(e) => {
-                    let js_exception_value = match e {
-                        ExecutionError::Exception(e) => e,
-                        other => other.to_string().into(),
-                    };
-                    let js_exception = serialize_value(context, js_exception_value).unwrap();
                     unsafe {
-                        q::JS_Throw(context, js_exception);
-                    }
-
-                    q::JSValue {
-                        u: q::JSValueUnion { int32: 0 },
-                        tag: TAG_EXCEPTION,
+                        free_value(self.context, getter);
+                        free_value(self.context, setter);
                     }
+                    return Err(e.into());
                 }
+            };
+            unsafe {
+                let atom = q::JS_NewAtom(self.context, name.as_ptr());
+                q::JS_DefinePropertyGetSet(
+                    self.context,
+                    proto.value.value,
+                    atom,
+                    getter,
+                    setter,
+                    q::JS_PROP_CONFIGURABLE as i32,
+                );
+                q::JS_FreeAtom(self.context, atom);
             }
+        }
+
+        unsafe {
+            q::JS_SetClassProto(self.context, class_id, dup_value(proto.value.value));
+        }
+
+        let context = self.context;
+        let (argument_count, constructor) = match builder.constructor {
+            Some((argument_count, constructor)) => (argument_count, Some(constructor)),
+            None => (0, None),
         };
+        let cfunc = self.create_raw_constructor(
+            T::NAME,
+            argument_count,
+            proto.value.value,
+            move |new_target, argc, argv| {
+                let constructor = constructor.as_ref().ok_or_else(|| {
+                    ExecutionError::Exception(format!("{} can not be constructed", T::NAME).into())
+                })?;
+                let value =
+                    Self::exec_callback(context, js_undefined_value(), argc, argv, constructor)?;
 
-        let (pair, trampoline) = unsafe { build_closure_trampoline(wrapper) };
-        let data = (&*pair.1) as *const q::JSValue as *mut q::JSValue;
-        self.callbacks.lock().unwrap().push(pair);
+                let wrapper = unsafe { Self::from_context(context) };
+                let instance = wrapper.new_instance(new_target, class_id)?;
+                unsafe { class::set_instance_value(instance.value, value) };
+                Ok(instance.into_inner())
+            },
+        )?;
 
-        let cfunc =
-            unsafe { q::JS_NewCFunctionData(self.context, trampoline, argcount, 0, 1, data) };
-        if cfunc.tag != TAG_OBJECT {
-            return Err(ExecutionError::Internal("Could not create callback".into()));
+        let global = self.global()?;
+        unsafe {
+            global.set_property_raw(T::NAME, cfunc)?;
         }
-
-        Ok(cfunc)
+        self.declare(|d| d.add_class(T::NAME, declaration));
+        Ok(())
     }
 
-    pub fn add_callback<'a, F>(
-        &'a self,
+    /// Add a host object to the global object.
+    pub fn add_host_object(
+        &self,
         name: &str,
-        callback: impl Callback<F> + 'static,
+        object: impl HostObject,
     ) -> Result<(), ExecutionError> {
-        let cfunc = self.create_callback(callback)?;
+        let value = host::new_host_object(self.context, Box::new(object))?;
         let global = self.global()?;
         unsafe {
-            global.set_property_raw(name, cfunc)?;
+            global.set_property_raw(name, value)?;
         }
+        self.declare(|d| d.add_object(name));
         Ok(())
     }
+
+    /// Update the Typescript declarations.
+    pub fn declare(&self, f: impl FnOnce(&mut Declarations)) {
+        f(&mut self.declarations.lock().unwrap());
+    }
 }
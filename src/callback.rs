@@ -1,23 +1,892 @@
-use std::{convert::TryFrom, marker::PhantomData, panic::RefUnwindSafe};
+use std::{
+    cell::RefCell, collections::HashMap, convert::TryFrom, marker::PhantomData,
+    panic::RefUnwindSafe, rc::Rc, time::SystemTime,
+};
 
-use crate::value::{JsValue, ValueError};
+use libquickjs_sys as q;
+
+use crate::bindings::{
+    js_bool_value, js_float_value, js_int_value, js_null_value, ContextWrapper, PersistentRef,
+    TAG_BOOL, TAG_FLOAT64, TAG_INT, TAG_STRING,
+};
+use crate::class::{JsClass, JsInstance};
+use crate::typescript::{array_type, Signature};
+use crate::value::{JsDate, JsObjectMap, JsString, JsValue, TypedArray, ValueError};
+use crate::ExecutionError;
+
+/// An error returned by a callback.
+pub(crate) type HostError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Convert an error returned by a callback.
+///
+/// The error is kept as a host error, so it can be recovered if Javascript
+/// does not catch it. An `ExecutionError`, for example of a nested `eval`
+/// call, is raised unchanged.
+pub(crate) fn callback_error<E: Into<HostError>>(error: E) -> ExecutionError {
+    match error.into().downcast::<ExecutionError>() {
+        Ok(e) => *e,
+        Err(e) => ExecutionError::Host(e),
+    }
+}
+
+/// A type that can be taken as a callback argument.
+///
+/// Implemented for the types that implement `TryFrom<JsValue>` in this
+/// crate, and by [`#[derive(FromJs)]`](derive.FromJs.html). A type with a
+/// hand-written `TryFrom<JsValue>` implementation opts in with an empty
+/// implementation:
+///
+/// ```rust
+/// use quick_js::{Context, FromJsArg, JsValue, ValueError};
+/// use std::convert::TryFrom;
+///
+/// struct Even(i32);
+///
+/// impl TryFrom<JsValue> for Even {
+///     type Error = ValueError;
+///
+///     fn try_from(value: JsValue) -> Result<Self, ValueError> {
+///         match value {
+///             JsValue::Int(int) if int % 2 == 0 => Ok(Even(int)),
+///             _ => Err(ValueError::UnexpectedType),
+///         }
+///     }
+/// }
+///
+/// impl FromJsArg for Even {
+///     fn ts_type() -> String {
+///         "number".into()
+///     }
+/// }
+///
+/// let context = Context::new().unwrap();
+/// context.add_callback("half", |even: Even| even.0 / 2).unwrap();
+/// assert_eq!(context.eval_as::<i32>("half(4)").unwrap(), 2);
+/// ```
+///
+/// Such types are converted through a [JsValue](JsValue), and are declared
+/// as `any` in [Typescript declarations](crate::Context::typescript_declarations)
+/// unless they override `ts_type`. Primitives like `i32`, `f64`, `bool` and
+/// `String` are read from the Javascript value directly.
+pub trait FromJsArg: TryFrom<JsValue, Error = ValueError> {
+    /// Whether the argument may be omitted when it is in trailing position,
+    /// in which case it is converted from `undefined`. Set for `Option<T>`.
+    const OPTIONAL: bool = false;
+
+    /// The Typescript type.
+    fn ts_type() -> String {
+        "any".into()
+    }
+
+    /// Convert a callback argument.
+    #[doc(hidden)]
+    fn from_js_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        Self::try_from(context.wrapper.to_value(value)?)
+    }
+}
+
+/// A type that can be returned from a callback.
+///
+/// Implemented for the types that implement `Into<JsValue>` in this crate,
+/// and by [`#[derive(IntoJs)]`](derive.IntoJs.html). Like with
+/// [FromJsArg](FromJsArg), a type with a hand-written conversion opts in
+/// with an empty implementation, is converted through a [JsValue](JsValue)
+/// and is declared as `any` unless it overrides `ts_type`.
+pub trait IntoJsReturn: Into<JsValue> {
+    /// The Typescript type.
+    fn ts_type() -> String {
+        "any".into()
+    }
+
+    /// Convert a callback result to an owned Javascript value.
+    #[doc(hidden)]
+    fn into_js_return(self, context: &CallContext) -> Result<q::JSValue, ValueError> {
+        context.wrapper.to_raw(self.into())
+    }
+}
+
+impl FromJsArg for bool {
+    fn ts_type() -> String {
+        "boolean".into()
+    }
+
+    fn from_js_arg(_context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        match value.tag {
+            TAG_BOOL => Ok(unsafe { value.u.int32 } > 0),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+impl FromJsArg for i32 {
+    fn ts_type() -> String {
+        "number".into()
+    }
+
+    fn from_js_arg(_context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        match value.tag {
+            TAG_INT => Ok(unsafe { value.u.int32 }),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+impl FromJsArg for f64 {
+    fn ts_type() -> String {
+        "number".into()
+    }
+
+    fn from_js_arg(_context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        match value.tag {
+            TAG_FLOAT64 => Ok(unsafe { value.u.float64 }),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+impl FromJsArg for String {
+    fn ts_type() -> String {
+        "string".into()
+    }
+
+    fn from_js_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        match value.tag {
+            TAG_STRING => context
+                .wrapper
+                .to_js_string(value)?
+                .into_string()
+                .map_err(|_| ValueError::UnexpectedType),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+impl FromJsArg for JsString {
+    fn ts_type() -> String {
+        "string".into()
+    }
+
+    fn from_js_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        match value.tag {
+            TAG_STRING => context.wrapper.to_js_string(value),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+/// `undefined` and `null` convert to `None`.
+impl<T: FromJsArg> FromJsArg for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn ts_type() -> String {
+        format!("{} | null", T::ts_type())
+    }
+
+    fn from_js_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        if context.wrapper.is_nullish(value) {
+            Ok(None)
+        } else {
+            T::from_js_arg(context, value).map(Some)
+        }
+    }
+}
+
+impl<T: FromJsArg> FromJsArg for Vec<T> {
+    fn ts_type() -> String {
+        array_type(&T::ts_type())
+    }
+}
+
+impl<V: FromJsArg> FromJsArg for HashMap<String, V> {
+    fn ts_type() -> String {
+        format!("Record<string, {}>", V::ts_type())
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<V: FromJsArg> FromJsArg for indexmap::IndexMap<String, V> {
+    fn ts_type() -> String {
+        format!("Record<string, {}>", V::ts_type())
+    }
+}
+
+/// Types that are converted through a `JsValue`.
+macro_rules! impl_js_value_conversion {
+    ( $trait:ident { $( $( #[$attr:meta] )* $t:ty => $ts:expr, )* } ) => {
+        $(
+            $( #[$attr] )*
+            impl $trait for $t {
+                fn ts_type() -> String {
+                    $ts.into()
+                }
+            }
+        )*
+    };
+}
+
+// `JsValue` itself is not a `FromJsArg`, because its `TryFrom` can not fail.
+impl_js_value_conversion!(FromJsArg {
+    Vec<JsValue> => "any[]",
+    HashMap<String, JsValue> => "Record<string, any>",
+    #[cfg(feature = "preserve_order")]
+    indexmap::IndexMap<String, JsValue> => "Record<string, any>",
+    #[cfg(feature = "bigint")]
+    i64 => "number | bigint",
+    #[cfg(feature = "bigint")]
+    u64 => "number | bigint",
+    #[cfg(feature = "bigint")]
+    i128 => "number | bigint",
+    #[cfg(feature = "bigint")]
+    u128 => "number | bigint",
+    #[cfg(feature = "bigint")]
+    num_bigint::BigInt => "number | bigint",
+    JsObjectMap => "Record<string, any>",
+    TypedArray => "any",
+    JsDate => "Date",
+    SystemTime => "Date",
+    #[cfg(feature = "chrono")]
+    chrono::DateTime<chrono::Utc> => "Date",
+    #[cfg(feature = "chrono")]
+    chrono::DateTime<chrono::FixedOffset> => "Date",
+    #[cfg(feature = "chrono")]
+    chrono::NaiveDateTime => "Date",
+    #[cfg(feature = "time")]
+    time::OffsetDateTime => "Date",
+    #[cfg(feature = "time")]
+    time::PrimitiveDateTime => "Date",
+});
+
+/// `undefined` converts to `null`.
+#[cfg(feature = "serde_json")]
+impl FromJsArg for serde_json::Value {
+    const OPTIONAL: bool = true;
+}
+
+/// Types that are created without a `JsValue`.
+macro_rules! impl_into_js_return {
+    ( $( $t:ty => $ts:expr, |$value:ident, $context:ident| $expr:expr, )* ) => {
+        $(
+            impl IntoJsReturn for $t {
+                fn ts_type() -> String {
+                    $ts.into()
+                }
+
+                fn into_js_return(self, $context: &CallContext) -> Result<q::JSValue, ValueError> {
+                    let $value = self;
+                    $expr
+                }
+            }
+        )*
+    };
+}
+
+impl_into_js_return! {
+    bool => "boolean", |value, _context| Ok(js_bool_value(value)),
+    i8 => "number", |value, _context| Ok(js_int_value(value.into())),
+    i16 => "number", |value, _context| Ok(js_int_value(value.into())),
+    i32 => "number", |value, _context| Ok(js_int_value(value)),
+    u8 => "number", |value, _context| Ok(js_int_value(value.into())),
+    u16 => "number", |value, _context| Ok(js_int_value(value.into())),
+    u32 => "number", |value, _context| Ok(js_float_value(value.into())),
+    f64 => "number", |value, _context| Ok(js_float_value(value)),
+    String => "string", |value, context| context.wrapper.new_string(value.as_bytes()),
+    &str => "string", |value, context| context.wrapper.new_string(value.as_bytes()),
+    JsString => "string", |value, context| context.wrapper.new_string(value.as_wtf8()),
+}
+
+/// `None` is converted to `null`.
+impl<T: IntoJsReturn> IntoJsReturn for Option<T> {
+    fn ts_type() -> String {
+        format!("{} | null", T::ts_type())
+    }
+
+    fn into_js_return(self, context: &CallContext) -> Result<q::JSValue, ValueError> {
+        match self {
+            Some(value) => value.into_js_return(context),
+            None => Ok(js_null_value()),
+        }
+    }
+}
+
+impl<T: IntoJsReturn> IntoJsReturn for Vec<T> {
+    fn ts_type() -> String {
+        array_type(&T::ts_type())
+    }
+}
+
+impl<K: Into<String>, V: IntoJsReturn> IntoJsReturn for HashMap<K, V> {
+    fn ts_type() -> String {
+        format!("Record<string, {}>", V::ts_type())
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<K: Into<String>, V: IntoJsReturn> IntoJsReturn for indexmap::IndexMap<K, V> {
+    fn ts_type() -> String {
+        format!("Record<string, {}>", V::ts_type())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> IntoJsReturn for chrono::DateTime<Tz> {
+    fn ts_type() -> String {
+        "Date".into()
+    }
+}
+
+impl_js_value_conversion!(IntoJsReturn {
+    JsValue => "any",
+    #[cfg(feature = "bigint")]
+    i64 => "bigint",
+    #[cfg(feature = "bigint")]
+    u64 => "bigint",
+    #[cfg(feature = "bigint")]
+    i128 => "bigint",
+    #[cfg(feature = "bigint")]
+    u128 => "bigint",
+    #[cfg(feature = "bigint")]
+    num_bigint::BigInt => "bigint",
+    JsObjectMap => "Record<string, any>",
+    TypedArray => "any",
+    JsDate => "Date",
+    SystemTime => "Date",
+    #[cfg(feature = "chrono")]
+    chrono::NaiveDateTime => "Date",
+    #[cfg(feature = "time")]
+    time::OffsetDateTime => "Date",
+    #[cfg(feature = "time")]
+    time::PrimitiveDateTime => "Date",
+});
 
 pub trait IntoCallbackResult {
-    fn into_callback_res(self) -> Result<JsValue, String>;
+    fn into_callback_res(self) -> Result<JsValue, ExecutionError>;
+
+    /// Convert the result to an owned Javascript value.
+    fn into_callback_raw(self, context: &CallContext) -> Result<q::JSValue, ExecutionError>;
+
+    /// The Typescript type of the result.
+    fn ts_type() -> String {
+        "any".into()
+    }
 }
 
-impl<T: Into<JsValue>> IntoCallbackResult for T {
-    fn into_callback_res(self) -> Result<JsValue, String> {
+impl<T: IntoJsReturn> IntoCallbackResult for T {
+    fn into_callback_res(self) -> Result<JsValue, ExecutionError> {
         Ok(self.into())
     }
+
+    fn into_callback_raw(self, context: &CallContext) -> Result<q::JSValue, ExecutionError> {
+        Ok(self.into_js_return(context)?)
+    }
+
+    fn ts_type() -> String {
+        <T as IntoJsReturn>::ts_type()
+    }
 }
 
-impl<T: Into<JsValue>, E: std::fmt::Display> IntoCallbackResult for Result<T, E> {
-    fn into_callback_res(self) -> Result<JsValue, String> {
+impl<T, E> IntoCallbackResult for Result<T, E>
+where
+    T: IntoJsReturn,
+    E: Into<HostError>,
+{
+    fn into_callback_res(self) -> Result<JsValue, ExecutionError> {
         match self {
             Ok(v) => Ok(v.into()),
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(callback_error(e)),
+        }
+    }
+
+    fn into_callback_raw(self, context: &CallContext) -> Result<q::JSValue, ExecutionError> {
+        match self {
+            Ok(v) => Ok(v.into_js_return(context)?),
+            Err(e) => Err(callback_error(e)),
+        }
+    }
+
+    fn ts_type() -> String {
+        <T as IntoJsReturn>::ts_type()
+    }
+}
+
+pub trait IntoConstructorResult<T> {
+    fn into_constructor_res(self) -> Result<T, ExecutionError>;
+}
+
+impl<T> IntoConstructorResult<T> for T {
+    fn into_constructor_res(self) -> Result<T, ExecutionError> {
+        Ok(self)
+    }
+}
+
+impl<T, E: Into<HostError>> IntoConstructorResult<T> for Result<T, E> {
+    fn into_constructor_res(self) -> Result<T, ExecutionError> {
+        self.map_err(callback_error)
+    }
+}
+
+pub trait FromCallbackArg: Sized {
+    /// Whether the argument collects all remaining arguments.
+    const REST: bool = false;
+
+    /// Whether the argument may be omitted when it is in trailing position.
+    const OPTIONAL: bool = false;
+
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError>;
+
+    /// The Typescript type of the argument.
+    fn ts_type() -> String {
+        "any".into()
+    }
+
+    /// Convert the argument from the remaining arguments.
+    fn take_callback_arg(
+        context: &CallContext,
+        args: &mut std::slice::Iter<q::JSValue>,
+    ) -> Result<Self, ValueError> {
+        match args.next() {
+            Some(value) => Self::from_callback_arg(context, value),
+            None => Err(ValueError::Internal("Missing argument".into())),
+        }
+    }
+}
+
+/// Get the number of required arguments, given which arguments are
+/// optional. Only trailing optional arguments may be omitted.
+fn required_arguments(optional: &[bool]) -> usize {
+    optional
+        .iter()
+        .rposition(|optional| !optional)
+        .map_or(0, |index| index + 1)
+}
+
+/// Missing optional arguments are converted from `undefined`.
+impl<T: FromJsArg> FromCallbackArg for T {
+    const OPTIONAL: bool = T::OPTIONAL;
+
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        T::from_js_arg(context, value)
+    }
+
+    fn take_callback_arg(
+        context: &CallContext,
+        args: &mut std::slice::Iter<q::JSValue>,
+    ) -> Result<Self, ValueError> {
+        match args.next() {
+            Some(value) => Self::from_callback_arg(context, value),
+            None if T::OPTIONAL => T::try_from(JsValue::Undefined),
+            None => Err(ValueError::Internal("Missing argument".into())),
+        }
+    }
+
+    fn ts_type() -> String {
+        <T as FromJsArg>::ts_type()
+    }
+}
+
+impl FromCallbackArg for JsValue {
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        context.wrapper.to_value(value)
+    }
+}
+
+/// Optional arguments of types that are not converted with
+/// `TryFrom<JsValue>`. They accept missing, `undefined` and `null` values.
+macro_rules! impl_optional_arg {
+    ( $( [ $( $generics:tt )* ] $t:ty ),* $(,)? ) => {
+        $(
+            impl< $( $generics )* > FromCallbackArg for Option<$t> {
+                const OPTIONAL: bool = true;
+
+                fn from_callback_arg(
+                    context: &CallContext,
+                    value: &q::JSValue,
+                ) -> Result<Self, ValueError> {
+                    if context.wrapper.is_nullish(value) {
+                        return Ok(None);
+                    }
+                    <$t as FromCallbackArg>::from_callback_arg(context, value).map(Some)
+                }
+
+                fn ts_type() -> String {
+                    format!("{} | null", <$t as FromCallbackArg>::ts_type())
+                }
+
+                fn take_callback_arg(
+                    context: &CallContext,
+                    args: &mut std::slice::Iter<q::JSValue>,
+                ) -> Result<Self, ValueError> {
+                    match args.next() {
+                        Some(value) => Self::from_callback_arg(context, value),
+                        None => Ok(None),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_optional_arg![
+    [] JsValue,
+    [] JsFunction,
+    [] JsObject,
+    [] JsRef,
+    [T: JsClass] JsInstance<T>,
+];
+
+/// Collects the remaining arguments of a callback.
+///
+/// A `Rest<T>` must be the last argument of a callback. All remaining
+/// arguments are converted to `T`.
+///
+/// ```rust
+/// use quick_js::{Context, Rest};
+/// let context = Context::new().unwrap();
+///
+/// context.add_callback("sum", |first: i32, rest: Rest<i32>| {
+///     first + rest.iter().sum::<i32>()
+/// }).unwrap();
+///
+/// let value = context.eval_as::<i32>(" sum(1, 2, 3) ").unwrap();
+/// assert_eq!(value, 6);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rest<T>(Vec<T>);
+
+impl<T> Rest<T> {
+    /// Unpack the arguments into a Vec.
+    pub fn into_vec(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Rest<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T: FromCallbackArg> FromCallbackArg for Rest<T> {
+    const REST: bool = true;
+    const OPTIONAL: bool = true;
+
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        Ok(Rest(vec![T::from_callback_arg(context, value)?]))
+    }
+
+    fn ts_type() -> String {
+        T::ts_type()
+    }
+
+    fn take_callback_arg(
+        context: &CallContext,
+        args: &mut std::slice::Iter<q::JSValue>,
+    ) -> Result<Self, ValueError> {
+        args.map(|value| T::from_callback_arg(context, value))
+            .collect::<Result<_, _>>()
+            .map(Rest)
+    }
+}
+
+/// Access to the Javascript context from within a callback.
+///
+/// To receive a `CallContext`, a callback closure must take a `&CallContext`
+/// as its first argument.
+///
+/// ```rust
+/// use quick_js::{CallContext, Context, JsValue};
+/// let context = Context::new().unwrap();
+///
+/// context.add_callback("callTwice", |ctx: &CallContext, name: String| {
+///     ctx.call_function(&name, vec![1])?;
+///     ctx.call_function(&name, vec![2])
+/// }).unwrap();
+///
+/// context.eval(" var calls = []; function f(x) { calls.push(x); } ").unwrap();
+/// context.eval(" callTwice('f') ").unwrap();
+/// assert_eq!(context.eval(" calls ").unwrap(), vec![1, 2].into());
+/// ```
+pub struct CallContext<'a> {
+    wrapper: &'a ContextWrapper,
+    this: q::JSValue,
+    args: &'a [q::JSValue],
+}
+
+impl<'a> CallContext<'a> {
+    pub(crate) fn new(
+        wrapper: &'a ContextWrapper,
+        this: q::JSValue,
+        args: &'a [q::JSValue],
+    ) -> Self {
+        Self {
+            wrapper,
+            this,
+            args,
+        }
+    }
+
+    /// The `this` value the function was called with.
+    ///
+    /// For plain function calls this is `JsValue::Undefined`.
+    pub fn this(&self) -> Result<JsValue, ValueError> {
+        self.wrapper.to_value(&self.this)
+    }
+
+    /// The `this` value as an object reference.
+    ///
+    /// Returns `None` if `this` is not an object.
+    /// Inside a constructor added with
+    /// [Context::add_constructor](crate::Context::add_constructor), this is
+    /// the newly created instance.
+    pub fn this_object(&self) -> Option<JsObject> {
+        if !self.wrapper.is_object(&self.this) {
+            return None;
+        }
+        Some(JsObject {
+            value: self.wrapper.persistent(&self.this),
+        })
+    }
+
+    /// The raw `this` value.
+    pub(crate) fn raw_this(&self) -> &q::JSValue {
+        &self.this
+    }
+
+    /// Get the user data of type `T` stored in the context.
+    ///
+    /// See [Context::set_data](crate::Context::set_data).
+    pub fn data<T: 'static>(&self) -> Option<Rc<RefCell<T>>> {
+        self.wrapper.data::<T>()
+    }
+
+    /// Deserialize all arguments.
+    fn arguments(&self) -> Result<Vec<JsValue>, ValueError> {
+        self.args
+            .iter()
+            .map(|raw| self.wrapper.to_value(raw))
+            .collect()
+    }
+
+    /// Evaluates Javascript code and returns the value of the final expression.
+    ///
+    /// See [Context::eval](crate::Context::eval).
+    pub fn eval(&self, code: &str) -> Result<JsValue, ExecutionError> {
+        let value_raw = self.wrapper.eval(code)?;
+        let value = value_raw.to_value()?;
+        Ok(value)
+    }
+
+    /// Evaluates Javascript code and returns the value of the final expression
+    /// as a Rust type.
+    ///
+    /// See [Context::eval_as](crate::Context::eval_as).
+    pub fn eval_as<R>(&self, code: &str) -> Result<R, ExecutionError>
+    where
+        R: TryFrom<JsValue>,
+        R::Error: Into<ValueError>,
+    {
+        let value = self.eval(code)?;
+        let ret = R::try_from(value).map_err(|e| e.into())?;
+        Ok(ret)
+    }
+
+    /// Call a global function in the Javascript namespace.
+    ///
+    /// See [Context::call_function](crate::Context::call_function).
+    pub fn call_function(
+        &self,
+        function_name: &str,
+        args: impl IntoIterator<Item = impl Into<JsValue>>,
+    ) -> Result<JsValue, ExecutionError> {
+        let args = args.into_iter().map(|arg| arg.into()).collect();
+        self.wrapper.call_global_function(function_name, args)
+    }
+}
+
+/// A reference to a Javascript function.
+///
+/// Callbacks can take a `JsFunction` argument to receive functions from
+/// Javascript.
+///
+/// A `JsFunction` keeps the function alive. It becomes invalid when the
+/// context it belongs to is dropped or reset.
+///
+/// The garbage collector can not see references held by Rust closures. If a
+/// callback closure keeps a `JsFunction` that in turn references the
+/// callback, the cycle is never collected, and both are only freed when the
+/// context is dropped or reset.
+///
+/// ```rust
+/// use quick_js::{Context, JsFunction};
+/// let context = Context::new().unwrap();
+///
+/// context.add_callback("map", |values: Vec<i32>, f: JsFunction| {
+///     values
+///         .into_iter()
+///         .map(|value| f.call(vec![value]))
+///         .collect::<Result<Vec<_>, _>>()
+/// }).unwrap();
+///
+/// let value = context.eval_as::<Vec<i32>>(" map([1, 2, 3], x => x * 2) ").unwrap();
+/// assert_eq!(value, vec![2, 4, 6]);
+/// ```
+#[derive(Clone)]
+pub struct JsFunction {
+    value: PersistentRef,
+}
+
+impl JsFunction {
+    /// Call the function with the given arguments.
+    ///
+    /// Just like [Context::call_function](crate::Context::call_function),
+    /// a returned Promise will be resolved.
+    pub fn call(
+        &self,
+        args: impl IntoIterator<Item = impl Into<JsValue>>,
+    ) -> Result<JsValue, ExecutionError> {
+        let args = args.into_iter().map(|arg| arg.into()).collect();
+        self.value.call(args)
+    }
+}
+
+impl std::fmt::Debug for JsFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JsFunction(?)")
+    }
+}
+
+impl FromCallbackArg for JsFunction {
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        if !context.wrapper.is_function(value) {
+            return Err(ValueError::UnexpectedType);
+        }
+        Ok(JsFunction {
+            value: context.wrapper.persistent(value),
+        })
+    }
+
+    fn ts_type() -> String {
+        "Function".into()
+    }
+}
+
+/// A reference to a Javascript object.
+///
+/// Callbacks can take a `JsObject` argument to receive objects without
+/// converting them to a `JsValue`. Modifications are visible to Javascript.
+///
+/// A `JsObject` keeps the object alive. It becomes invalid when the
+/// context it belongs to is dropped or reset. Like with
+/// [JsFunction](JsFunction), a reference cycle through a callback closure is
+/// only freed with the context.
+///
+/// ```rust
+/// use std::convert::TryInto;
+/// use quick_js::{Context, ExecutionError, JsObject};
+/// let context = Context::new().unwrap();
+///
+/// context.add_callback("increment", |obj: JsObject| -> Result<i32, ExecutionError> {
+///     let count: i32 = obj.property("count")?.try_into()?;
+///     obj.set_property("count", count + 1)?;
+///     Ok(count + 1)
+/// }).unwrap();
+///
+/// let value = context.eval_as::<i32>(" let o = { count: 1 }; increment(o); o.count ").unwrap();
+/// assert_eq!(value, 2);
+/// ```
+#[derive(Clone)]
+pub struct JsObject {
+    value: PersistentRef,
+}
+
+impl JsObject {
+    /// Get the value of a property.
+    ///
+    /// Missing properties are returned as `JsValue::Undefined`.
+    pub fn property(&self, name: &str) -> Result<JsValue, ExecutionError> {
+        self.value.property(name)
+    }
+
+    /// Set the value of a property.
+    pub fn set_property(
+        &self,
+        name: &str,
+        value: impl Into<JsValue>,
+    ) -> Result<(), ExecutionError> {
+        self.value.set_property(name, value.into())
+    }
+}
+
+impl std::fmt::Debug for JsObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JsObject(?)")
+    }
+}
+
+impl FromCallbackArg for JsObject {
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        if !context.wrapper.is_object(value) {
+            return Err(ValueError::UnexpectedType);
         }
+        Ok(JsObject {
+            value: context.wrapper.persistent(value),
+        })
+    }
+
+    fn ts_type() -> String {
+        "object".into()
+    }
+}
+
+/// A reference to a Javascript value of any type.
+///
+/// The value is not converted to a [JsValue](JsValue), so large values can be
+/// passed around cheaply. Callbacks can take a `JsRef` argument to receive
+/// any value unchanged.
+///
+/// A `JsRef` keeps the value alive. It becomes invalid when the context it
+/// belongs to is dropped or reset.
+///
+/// ```rust
+/// use quick_js::{Context, JsValue};
+/// let context = Context::new().unwrap();
+///
+/// let list = context.eval_ref(" [1, 2, 3] ").unwrap();
+/// context.set_global("list", &list).unwrap();
+/// context.eval(" list.push(4) ").unwrap();
+///
+/// assert_eq!(list.to_value().unwrap(), vec![1, 2, 3, 4].into());
+/// ```
+#[derive(Clone)]
+pub struct JsRef {
+    pub(crate) value: PersistentRef,
+}
+
+impl JsRef {
+    /// Convert the value to a [JsValue](JsValue).
+    pub fn to_value(&self) -> Result<JsValue, ExecutionError> {
+        self.value.to_value()
+    }
+
+    /// Convert the value to a JSON string with `JSON.stringify`, see
+    /// [Context::json_stringify](crate::Context::json_stringify).
+    pub fn json_stringify(&self, indent: Option<usize>) -> Result<String, ExecutionError> {
+        self.value.json_stringify(indent)
+    }
+}
+
+impl std::fmt::Debug for JsRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JsRef(?)")
+    }
+}
+
+impl FromCallbackArg for JsRef {
+    fn from_callback_arg(context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        Ok(JsRef {
+            value: context.wrapper.persistent(value),
+        })
     }
 }
 
@@ -31,27 +900,227 @@ pub trait Callback<F>: RefUnwindSafe {
     /// Should return:
     ///   - Err(_) if the JS values could not be converted
     ///   - Ok(Err(_)) if an error ocurred while processing.
-    ///       The given error will be raised as a JS exception.
+    ///     The given error will be raised as a JS exception.
     ///   - Ok(Ok(result)) when execution succeeded.
-    fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError>;
+    fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError>;
+
+    /// Execute the callback, and return an owned Javascript value.
+    #[doc(hidden)]
+    fn call_raw(
+        &self,
+        context: &CallContext,
+    ) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+        raw_result(context, self.call(context))
+    }
+
+    /// The Typescript signature, see
+    /// [Context::typescript_declarations](crate::Context::typescript_declarations).
+    fn signature(&self) -> Signature {
+        Signature::default()
+    }
+}
+
+/// Convert the result of a callback to an owned Javascript value.
+fn raw_result(
+    context: &CallContext,
+    result: Result<Result<JsValue, ExecutionError>, ValueError>,
+) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+    match result? {
+        Ok(value) => Ok(Ok(context.wrapper.to_raw(value)?)),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+/// The CallbackMut trait is implemented for `FnMut` functions/closures that
+/// can be used as callbacks in the JS runtime.
+///
+/// See [Context::add_callback_mut](crate::Context::add_callback_mut).
+pub trait CallbackMut<F> {
+    /// The number of JS arguments required.
+    fn argument_count(&self) -> usize;
+    /// Execute the callback.
+    ///
+    /// See [Callback::call](Callback::call).
+    fn call_mut(
+        &mut self,
+        context: &CallContext,
+    ) -> Result<Result<JsValue, ExecutionError>, ValueError>;
+
+    /// Execute the callback, and return an owned Javascript value.
+    #[doc(hidden)]
+    fn call_mut_raw(
+        &mut self,
+        context: &CallContext,
+    ) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+        let result = self.call_mut(context);
+        raw_result(context, result)
+    }
+
+    /// The Typescript signature, see
+    /// [Context::typescript_declarations](crate::Context::typescript_declarations).
+    fn signature(&self) -> Signature {
+        Signature::default()
+    }
+}
+
+/// A Rust function that can be registered as a global Javascript function.
+///
+/// Implemented by the `#[js_function]` attribute of the `derive` feature.
+/// Use [register_functions!](crate::register_functions) to register several
+/// functions at once.
+pub trait JsFunctionDef {
+    /// The Javascript name of the function.
+    const NAME: &'static str;
+    /// The doc comment of the function.
+    const DOC: &'static str;
+
+    /// Add the function to the global object of the context.
+    fn register(context: &crate::Context) -> Result<(), ExecutionError>;
+}
+
+/// Register functions annotated with `#[js_function]`.
+///
+/// Returns the first error, if any function could not be registered.
+///
+/// ```rust
+/// use quick_js::{register_functions, Context, ExecutionError, JsFunctionDef};
+///
+/// // Usually implemented by `#[js_function]`.
+/// struct Answer {}
+///
+/// impl JsFunctionDef for Answer {
+///     const NAME: &'static str = "answer";
+///     const DOC: &'static str = "Returns the answer.";
+///
+///     fn register(context: &Context) -> Result<(), ExecutionError> {
+///         context.add_callback(Self::NAME, || 42)
+///     }
+/// }
+///
+/// let context = Context::new().unwrap();
+/// register_functions!(context, [Answer]).unwrap();
+/// assert_eq!(context.eval_as::<i32>("answer()").unwrap(), 42);
+/// ```
+#[macro_export]
+macro_rules! register_functions {
+    ( $context:expr, [ $( $( $function:ident )::+ ),* $(,)? ] ) => {{
+        let context: &$crate::Context = &$context;
+        (|| -> ::std::result::Result<(), $crate::ExecutionError> {
+            $( <$( $function )::+ as $crate::JsFunctionDef>::register(context)?; )*
+            Ok(())
+        })()
+    }};
+}
+
+/// The Method trait is implemented for functions/closures that can be used
+/// as methods of a [JsClass](crate::JsClass).
+///
+/// Methods are like callbacks, but take a `&T` or `&mut T` first argument
+/// that receives the Rust value of the instance.
+pub trait Method<T, F>: RefUnwindSafe {
+    /// The number of JS arguments required.
+    fn argument_count(&self) -> usize;
+    /// Execute the method.
+    ///
+    /// See [Callback::call](Callback::call).
+    fn call(
+        &self,
+        this: &RefCell<T>,
+        context: &CallContext,
+    ) -> Result<Result<JsValue, ExecutionError>, ValueError>;
+
+    /// Execute the method, and return an owned Javascript value.
+    #[doc(hidden)]
+    fn call_raw(
+        &self,
+        this: &RefCell<T>,
+        context: &CallContext,
+    ) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+        raw_result(context, self.call(this, context))
+    }
+
+    /// The Typescript signature, see
+    /// [Context::typescript_declarations](crate::Context::typescript_declarations).
+    fn signature(&self) -> Signature {
+        Signature::default()
+    }
+}
+
+/// The Constructor trait is implemented for functions/closures that can be
+/// used as the constructor of a [JsClass](crate::JsClass).
+///
+/// Constructors are like callbacks, but return the Rust value of the new
+/// instance.
+pub trait Constructor<T, F>: RefUnwindSafe {
+    /// The number of JS arguments required.
+    fn argument_count(&self) -> usize;
+    /// Execute the constructor.
+    ///
+    /// See [Callback::call](Callback::call).
+    fn call(&self, context: &CallContext) -> Result<Result<T, ExecutionError>, ValueError>;
+
+    /// The Typescript signature, see
+    /// [Context::typescript_declarations](crate::Context::typescript_declarations).
+    fn signature(&self) -> Signature {
+        Signature::default()
+    }
 }
 
 macro_rules! impl_callback {
-    (@call $len:literal $self:ident $args:ident ) => {
-        $self()
+    (@call $len:literal $self:ident $context:ident ( $( $pre:expr ),* ) ) => {
+        $self( $( $pre ),* )
     };
 
-    (@call $len:literal $self:ident $args:ident $( $arg:ident ),* ) => {
+    (@call $len:literal $self:ident $context:ident ( $( $pre:expr ),* ) $( $arg:ident ),* ) => {
         {
-            let mut iter = $args.into_iter();
+            let mut iter = $context.args.iter();
             $self(
+                $( $pre, )*
                 $(
-                    $arg::try_from(iter.next().unwrap())?,
+                    $arg::take_callback_arg($context, &mut iter)?,
                 )*
             )
         }
     };
 
+    (@signature $ret:expr; $( $arg:ident ),* ) => {
+        Signature::new(
+            vec![ $( (
+                <$arg as FromCallbackArg>::ts_type(),
+                <$arg as FromCallbackArg>::OPTIONAL,
+                <$arg as FromCallbackArg>::REST,
+            ) ),* ],
+            $ret,
+        )
+    };
+
+    (@required $( $arg:ident ),* ) => {
+        required_arguments(&[ $( <$arg as FromCallbackArg>::OPTIONAL ),* ])
+    };
+
+    (@check $len:literal $self:ident $context:ident $( $arg:ident ),* ) => {
+        let total: usize = $len;
+        let rest = false $( || <$arg as FromCallbackArg>::REST )*;
+        let count = $context.args.len();
+        // The required arguments are only looked up if some are missing.
+        let missing = count < total && count < impl_callback!(@required $( $arg ),* );
+        if missing || (count > total && !rest) {
+            let required = impl_callback!(@required $( $arg ),* );
+            let expected = if rest {
+                format!("at least {}", required)
+            } else if required < total {
+                format!("{} to {}", required, total)
+            } else {
+                total.to_string()
+            };
+            return Ok(Err(ExecutionError::Exception(format!(
+                "Invalid argument count: Expected {}, got {}",
+                expected,
+                count
+            ).into())));
+        }
+    };
+
     [ $(  $len:literal : ( $( $arg:ident, )* ), )* ] => {
         $(
 
@@ -65,29 +1134,270 @@ macro_rules! impl_callback {
                 &F,
             )>> for F
             where
-                $( $arg: TryFrom<JsValue, Error = ValueError>, )*
+                $( $arg: FromCallbackArg, )*
                 R: IntoCallbackResult,
                 F: Fn( $( $arg, )*  ) -> R + Sized + RefUnwindSafe,
             {
                 fn argument_count(&self) -> usize {
-                    $len
+                    impl_callback!(@required $( $arg ),* )
                 }
 
-                fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
-                    if args.len() != $len {
-                        return Ok(Err(format!(
-                            "Invalid argument count: Expected {}, got {}",
-                            self.argument_count(),
-                            args.len()
-                        )));
-                    }
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature <R as IntoCallbackResult>::ts_type(); $( $arg ),* )
+                }
+
+                fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context () $($arg),* );
+                    Ok(res.into_callback_res())
+                }
 
-                    let res = impl_callback!(@call $len self args $($arg),* );
+                fn call_raw(&self, context: &CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context () $($arg),* );
+                    Ok(res.into_callback_raw(context))
+                }
+            }
+
+            impl<
+                $( $arg, )*
+                R,
+                F,
+            > Callback<PhantomData<(
+                &CallContext<'static>,
+                $( &$arg, )*
+                &R,
+                &F,
+            )>> for F
+            where
+                $( $arg: FromCallbackArg, )*
+                R: IntoCallbackResult,
+                F: Fn( &CallContext, $( $arg, )*  ) -> R + Sized + RefUnwindSafe,
+            {
+                fn argument_count(&self) -> usize {
+                    impl_callback!(@required $( $arg ),* )
+                }
+
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature <R as IntoCallbackResult>::ts_type(); $( $arg ),* )
+                }
+
+                fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context (context) $($arg),* );
                     Ok(res.into_callback_res())
                 }
+
+                fn call_raw(&self, context: &CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context (context) $($arg),* );
+                    Ok(res.into_callback_raw(context))
+                }
+            }
+
+            impl<
+                $( $arg, )*
+                R,
+                F,
+            > CallbackMut<PhantomData<(
+                $( &$arg, )*
+                &R,
+                &F,
+            )>> for F
+            where
+                $( $arg: FromCallbackArg, )*
+                R: IntoCallbackResult,
+                F: FnMut( $( $arg, )*  ) -> R + Sized,
+            {
+                fn argument_count(&self) -> usize {
+                    impl_callback!(@required $( $arg ),* )
+                }
+
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature <R as IntoCallbackResult>::ts_type(); $( $arg ),* )
+                }
+
+                fn call_mut(&mut self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context () $($arg),* );
+                    Ok(res.into_callback_res())
+                }
+
+                fn call_mut_raw(&mut self, context: &CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context () $($arg),* );
+                    Ok(res.into_callback_raw(context))
+                }
+            }
+
+            impl<
+                $( $arg, )*
+                R,
+                F,
+            > CallbackMut<PhantomData<(
+                &CallContext<'static>,
+                $( &$arg, )*
+                &R,
+                &F,
+            )>> for F
+            where
+                $( $arg: FromCallbackArg, )*
+                R: IntoCallbackResult,
+                F: FnMut( &CallContext, $( $arg, )*  ) -> R + Sized,
+            {
+                fn argument_count(&self) -> usize {
+                    impl_callback!(@required $( $arg ),* )
+                }
+
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature <R as IntoCallbackResult>::ts_type(); $( $arg ),* )
+                }
+
+                fn call_mut(&mut self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context (context) $($arg),* );
+                    Ok(res.into_callback_res())
+                }
+
+                fn call_mut_raw(&mut self, context: &CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context (context) $($arg),* );
+                    Ok(res.into_callback_raw(context))
+                }
+            }
+
+            impl_callback!(@method $len this context &T, try_borrow, (&*this) $($arg,)* );
+            impl_callback!(@method $len this context &T, try_borrow, (&*this, context)
+                { &CallContext<'static>; &CallContext } $($arg,)* );
+            impl_callback!(@method $len this context &mut T, try_borrow_mut, (&mut *this) $($arg,)* );
+            impl_callback!(@method $len this context &mut T, try_borrow_mut, (&mut *this, context)
+                { &CallContext<'static>; &CallContext } $($arg,)* );
+
+            impl<
+                T,
+                $( $arg, )*
+                R,
+                F,
+            > Constructor<T, PhantomData<(
+                $( &$arg, )*
+                &R,
+                &F,
+            )>> for F
+            where
+                $( $arg: FromCallbackArg, )*
+                R: IntoConstructorResult<T>,
+                F: Fn( $( $arg, )*  ) -> R + Sized + RefUnwindSafe,
+            {
+                fn argument_count(&self) -> usize {
+                    impl_callback!(@required $( $arg ),* )
+                }
+
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature "void".into(); $( $arg ),* )
+                }
+
+                fn call(&self, context: &CallContext) -> Result<Result<T, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context () $($arg),* );
+                    Ok(res.into_constructor_res())
+                }
+            }
+
+            impl<
+                T,
+                $( $arg, )*
+                R,
+                F,
+            > Constructor<T, PhantomData<(
+                &CallContext<'static>,
+                $( &$arg, )*
+                &R,
+                &F,
+            )>> for F
+            where
+                $( $arg: FromCallbackArg, )*
+                R: IntoConstructorResult<T>,
+                F: Fn( &CallContext, $( $arg, )*  ) -> R + Sized + RefUnwindSafe,
+            {
+                fn argument_count(&self) -> usize {
+                    impl_callback!(@required $( $arg ),* )
+                }
+
+                fn signature(&self) -> Signature {
+                    impl_callback!(@signature "void".into(); $( $arg ),* )
+                }
+
+                fn call(&self, context: &CallContext) -> Result<Result<T, ExecutionError>, ValueError> {
+                    impl_callback!(@check $len self context $($arg),* );
+                    let res = impl_callback!(@call $len self context (context) $($arg),* );
+                    Ok(res.into_constructor_res())
+                }
             }
         )*
     };
+
+    (@method $len:literal $this_var:ident $context:ident $this:ty, $borrow:ident, ( $( $pre:expr ),* )
+        $( { $ctx:ty; $ctx_arg:ty } )? $( $arg:ident, )* ) => {
+        impl<
+            T,
+            $( $arg, )*
+            R,
+            F,
+        > Method<T, PhantomData<(
+            $this,
+            $( $ctx, )?
+            $( &$arg, )*
+            &R,
+            &F,
+        )>> for F
+        where
+            $( $arg: FromCallbackArg, )*
+            R: IntoCallbackResult,
+            F: Fn( $this, $( $ctx_arg, )? $( $arg, )* ) -> R + Sized + RefUnwindSafe,
+        {
+            fn argument_count(&self) -> usize {
+                impl_callback!(@required $( $arg ),* )
+            }
+
+            fn signature(&self) -> Signature {
+                impl_callback!(@signature <R as IntoCallbackResult>::ts_type(); $( $arg ),* )
+            }
+
+            fn call(
+                &self,
+                $this_var: &RefCell<T>,
+                $context: &CallContext,
+            ) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+                impl_callback!(@check $len self $context $($arg),* );
+                #[allow(unused_mut)]
+                let mut $this_var = match $this_var.$borrow() {
+                    Ok(value) => value,
+                    Err(_) => {
+                        return Ok(Err(ExecutionError::Exception("Object is already in use".into())))
+                    }
+                };
+                let res = impl_callback!(@call $len self $context ( $( $pre ),* ) $($arg),* );
+                Ok(res.into_callback_res())
+            }
+
+            fn call_raw(
+                &self,
+                $this_var: &RefCell<T>,
+                $context: &CallContext,
+            ) -> Result<Result<q::JSValue, ExecutionError>, ValueError> {
+                impl_callback!(@check $len self $context $($arg),* );
+                #[allow(unused_mut)]
+                let mut $this_var = match $this_var.$borrow() {
+                    Ok(value) => value,
+                    Err(_) => {
+                        return Ok(Err(ExecutionError::Exception("Object is already in use".into())))
+                    }
+                };
+                let res = impl_callback!(@call $len self $context ( $( $pre ),* ) $($arg),* );
+                Ok(res.into_callback_raw($context))
+            }
+        }
+    };
 }
 
 impl_callback![
@@ -97,6 +1407,13 @@ impl_callback![
     3: (A1, A2, A3,),
     4: (A1, A2, A3, A4,),
     5: (A1, A2, A3, A4, A5,),
+    6: (A1, A2, A3, A4, A5, A6,),
+    7: (A1, A2, A3, A4, A5, A6, A7,),
+    8: (A1, A2, A3, A4, A5, A6, A7, A8,),
+    9: (A1, A2, A3, A4, A5, A6, A7, A8, A9,),
+    10: (A1, A2, A3, A4, A5, A6, A7, A8, A9, A10,),
+    11: (A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11,),
+    12: (A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12,),
 ];
 
 /// A wrapper around Vec<JsValue>, used for vararg callbacks.
@@ -120,9 +1437,9 @@ where
         0
     }
 
-    fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
-        (self)(Arguments(args));
-        Ok(Ok(JsValue::Null))
+    fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        (self)(Arguments(context.arguments()?));
+        Ok(Ok(JsValue::Undefined))
     }
 }
 
@@ -135,8 +1452,73 @@ where
         0
     }
 
-    fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
-        let res = (self)(Arguments(args));
+    fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        let res = (self)(Arguments(context.arguments()?));
+        Ok(res.into_callback_res())
+    }
+}
+
+impl<F> Callback<PhantomData<(&CallContext<'static>, &Arguments, &F)>> for F
+where
+    F: Fn(&CallContext, Arguments) + Sized + RefUnwindSafe,
+{
+    fn argument_count(&self) -> usize {
+        0
+    }
+
+    fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        (self)(context, Arguments(context.arguments()?));
+        Ok(Ok(JsValue::Undefined))
+    }
+}
+
+impl<F, R> Callback<PhantomData<(&CallContext<'static>, &Arguments, &F, &R)>> for F
+where
+    R: IntoCallbackResult,
+    F: Fn(&CallContext, Arguments) -> R + Sized + RefUnwindSafe,
+{
+    fn argument_count(&self) -> usize {
+        0
+    }
+
+    fn call(&self, context: &CallContext) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        let res = (self)(context, Arguments(context.arguments()?));
+        Ok(res.into_callback_res())
+    }
+}
+
+impl<F, R> CallbackMut<PhantomData<(&Arguments, &F, &R)>> for F
+where
+    R: IntoCallbackResult,
+    F: FnMut(Arguments) -> R + Sized,
+{
+    fn argument_count(&self) -> usize {
+        0
+    }
+
+    fn call_mut(
+        &mut self,
+        context: &CallContext,
+    ) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        let res = (self)(Arguments(context.arguments()?));
+        Ok(res.into_callback_res())
+    }
+}
+
+impl<F, R> CallbackMut<PhantomData<(&CallContext<'static>, &Arguments, &F, &R)>> for F
+where
+    R: IntoCallbackResult,
+    F: FnMut(&CallContext, Arguments) -> R + Sized,
+{
+    fn argument_count(&self) -> usize {
+        0
+    }
+
+    fn call_mut(
+        &mut self,
+        context: &CallContext,
+    ) -> Result<Result<JsValue, ExecutionError>, ValueError> {
+        let res = (self)(context, Arguments(context.arguments()?));
         Ok(res.into_callback_res())
     }
 }
@@ -151,7 +1533,7 @@ where
 //0
 //}
 
-//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
+//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, ExecutionError>, ValueError> {
 //if !args.is_empty() {
 //return Ok(Err(format!(
 //"Invalid argument count: Expected 0, got {}",
@@ -174,7 +1556,7 @@ where
 //fn argument_count(&self) -> usize {
 //1
 //}
-//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
+//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, ExecutionError>, ValueError> {
 //if args.len() != 1 {
 //return Ok(Err(format!(
 //"Invalid argument count: Expected 1, got {}",
@@ -201,7 +1583,7 @@ where
 //2
 //}
 
-//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
+//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, ExecutionError>, ValueError> {
 //if args.len() != 2 {
 //return Ok(Err(format!(
 //"Invalid argument count: Expected 2, got {}",
@@ -234,7 +1616,7 @@ where
 //3
 //}
 
-//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
+//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, ExecutionError>, ValueError> {
 //if args.len() != self.argument_count() {
 //return Ok(Err(format!(
 //"Invalid argument count: Expected 3, got {}",
@@ -271,7 +1653,7 @@ where
 //4
 //}
 
-//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, String>, ValueError> {
+//fn call(&self, args: Vec<JsValue>) -> Result<Result<JsValue, ExecutionError>, ValueError> {
 //if args.len() != self.argument_count() {
 //return Ok(Err(format!(
 //"Invalid argument count: Expected 3, got {}",
@@ -0,0 +1,312 @@
+use std::{
+    any::TypeId,
+    cell::{Ref, RefCell, RefMut},
+    marker::PhantomData,
+    os::raw::c_void,
+    panic::RefUnwindSafe,
+    rc::Rc,
+    sync::Mutex,
+};
+
+use libquickjs_sys as q;
+
+use crate::callback::{
+    callback_error, CallContext, Constructor, FromCallbackArg, HostError, Method,
+};
+use crate::typescript::ClassDeclaration;
+use crate::value::{JsValue, ValueError};
+use crate::ExecutionError;
+
+/// A Rust type that can be exposed to Javascript as a class.
+///
+/// Classes are registered with [Context::add_class](crate::Context::add_class).
+/// Each Javascript instance owns a Rust value of the type, which is dropped
+/// when the instance is garbage collected.
+///
+/// ```rust
+/// use quick_js::{ClassBuilder, Context, JsClass};
+///
+/// struct Counter {
+///     count: i32,
+/// }
+///
+/// impl JsClass for Counter {
+///     const NAME: &'static str = "Counter";
+///
+///     fn define(class: &mut ClassBuilder<Self>) {
+///         class
+///             .constructor(|start: i32| Counter { count: start })
+///             .method("increment", |this: &mut Counter| {
+///                 this.count += 1;
+///                 this.count
+///             })
+///             .property(
+///                 "count",
+///                 |this: &Counter| this.count,
+///                 |this: &mut Counter, count: i32| this.count = count,
+///             );
+///     }
+/// }
+///
+/// let context = Context::new().unwrap();
+/// context.add_class::<Counter>().unwrap();
+///
+/// let value = context.eval_as::<i32>("
+///     const counter = new Counter(1);
+///     counter.increment();
+///     counter.count *= 10;
+///     counter.count
+/// ").unwrap();
+/// assert_eq!(value, 20);
+/// ```
+pub trait JsClass: Sized + 'static {
+    /// The name of the class in Javascript.
+    const NAME: &'static str;
+
+    /// Define the constructor, methods and properties of the class.
+    fn define(class: &mut ClassBuilder<Self>);
+}
+
+pub trait IntoSetterResult {
+    fn into_setter_res(self) -> Result<(), ExecutionError>;
+}
+
+impl IntoSetterResult for () {
+    fn into_setter_res(self) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+impl<E: Into<HostError>> IntoSetterResult for Result<(), E> {
+    fn into_setter_res(self) -> Result<(), ExecutionError> {
+        self.map_err(callback_error)
+    }
+}
+
+pub(crate) type ConstructorFn<T> =
+    dyn Fn(&CallContext) -> Result<Result<T, ExecutionError>, ValueError>;
+pub(crate) type MethodFn<T> =
+    dyn Fn(&RefCell<T>, &CallContext) -> Result<Result<q::JSValue, ExecutionError>, ValueError>;
+/// A property name with a getter and an optional setter.
+pub(crate) type PropertyDef<T> = (String, Box<MethodFn<T>>, Option<Box<MethodFn<T>>>);
+
+/// Builder for the definition of a [JsClass](JsClass).
+pub struct ClassBuilder<T> {
+    pub(crate) constructor: Option<(usize, Box<ConstructorFn<T>>)>,
+    pub(crate) methods: Vec<(String, usize, Box<MethodFn<T>>)>,
+    pub(crate) properties: Vec<PropertyDef<T>>,
+    pub(crate) declaration: ClassDeclaration,
+}
+
+impl<T: 'static> ClassBuilder<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            constructor: None,
+            methods: Vec::new(),
+            properties: Vec::new(),
+            declaration: ClassDeclaration::default(),
+        }
+    }
+
+    /// Set the constructor of the class.
+    ///
+    /// The constructor takes arguments just like a
+    /// [Callback](crate::Callback) and returns the Rust value for the new
+    /// instance, or a `Result` of it.
+    ///
+    /// Classes without a constructor can not be instantiated with `new`.
+    pub fn constructor<F>(&mut self, constructor: impl Constructor<T, F> + 'static) -> &mut Self {
+        let argument_count = constructor.argument_count();
+        self.declaration.constructor = Some(constructor.signature());
+        self.constructor = Some((argument_count, Box::new(move |ctx| constructor.call(ctx))));
+        self
+    }
+
+    /// Add a method to the class prototype.
+    ///
+    /// The method takes a `&T` or `&mut T` first argument, followed by
+    /// arguments just like a [Callback](crate::Callback).
+    pub fn method<F>(&mut self, name: &str, method: impl Method<T, F> + 'static) -> &mut Self {
+        let argument_count = method.argument_count();
+        self.declaration
+            .methods
+            .push((name.to_string(), method.signature()));
+        self.methods.push((
+            name.to_string(),
+            argument_count,
+            Box::new(move |this, ctx| method.call_raw(this, ctx)),
+        ));
+        self
+    }
+
+    /// Add a read-only property to the class prototype.
+    pub fn getter<F>(&mut self, name: &str, getter: impl Method<T, F> + 'static) -> &mut Self {
+        self.declaration
+            .properties
+            .push((name.to_string(), getter.signature().returns, true));
+        self.properties.push((
+            name.to_string(),
+            Box::new(move |this, ctx| getter.call_raw(this, ctx)),
+            None,
+        ));
+        self
+    }
+
+    /// Add a property with a getter and a setter to the class prototype.
+    ///
+    /// The setter may return a `Result`, an error is raised as an exception.
+    pub fn property<F, V, R>(
+        &mut self,
+        name: &str,
+        getter: impl Method<T, F> + 'static,
+        setter: impl Fn(&mut T, V) -> R + RefUnwindSafe + 'static,
+    ) -> &mut Self
+    where
+        V: FromCallbackArg + 'static,
+        R: IntoSetterResult + 'static,
+    {
+        self.declaration
+            .properties
+            .push((name.to_string(), getter.signature().returns, false));
+        let setter = move |this: &mut T, value: V| -> Result<JsValue, ExecutionError> {
+            setter(this, value).into_setter_res()?;
+            Ok(JsValue::Undefined)
+        };
+        self.properties.push((
+            name.to_string(),
+            Box::new(move |this, ctx| getter.call_raw(this, ctx)),
+            Some(Box::new(move |this, ctx| {
+                Method::<T, PhantomData<(&mut T, &V, &Result<JsValue, ExecutionError>, &_)>>::call_raw(
+                    &setter, this, ctx,
+                )
+            })),
+        ));
+        self
+    }
+}
+
+/// Class ids are global to the process, so each type is assigned an id once.
+static CLASS_IDS: Mutex<Vec<(TypeId, q::JSClassID)>> = Mutex::new(Vec::new());
+
+/// Get the class id of a type.
+pub(crate) fn class_id<T: 'static>() -> q::JSClassID {
+    let type_id = TypeId::of::<T>();
+    let mut ids = CLASS_IDS.lock().unwrap();
+    if let Some((_, id)) = ids.iter().find(|(t, _)| *t == type_id) {
+        return *id;
+    }
+
+    let mut id = 0;
+    unsafe { q::JS_NewClassID(&mut id) };
+    ids.push((type_id, id));
+    id
+}
+
+/// Attach a Rust value to a newly created instance.
+pub(crate) unsafe fn set_instance_value<T: 'static>(instance: q::JSValue, value: T) {
+    let value = Rc::new(RefCell::new(value));
+    q::JS_SetOpaque(instance, Rc::into_raw(value) as *mut c_void);
+}
+
+/// Get the Rust value of an instance.
+///
+/// Returns `None` if the value is not an instance of the class.
+/// The reference is valid as long as the instance is alive.
+pub(crate) unsafe fn instance_value<'a, T: 'static>(value: &q::JSValue) -> Option<&'a RefCell<T>> {
+    let ptr = q::JS_GetOpaque(*value, class_id::<T>()) as *const RefCell<T>;
+    ptr.as_ref()
+}
+
+/// Class finalizer that drops the Rust value of an instance.
+pub(crate) unsafe extern "C" fn finalize<T: 'static>(_rt: *mut q::JSRuntime, value: q::JSValue) {
+    let ptr = q::JS_GetOpaque(value, class_id::<T>()) as *const RefCell<T>;
+    if !ptr.is_null() {
+        drop(Rc::from_raw(ptr));
+    }
+}
+
+/// A reference to the Rust value of a [JsClass](JsClass) instance.
+///
+/// Callbacks can take a `JsInstance<T>` argument to receive instances
+/// created in Javascript.
+///
+/// ```rust
+/// use quick_js::{ClassBuilder, Context, JsClass, JsInstance};
+///
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// impl JsClass for Point {
+///     const NAME: &'static str = "Point";
+///
+///     fn define(class: &mut ClassBuilder<Self>) {
+///         class.constructor(|x: i32, y: i32| Point { x, y });
+///     }
+/// }
+///
+/// let context = Context::new().unwrap();
+/// context.add_class::<Point>().unwrap();
+/// context.add_callback("distance", |a: JsInstance<Point>, b: JsInstance<Point>| {
+///     let (a, b) = (a.borrow(), b.borrow());
+///     (a.x - b.x).abs() + (a.y - b.y).abs()
+/// }).unwrap();
+///
+/// let value = context.eval_as::<i32>(" distance(new Point(1, 2), new Point(4, 6)) ").unwrap();
+/// assert_eq!(value, 7);
+/// ```
+pub struct JsInstance<T> {
+    value: Rc<RefCell<T>>,
+}
+
+impl<T> JsInstance<T> {
+    /// Immutably borrow the Rust value.
+    ///
+    /// Panics if the value is currently mutably borrowed, for example by a
+    /// running method.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.value.borrow()
+    }
+
+    /// Mutably borrow the Rust value.
+    ///
+    /// Panics if the value is currently borrowed, for example by a running
+    /// method.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.value.borrow_mut()
+    }
+}
+
+impl<T> Clone for JsInstance<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for JsInstance<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JsInstance(?)")
+    }
+}
+
+impl<T: JsClass> FromCallbackArg for JsInstance<T> {
+    fn from_callback_arg(_context: &CallContext, value: &q::JSValue) -> Result<Self, ValueError> {
+        let ptr = unsafe { q::JS_GetOpaque(*value, class_id::<T>()) } as *const RefCell<T>;
+        if ptr.is_null() {
+            return Err(ValueError::UnexpectedType);
+        }
+        // The instance holds one reference, which is shared with the handle.
+        let value = unsafe {
+            Rc::increment_strong_count(ptr);
+            Rc::from_raw(ptr)
+        };
+        Ok(JsInstance { value })
+    }
+
+    fn ts_type() -> String {
+        T::NAME.into()
+    }
+}
@@ -80,6 +80,7 @@ mod log {
     fn print_value(value: JsValue) -> String {
         match value {
             JsValue::Null => "null".to_string(),
+            JsValue::Undefined => "undefined".to_string(),
             JsValue::Bool(v) => v.to_string(),
             JsValue::Int(v) => v.to_string(),
             JsValue::Float(v) => v.to_string(),
@@ -100,7 +101,26 @@ mod log {
                     .join(", ");
                 format!("{{{}}}", parts)
             }
-            #[cfg(feature = "chrono")]
+            JsValue::ArrayBuffer(v) => format!("ArrayBuffer({})", v.len()),
+            JsValue::TypedArray(v) => format!("{}({})", v.name(), v.len()),
+            JsValue::Map(entries) => {
+                let parts = entries
+                    .into_iter()
+                    .map(|(key, value)| format!("{} => {}", print_value(key), print_value(value)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("Map {{{}}}", parts)
+            }
+            JsValue::Set(values) => {
+                let parts = values
+                    .into_iter()
+                    .map(print_value)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("Set {{{}}}", parts)
+            }
+            JsValue::IllFormedString(v) => v.to_string(),
+            JsValue::Symbol(v) => format!("Symbol({})", v.description().unwrap_or_default()),
             JsValue::Date(v) => v.to_string(),
             #[cfg(feature = "bigint")]
             JsValue::BigInt(v) => v.to_string(),
@@ -0,0 +1,224 @@
+//! Helpers for the code generated by `#[derive(FromJs)]`, `#[derive(IntoJs)]`
+//! and `#[js_function]`.
+//!
+//! Not part of the public API.
+
+use std::convert::TryFrom;
+
+use crate::typescript::property_name;
+use crate::{FromJsArg, JsObjectMap, JsValue, Param, Signature, ValueError};
+
+/// The Typescript type of a field that is converted from Javascript.
+///
+/// Unlike callback arguments, fields may be a `JsValue`.
+pub trait FromJsField {
+    fn ts_type() -> String;
+}
+
+impl<T: FromJsArg> FromJsField for T {
+    fn ts_type() -> String {
+        T::ts_type()
+    }
+}
+
+impl FromJsField for JsValue {
+    fn ts_type() -> String {
+        "any".into()
+    }
+}
+
+impl FromJsField for Option<JsValue> {
+    fn ts_type() -> String {
+        "any | null".into()
+    }
+}
+
+pub fn object(value: JsValue) -> Result<JsObjectMap, ValueError> {
+    match value {
+        JsValue::Object(object) => Ok(object),
+        _ => Err(ValueError::UnexpectedType),
+    }
+}
+
+pub fn array(value: JsValue, len: usize) -> Result<std::vec::IntoIter<JsValue>, ValueError> {
+    match value {
+        JsValue::Array(values) if values.len() == len => Ok(values.into_iter()),
+        JsValue::Array(values) => Err(ValueError::Custom(format!(
+            "Expected an array of length {}, got length {}",
+            len,
+            values.len()
+        ))),
+        _ => Err(ValueError::UnexpectedType),
+    }
+}
+
+pub fn unit(value: JsValue) -> Result<(), ValueError> {
+    if value.is_nullish() {
+        Ok(())
+    } else {
+        Err(ValueError::UnexpectedType)
+    }
+}
+
+pub fn convert<T>(value: JsValue) -> Result<T, ValueError>
+where
+    T: TryFrom<JsValue>,
+    T::Error: Into<ValueError>,
+{
+    T::try_from(value).map_err(Into::into)
+}
+
+pub fn element<T>(values: &mut std::vec::IntoIter<JsValue>, index: usize) -> Result<T, ValueError>
+where
+    T: TryFrom<JsValue>,
+    T::Error: Into<ValueError>,
+{
+    let value = values.next().unwrap_or(JsValue::Undefined);
+    convert(value).map_err(|e| ValueError::Custom(format!("Invalid element {}: {}", index, e)))
+}
+
+pub fn field<T>(object: &mut JsObjectMap, name: &str) -> Result<T, ValueError>
+where
+    T: TryFrom<JsValue>,
+    T::Error: Into<ValueError>,
+{
+    match object.remove(name) {
+        Some(value) => convert(value).map_err(|e| invalid_field(name, e)),
+        None => Err(ValueError::Custom(format!("Missing field `{}`", name))),
+    }
+}
+
+pub fn optional_field<T>(object: &mut JsObjectMap, name: &str) -> Result<Option<T>, ValueError>
+where
+    T: TryFrom<JsValue>,
+    T::Error: Into<ValueError>,
+{
+    match object.remove(name) {
+        Some(value) if !value.is_nullish() => {
+            convert(value).map(Some).map_err(|e| invalid_field(name, e))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn default_field<T>(
+    object: &mut JsObjectMap,
+    name: &str,
+    default: impl FnOnce() -> T,
+) -> Result<T, ValueError>
+where
+    T: TryFrom<JsValue>,
+    T::Error: Into<ValueError>,
+{
+    match object.remove(name) {
+        Some(JsValue::Undefined) | None => Ok(default()),
+        Some(value) => convert(value).map_err(|e| invalid_field(name, e)),
+    }
+}
+
+fn invalid_field(name: &str, error: ValueError) -> ValueError {
+    ValueError::Custom(format!("Invalid field `{}`: {}", name, error))
+}
+
+/// Remove the string tag of an internally or adjacently tagged enum.
+pub fn tag(object: &mut JsObjectMap, name: &str) -> Result<String, ValueError> {
+    match object.remove(name) {
+        Some(JsValue::String(variant)) => Ok(variant),
+        Some(_) => Err(invalid_field(name, ValueError::UnexpectedType)),
+        None => Err(ValueError::Custom(format!("Missing tag `{}`", name))),
+    }
+}
+
+/// Split an externally tagged enum into the variant name and content.
+pub fn external_variant(value: JsValue) -> Result<(String, Option<JsValue>), ValueError> {
+    match value {
+        JsValue::String(variant) => Ok((variant, None)),
+        JsValue::Object(object) if object.len() == 1 => {
+            let (variant, content) = object.into_iter().next().unwrap();
+            Ok((variant, Some(content)))
+        }
+        _ => Err(ValueError::Custom(
+            "Expected a variant name or an object with a single key".to_string(),
+        )),
+    }
+}
+
+pub fn content(content: Option<JsValue>, variant: &str) -> Result<JsValue, ValueError> {
+    content.ok_or_else(|| ValueError::Custom(format!("Missing content for variant `{}`", variant)))
+}
+
+pub fn unknown_variant(variant: &str) -> ValueError {
+    ValueError::Custom(format!("Unknown variant `{}`", variant))
+}
+
+fn with_doc(mut signature: Signature, doc: &str) -> Signature {
+    if !doc.is_empty() {
+        signature.doc = Some(doc.to_string());
+    }
+    signature
+}
+
+/// Name the parameters of a `#[js_function]` and add its documentation.
+pub fn describe(mut signature: Signature, doc: &str, names: &[&str]) -> Signature {
+    for (param, name) in signature.params.iter_mut().zip(names) {
+        if !name.is_empty() {
+            param.name = name.to_string();
+        }
+    }
+    with_doc(signature, doc)
+}
+
+/// The options of a `#[js_function(options)]` when the options object is
+/// omitted, which is allowed if all of its properties are optional.
+pub fn omitted_options<T>() -> T
+where
+    T: TryFrom<JsValue, Error = ValueError>,
+{
+    // Missing optional properties take their defaults, so this can't fail.
+    T::try_from(JsValue::Object(JsObjectMap::new())).expect("Options have required properties")
+}
+
+/// Replace the parameters of a `#[js_function(options)]` with the options
+/// object, given the name, type and optionality of its properties.
+pub fn describe_options(
+    mut signature: Signature,
+    doc: &str,
+    properties: &[(&str, String, bool)],
+    optional: bool,
+) -> Signature {
+    signature.params = vec![Param {
+        optional,
+        ..Param::new("options", object_type(properties))
+    }];
+    with_doc(signature, doc)
+}
+
+/// The Typescript type of an object, given the name, type and optionality of
+/// its properties.
+pub fn object_type(properties: &[(&str, String, bool)]) -> String {
+    if properties.is_empty() {
+        return "{}".into();
+    }
+    let properties = properties
+        .iter()
+        .map(|(name, ty, optional)| {
+            let optional = if *optional { "?" } else { "" };
+            format!("{}{}: {}", property_name(name), optional, ty)
+        })
+        .collect::<Vec<_>>();
+    format!("{{ {} }}", properties.join("; "))
+}
+
+/// The Typescript type of an array with the given element types.
+pub fn tuple_type(elements: &[String]) -> String {
+    format!("[{}]", elements.join(", "))
+}
+
+/// The Typescript type of the variants of an enum.
+pub fn union_type(variants: &[String]) -> String {
+    if variants.is_empty() {
+        "never".into()
+    } else {
+        variants.join(" | ")
+    }
+}
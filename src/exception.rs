@@ -0,0 +1,109 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, Weak},
+};
+
+use crate::value::JsValue;
+
+/// A Javascript exception, returned as
+/// [ExecutionError::Exception](crate::ExecutionError::Exception).
+///
+/// An exception that was thrown by Javascript code keeps a handle to the
+/// original value, so a callback that returns the error rethrows the
+/// thrown value instead of its converted message.
+/// Exceptions are compared by their converted value.
+///
+/// ```rust
+/// use quick_js::{Context, ExecutionError, JsValue};
+///
+/// let context = Context::new().unwrap();
+/// match context.eval(" throw 'failed' ") {
+///     Err(ExecutionError::Exception(e)) => assert_eq!(e.value(), &JsValue::from("failed")),
+///     other => panic!("Expected an exception, got {:?}", other),
+/// }
+/// ```
+pub struct JsException {
+    value: JsValue,
+    handle: Option<ExceptionHandle>,
+}
+
+impl JsException {
+    pub(crate) fn thrown(value: JsValue, handle: ExceptionHandle) -> Self {
+        Self {
+            value,
+            handle: Some(handle),
+        }
+    }
+
+    /// The converted exception value.
+    pub fn value(&self) -> &JsValue {
+        &self.value
+    }
+
+    /// Take the converted exception value.
+    pub fn into_value(self) -> JsValue {
+        self.value
+    }
+
+    pub(crate) fn handle(&self) -> Option<&ExceptionHandle> {
+        self.handle.as_ref()
+    }
+}
+
+impl<T: Into<JsValue>> From<T> for JsException {
+    fn from(value: T) -> Self {
+        Self {
+            value: value.into(),
+            handle: None,
+        }
+    }
+}
+
+impl PartialEq for JsException {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl fmt::Debug for JsException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// Ids of thrown values whose handle was dropped, shared between a context
+/// and the handles of its exceptions.
+pub(crate) type ReleasedExceptions = Arc<Mutex<Vec<u64>>>;
+
+/// Refers to the original value of an exception, which is stored by the
+/// context that threw it.
+///
+/// The handle can be sent to other threads, so it only holds the id of the
+/// value. Dropping it marks the value as released, and the context frees it
+/// the next time it stores or rethrows an exception.
+pub(crate) struct ExceptionHandle {
+    pub(crate) id: u64,
+    released: Weak<Mutex<Vec<u64>>>,
+}
+
+impl ExceptionHandle {
+    pub(crate) fn new(id: u64, released: &ReleasedExceptions) -> Self {
+        Self {
+            id,
+            released: Arc::downgrade(released),
+        }
+    }
+
+    /// Whether the value is stored by the context with the given list.
+    pub(crate) fn belongs_to(&self, released: &ReleasedExceptions) -> bool {
+        std::ptr::eq(self.released.as_ptr(), Arc::as_ptr(released))
+    }
+}
+
+impl Drop for ExceptionHandle {
+    fn drop(&mut self) {
+        if let Some(released) = self.released.upgrade() {
+            released.lock().unwrap().push(self.id);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    cell::RefCell,
+    os::raw::{c_int, c_void},
+};
+
+use libquickjs_sys as q;
+
+use crate::bindings::{
+    deserialize_string, deserialize_value, free_value, serialize_value, ContextWrapper, TAG_STRING,
+};
+use crate::value::JsValue;
+use crate::ExecutionError;
+
+/// A Javascript object whose properties are provided by Rust.
+///
+/// Property reads, writes, `in` checks, deletes and key enumeration on the
+/// object are answered by the trait methods, so large Rust data structures
+/// can be browsed from Javascript without converting them up front.
+///
+/// Host objects are added with
+/// [Context::add_host_object](crate::Context::add_host_object).
+/// Properties not provided by the host object are looked up on
+/// `Object.prototype` as usual.
+///
+/// Property values are not cached, see [HostValue](HostValue) for the
+/// identity of nested objects.
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use quick_js::{Context, HostObject, HostValue};
+///
+/// struct Row(HashMap<String, String>);
+///
+/// impl HostObject for Row {
+///     fn get(&self, name: &str) -> Option<HostValue> {
+///         self.0.get(name).map(|value| value.clone().into())
+///     }
+///
+///     fn own_keys(&self) -> Vec<String> {
+///         self.0.keys().cloned().collect()
+///     }
+/// }
+///
+/// let mut row = HashMap::new();
+/// row.insert("name".to_string(), "quickjs".to_string());
+///
+/// let context = Context::new().unwrap();
+/// context.add_host_object("row", Row(row)).unwrap();
+///
+/// let value = context.eval_as::<String>(" row.name.toUpperCase() ").unwrap();
+/// assert_eq!(value, "QUICKJS");
+/// ```
+pub trait HostObject: 'static {
+    /// Get the value of a property.
+    ///
+    /// Returns `None` if the object has no such property.
+    fn get(&self, name: &str) -> Option<HostValue>;
+
+    /// Set the value of a property.
+    ///
+    /// An error is raised as a Javascript exception.
+    /// By default, host objects are read-only.
+    fn set(&mut self, name: &str, value: JsValue) -> Result<(), String> {
+        let _ = value;
+        Err(format!("Cannot set property {} of host object", name))
+    }
+
+    /// Check if the object has a property.
+    fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Delete a property.
+    ///
+    /// Returns `false` if the property can not be deleted.
+    fn delete(&mut self, name: &str) -> bool {
+        let _ = name;
+        false
+    }
+
+    /// The names of all properties, used for enumeration, for example by
+    /// `Object.keys` or `JSON.stringify`.
+    fn own_keys(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The value of a [HostObject](HostObject) property.
+///
+/// Values are converted on every property read, so reading the same property
+/// twice gives two different Javascript objects, and `table[0] === table[0]`
+/// is `false` for nested host objects and plain objects. Changes made to a
+/// plain object value are not written back to the host object.
+pub enum HostValue {
+    /// A plain value that is converted to Javascript.
+    Value(JsValue),
+    /// A nested host object.
+    ///
+    /// A new Javascript object is created for every read of the property.
+    Object(Box<dyn HostObject>),
+}
+
+impl HostValue {
+    /// Create a nested host object value.
+    pub fn object(object: impl HostObject) -> Self {
+        HostValue::Object(Box::new(object))
+    }
+}
+
+impl<T: Into<JsValue>> From<T> for HostValue {
+    fn from(value: T) -> Self {
+        HostValue::Value(value.into())
+    }
+}
+
+impl std::fmt::Debug for HostValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HostValue::Value(value) => f.debug_tuple("Value").field(value).finish(),
+            HostValue::Object(_) => write!(f, "Object(?)"),
+        }
+    }
+}
+
+type HostCell = RefCell<Box<dyn HostObject>>;
+
+/// Marker type for the class id of host objects.
+struct HostClass;
+
+static HOST_EXOTIC_METHODS: q::JSClassExoticMethods = q::JSClassExoticMethods {
+    get_own_property: Some(host_get_own_property),
+    get_own_property_names: Some(host_get_own_property_names),
+    delete_property: Some(host_delete_property),
+    define_own_property: None,
+    has_property: None,
+    get_property: None,
+    set_property: Some(host_set_property),
+};
+
+/// Create a new Javascript object for a host object.
+pub(crate) fn new_host_object(
+    context: *mut q::JSContext,
+    object: Box<dyn HostObject>,
+) -> Result<q::JSValue, ExecutionError> {
+    let class_id = crate::class::class_id::<HostClass>();
+    unsafe {
+        let runtime = q::JS_GetRuntime(context);
+        if q::JS_IsRegisteredClass(runtime, class_id) == 0 {
+            let def = q::JSClassDef {
+                class_name: b"HostObject\0".as_ptr() as *const _,
+                finalizer: Some(host_finalize),
+                gc_mark: None,
+                call: None,
+                // quickjs keeps the pointer, so the methods must be static.
+                exotic: &HOST_EXOTIC_METHODS as *const _ as *mut _,
+            };
+            if q::JS_NewClass(runtime, class_id, &def) < 0 {
+                return Err(ExecutionError::Internal(
+                    "Could not register host object class".into(),
+                ));
+            }
+        }
+
+        // New classes have no prototype, so Object.prototype is used
+        // explicitly.
+        let proto = q::JS_GetClassProto(context, q::JS_CLASS_OBJECT);
+        let value = q::JS_NewObjectProtoClass(context, proto, class_id);
+        free_value(context, proto);
+        if value.tag != q::JS_TAG_OBJECT as i64 {
+            return Err(ExecutionError::Internal(
+                "Could not create host object".into(),
+            ));
+        }
+        let cell: Box<HostCell> = Box::new(RefCell::new(object));
+        q::JS_SetOpaque(value, Box::into_raw(cell) as *mut c_void);
+        Ok(value)
+    }
+}
+
+unsafe fn host_cell<'a>(value: q::JSValue) -> Option<&'a HostCell> {
+    let ptr = q::JS_GetOpaque(value, crate::class::class_id::<HostClass>()) as *const HostCell;
+    ptr.as_ref()
+}
+
+/// Get the name of a property atom.
+///
+/// Returns `None` for symbols, which are not passed to host objects.
+unsafe fn atom_name(context: *mut q::JSContext, atom: q::JSAtom) -> Option<String> {
+    let value = q::JS_AtomToValue(context, atom);
+    let name = match value.tag {
+        TAG_STRING => deserialize_string(context, &value).ok(),
+        _ => None,
+    };
+    free_value(context, value);
+    name.map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Raise an error as an exception and return the error code.
+fn throw(context: *mut q::JSContext, error: impl Into<String>) -> c_int {
+    ContextWrapper::throw(context, ExecutionError::Exception(error.into().into()));
+    -1
+}
+
+/// Run a host object method, raising an exception if it panics.
+fn catch_panic(context: *mut q::JSContext, f: impl FnOnce() -> c_int) -> c_int {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(ret) => ret,
+        Err(payload) => {
+            let error = ContextWrapper::caught_panic(context, payload);
+            ContextWrapper::throw(context, error);
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn host_finalize(_rt: *mut q::JSRuntime, value: q::JSValue) {
+    let ptr = q::JS_GetOpaque(value, crate::class::class_id::<HostClass>()) as *mut HostCell;
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+unsafe extern "C" fn host_get_own_property(
+    context: *mut q::JSContext,
+    desc: *mut q::JSPropertyDescriptor,
+    obj: q::JSValue,
+    prop: q::JSAtom,
+) -> c_int {
+    catch_panic(context, || {
+        let (cell, name) = match (host_cell(obj), atom_name(context, prop)) {
+            (Some(cell), Some(name)) => (cell, name),
+            _ => return 0,
+        };
+        let object = match cell.try_borrow() {
+            Ok(object) => object,
+            Err(_) => return throw(context, "Host object is already in use"),
+        };
+
+        // Only existence is checked if no descriptor is requested.
+        if desc.is_null() {
+            return object.has(&name) as c_int;
+        }
+
+        let value = match object.get(&name) {
+            Some(value) => value,
+            None => return 0,
+        };
+        drop(object);
+        let value = match value {
+            HostValue::Value(value) => {
+                serialize_value(context, value).map_err(ExecutionError::from)
+            }
+            HostValue::Object(object) => new_host_object(context, object),
+        };
+        match value {
+            Ok(value) => {
+                *desc = q::JSPropertyDescriptor {
+                    flags: (q::JS_PROP_ENUMERABLE | q::JS_PROP_WRITABLE | q::JS_PROP_CONFIGURABLE)
+                        as c_int,
+                    value,
+                    getter: undefined(),
+                    setter: undefined(),
+                };
+                1
+            }
+            Err(e) => throw(context, e.to_string()),
+        }
+    })
+}
+
+unsafe extern "C" fn host_get_own_property_names(
+    context: *mut q::JSContext,
+    ptab: *mut *mut q::JSPropertyEnum,
+    plen: *mut u32,
+    obj: q::JSValue,
+) -> c_int {
+    catch_panic(context, || {
+        let keys = match host_cell(obj).map(|cell| cell.try_borrow()) {
+            Some(Ok(object)) => object.own_keys(),
+            Some(Err(_)) => return throw(context, "Host object is already in use"),
+            None => Vec::new(),
+        };
+
+        // The table is freed by quickjs, so it must be allocated by quickjs.
+        let size = std::mem::size_of::<q::JSPropertyEnum>() * keys.len().max(1);
+        let tab = q::js_malloc(context, size as _) as *mut q::JSPropertyEnum;
+        if tab.is_null() {
+            return -1;
+        }
+        for (index, key) in keys.iter().enumerate() {
+            let atom = q::JS_NewAtomLen(context, key.as_ptr() as *const _, key.len() as _);
+            // JS_ATOM_NULL, the exception is already pending.
+            if atom == 0 {
+                for created in 0..index {
+                    q::JS_FreeAtom(context, (*tab.add(created)).atom);
+                }
+                q::js_free(context, tab as *mut c_void);
+                return -1;
+            }
+            *tab.add(index) = q::JSPropertyEnum {
+                is_enumerable: 0,
+                atom,
+            };
+        }
+        *ptab = tab;
+        *plen = keys.len() as u32;
+        0
+    })
+}
+
+unsafe extern "C" fn host_delete_property(
+    context: *mut q::JSContext,
+    obj: q::JSValue,
+    prop: q::JSAtom,
+) -> c_int {
+    catch_panic(context, || {
+        let (cell, name) = match (host_cell(obj), atom_name(context, prop)) {
+            (Some(cell), Some(name)) => (cell, name),
+            _ => return 0,
+        };
+        match cell.try_borrow_mut() {
+            Ok(mut object) => object.delete(&name) as c_int,
+            Err(_) => throw(context, "Host object is already in use"),
+        }
+    })
+}
+
+unsafe extern "C" fn host_set_property(
+    context: *mut q::JSContext,
+    obj: q::JSValue,
+    prop: q::JSAtom,
+    value: q::JSValue,
+    _receiver: q::JSValue,
+    _flags: c_int,
+) -> c_int {
+    catch_panic(context, || {
+        let cell = match host_cell(obj) {
+            Some(cell) => cell,
+            None => return 0,
+        };
+        let name = match atom_name(context, prop) {
+            Some(name) => name,
+            None => return throw(context, "Cannot set symbol property of host object"),
+        };
+        let value = match deserialize_value(context, &value) {
+            Ok(value) => value,
+            Err(e) => return throw(context, e.to_string()),
+        };
+        let result = match cell.try_borrow_mut() {
+            Ok(mut object) => object.set(&name, value),
+            Err(_) => Err("Host object is already in use".to_string()),
+        };
+        match result {
+            Ok(()) => 1,
+            Err(e) => throw(context, e),
+        }
+    })
+}
+
+fn undefined() -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: 0 },
+        tag: q::JS_TAG_UNDEFINED as i64,
+    }
+}
@@ -36,32 +36,82 @@
 
 mod bindings;
 mod callback;
+mod class;
 pub mod console;
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod derive;
 mod droppable_value;
+mod exception;
+mod host;
+#[cfg(feature = "serde")]
+mod serde_js;
+mod throw;
+mod typescript;
 mod value;
 
-use std::{convert::TryFrom, error, fmt};
-
-pub use callback::{Arguments, Callback};
+use std::{cell::RefCell, convert::TryFrom, error, fmt, rc::Rc};
+
+pub use callback::{
+    Arguments, CallContext, Callback, CallbackMut, Constructor, FromJsArg, IntoJsReturn,
+    JsFunction, JsFunctionDef, JsObject, JsRef, Method, Rest,
+};
+pub use class::{ClassBuilder, JsClass, JsInstance};
+pub use exception::JsException;
+pub use host::{HostObject, HostValue};
+pub use throw::JsThrow;
+pub use typescript::{Param, Signature};
 pub use value::*;
 
+#[cfg(feature = "derive")]
+pub use quick_js_derive::{js_function, FromJs, IntoJs};
+
+// Allows the derive macros to refer to `::quick_js` inside this crate.
+#[cfg(feature = "derive")]
+extern crate self as quick_js;
+
 /// Error on Javascript execution.
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum ExecutionError {
     /// Code to be executed contained zero-bytes.
+    ///
+    /// No longer returned, code may contain zero bytes.
     InputWithZeroBytes,
     /// Value conversion failed. (either input arguments or result value).
     Conversion(ValueError),
     /// Internal error.
     Internal(String),
     /// JS Exception was thrown.
-    Exception(JsValue),
+    Exception(JsException),
     /// JS Runtime exceeded the memory limit.
     OutOfMemory,
+    /// A callback returned an error that was not caught by Javascript.
+    ///
+    /// This is the original error returned by the callback, which can be
+    /// recovered with `downcast_ref`.
+    /// Javascript code sees the error as an `Error` with the error message.
+    Host(Box<dyn error::Error + Send + Sync>),
     #[doc(hidden)]
     __NonExhaustive,
 }
 
+/// Host errors are compared by their message.
+impl PartialEq for ExecutionError {
+    fn eq(&self, other: &Self) -> bool {
+        use ExecutionError::*;
+        match (self, other) {
+            (InputWithZeroBytes, InputWithZeroBytes) => true,
+            (Conversion(a), Conversion(b)) => a == b,
+            (Internal(a), Internal(b)) => a == b,
+            (Exception(a), Exception(b)) => a == b,
+            (OutOfMemory, OutOfMemory) => true,
+            (Host(a), Host(b)) => a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for ExecutionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use ExecutionError::*;
@@ -71,12 +121,20 @@ impl fmt::Display for ExecutionError {
             Internal(e) => write!(f, "Internal error: {}", e),
             Exception(e) => write!(f, "{:?}", e),
             OutOfMemory => write!(f, "Out of memory: runtime memory limit exceeded"),
+            Host(e) => e.fmt(f),
             __NonExhaustive => unreachable!(),
         }
     }
 }
 
-impl error::Error for ExecutionError {}
+impl error::Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ExecutionError::Host(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<ValueError> for ExecutionError {
     fn from(v: ValueError) -> Self {
@@ -86,6 +144,7 @@ impl From<ValueError> for ExecutionError {
 
 /// Error on context creation.
 #[derive(Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum ContextError {
     /// Runtime could not be created.
     RuntimeCreationFailed,
@@ -117,6 +176,10 @@ impl error::Error for ContextError {}
 pub struct ContextBuilder {
     memory_limit: Option<usize>,
     console_backend: Option<Box<dyn console::ConsoleBackend>>,
+    resume_panics: bool,
+    symbol_keys: SymbolKeys,
+    max_value_depth: Option<usize>,
+    max_value_elements: Option<usize>,
 }
 
 impl ContextBuilder {
@@ -124,6 +187,10 @@ impl ContextBuilder {
         Self {
             memory_limit: None,
             console_backend: None,
+            resume_panics: false,
+            symbol_keys: SymbolKeys::default(),
+            max_value_depth: None,
+            max_value_elements: None,
         }
     }
 
@@ -152,9 +219,78 @@ impl ContextBuilder {
         self
     }
 
+    /// Resume panics of callbacks once Javascript execution returns.
+    ///
+    /// By default, a panic in a callback is raised as a Javascript exception
+    /// with the panic message.
+    /// With this option, Javascript execution is stopped instead, and the
+    /// panic is resumed with the original payload when `eval` or another
+    /// method that runs Javascript returns. This makes tests fail with the
+    /// real panic.
+    ///
+    /// ```rust,should_panic
+    /// use quick_js::Context;
+    ///
+    /// let context = Context::builder().resume_panics(true).build().unwrap();
+    /// context.add_callback("check", |value: i32| {
+    ///     assert_eq!(value, 1, "unexpected value");
+    ///     value
+    /// }).unwrap();
+    ///
+    /// // Panics with "unexpected value".
+    /// context.eval(" try { check(2) } catch (e) {} ").unwrap();
+    /// ```
+    pub fn resume_panics(mut self, resume: bool) -> Self {
+        self.resume_panics = resume;
+        self
+    }
+
+    /// Set how symbol-keyed properties are handled when converting objects
+    /// to [JsValue::Object](JsValue::Object).
+    ///
+    /// By default, they are skipped.
+    pub fn symbol_keys(mut self, policy: SymbolKeys) -> Self {
+        self.symbol_keys = policy;
+        self
+    }
+
+    /// Set the maximum nesting depth of arrays, objects and collections when
+    /// converting Javascript values to [JsValue](JsValue).
+    ///
+    /// Deeper values fail with
+    /// [ValueError::MaxDepthExceeded](ValueError::MaxDepthExceeded).
+    /// The default is 256.
+    pub fn max_value_depth(mut self, depth: usize) -> Self {
+        self.max_value_depth = Some(depth);
+        self
+    }
+
+    /// Set the maximum total number of array elements, object properties and
+    /// collection entries when converting a Javascript value to
+    /// [JsValue](JsValue).
+    ///
+    /// Larger values fail with
+    /// [ValueError::MaxElementsExceeded](ValueError::MaxElementsExceeded).
+    /// The default is 1,000,000. Array lengths are checked before reading the
+    /// elements, so sparse arrays count with their full length.
+    pub fn max_value_elements(mut self, elements: usize) -> Self {
+        self.max_value_elements = Some(elements);
+        self
+    }
+
     /// Finalize the builder and build a JS Context.
     pub fn build(self) -> Result<Context, ContextError> {
-        let wrapper = bindings::ContextWrapper::new(self.memory_limit)?;
+        let mut wrapper = bindings::ContextWrapper::new(self.memory_limit)?;
+        if self.resume_panics {
+            wrapper.set_resume_panics();
+        }
+        wrapper.symbol_keys = self.symbol_keys;
+        if let Some(depth) = self.max_value_depth {
+            wrapper.deserialize_limits.max_depth = depth;
+        }
+        if let Some(elements) = self.max_value_elements {
+            wrapper.deserialize_limits.max_elements = elements;
+        }
         if let Some(be) = self.console_backend {
             wrapper.set_console(be).map_err(ContextError::Execution)?;
         }
@@ -170,11 +306,11 @@ impl ContextBuilder {
 /// different contexts in different threads, but each
 /// `Context` instance must be used only from a single thread.
 pub struct Context {
-    wrapper: bindings::ContextWrapper,
+    wrapper: Box<bindings::ContextWrapper>,
 }
 
 impl Context {
-    fn from_wrapper(wrapper: bindings::ContextWrapper) -> Self {
+    fn from_wrapper(wrapper: Box<bindings::ContextWrapper>) -> Self {
         Self { wrapper }
     }
 
@@ -277,6 +413,136 @@ impl Context {
         Ok(ret)
     }
 
+    /// Evaluates Javascript code and returns a reference to the value of the
+    /// final expression, without converting it.
+    ///
+    /// Promises are resolved just like with [eval](Context::eval).
+    pub fn eval_ref(&self, code: &str) -> Result<JsRef, ExecutionError> {
+        let value_raw = self.wrapper.eval(code)?;
+        Ok(JsRef {
+            value: self.wrapper.persistent_owned(value_raw),
+        })
+    }
+
+    /// Set a global variable to a referenced value.
+    pub fn set_global(&self, name: &str, value: &JsRef) -> Result<(), ExecutionError> {
+        self.wrapper.set_global(name, &value.value)
+    }
+
+    /// Parse a JSON string with the quickjs JSON parser.
+    ///
+    /// Parses like `JSON.parse`, which is faster than parsing the JSON in
+    /// Rust and converting the result.
+    ///
+    /// ```rust
+    /// use quick_js::{Context, JsValue};
+    /// let context = Context::new().unwrap();
+    ///
+    /// let value = context.parse_json(r#"{ "list": [1, 2.5, null] }"#).unwrap();
+    /// assert_eq!(
+    ///     value,
+    ///     JsValue::Object(
+    ///         vec![(
+    ///             "list".to_string(),
+    ///             vec![JsValue::Int(1), JsValue::Float(2.5), JsValue::Null].into(),
+    ///         )]
+    ///         .into_iter()
+    ///         .collect(),
+    ///     ),
+    /// );
+    ///
+    /// assert!(context.parse_json("{ list: [] }").is_err());
+    /// ```
+    pub fn parse_json(&self, json: &str) -> Result<JsValue, ExecutionError> {
+        let value = self.wrapper.parse_json(json)?;
+        Ok(value.to_value()?)
+    }
+
+    /// Parse a JSON string like [parse_json](Context::parse_json), and return
+    /// a reference to the value without converting it.
+    pub fn parse_json_ref(&self, json: &str) -> Result<JsRef, ExecutionError> {
+        let value = self.wrapper.parse_json(json)?;
+        Ok(JsRef {
+            value: self.wrapper.persistent_owned(value),
+        })
+    }
+
+    /// Convert a value to a JSON string with `JSON.stringify`.
+    ///
+    /// `indent` is the number of spaces used to indent nested values, at most
+    /// 10. With `None`, the JSON is not pretty-printed. Fails with
+    /// `ValueError::UnexpectedType` for values that have no JSON
+    /// representation, like `undefined`. See also
+    /// [JsRef::json_stringify](JsRef::json_stringify).
+    ///
+    /// ```rust
+    /// use quick_js::{Context, JsValue};
+    /// let context = Context::new().unwrap();
+    ///
+    /// let value = JsValue::Array(vec![JsValue::Int(1), JsValue::Undefined]);
+    /// assert_eq!(context.json_stringify(value.clone(), None).unwrap(), "[1,null]");
+    /// assert_eq!(
+    ///     context.json_stringify(value, Some(2)).unwrap(),
+    ///     "[\n  1,\n  null\n]",
+    /// );
+    /// ```
+    pub fn json_stringify(
+        &self,
+        value: JsValue,
+        indent: Option<usize>,
+    ) -> Result<String, ExecutionError> {
+        self.wrapper.serialize_value(value)?.json_stringify(indent)
+    }
+
+    /// Convert a Rust value to Javascript with serde.
+    ///
+    /// The value is converted directly, without building a
+    /// [JsValue](JsValue) first. Structs and maps become objects, sequences
+    /// become arrays and enums are externally tagged, like in `serde_json`.
+    /// Integers outside of the safe range of Javascript numbers become a
+    /// `BigInt`, and bytes become an `ArrayBuffer`.
+    ///
+    /// Requires the `serde` feature.
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    /// struct Point {
+    ///     x: i32,
+    ///     y: i32,
+    /// }
+    ///
+    /// let context = Context::new().unwrap();
+    /// let point = context.to_js(&Point { x: 1, y: 2 }).unwrap();
+    /// context.set_global("point", &point).unwrap();
+    ///
+    /// let moved = context.eval_ref(" ({ x: point.x + 10, y: point.y }) ").unwrap();
+    /// assert_eq!(context.from_js::<Point>(&moved).unwrap(), Point { x: 11, y: 2 });
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn to_js<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<JsRef, ExecutionError> {
+        Ok(JsRef {
+            value: self.wrapper.to_js(value)?,
+        })
+    }
+
+    /// Convert a Javascript value to a Rust value with serde.
+    ///
+    /// The value is converted directly, without building a
+    /// [JsValue](JsValue) first, see [to_js](Context::to_js).
+    /// Integral numbers are accepted for integer fields.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn from_js<T: serde::de::DeserializeOwned>(
+        &self,
+        value: &JsRef,
+    ) -> Result<T, ExecutionError> {
+        value.value.deserialize()
+    }
+
     /// Call a global function in the Javascript namespace.
     ///
     /// **Promises**:
@@ -300,35 +566,33 @@ impl Context {
         function_name: &str,
         args: impl IntoIterator<Item = impl Into<JsValue>>,
     ) -> Result<JsValue, ExecutionError> {
-        let qargs = args
-            .into_iter()
-            .map(|arg| self.wrapper.serialize_value(arg.into()))
-            .collect::<Result<Vec<_>, _>>()?;
-
-        let global = self.wrapper.global()?;
-        let func_obj = global.property(function_name)?;
-
-        if !func_obj.is_object() {
-            return Err(ExecutionError::Internal(format!(
-                "Could not find function '{}' in global scope: does not exist, or not an object",
-                function_name
-            )));
-        }
-
-        let value = self.wrapper.call_function(func_obj, qargs)?.to_value()?;
-        Ok(value)
+        let args = args.into_iter().map(|arg| arg.into()).collect();
+        self.wrapper.call_global_function(function_name, args)
     }
 
     /// Add a global JS function that is backed by a Rust function or closure.
     ///
     /// The callback must satisfy several requirements:
-    /// * accepts 0 - 5 arguments
-    /// * each argument must be convertible from a JsValue
+    /// * accepts 0 - 12 arguments
+    /// * each argument must implement [FromJsArg](FromJsArg), or be a
+    ///   [JsFunction](JsFunction) or [JsObject](JsObject)
+    /// * trailing `Option<T>` arguments may be omitted by the caller
+    /// * a final [Rest<T>](Rest) argument collects all remaining arguments
+    /// * may take a [&CallContext](CallContext) as an additional first
+    ///   argument, which gives access to the context while executing
     /// * must return a value
     /// * the return value must either:
-    ///   - be convertible to JsValue
-    ///   - be a Result<T, E> where T is convertible to JsValue
-    ///     if Err(e) is returned, a Javascript exception will be raised
+    ///   - implement [IntoJsReturn](IntoJsReturn)
+    ///   - be a Result<T, E> where T implements [IntoJsReturn](IntoJsReturn)
+    ///     and E converts into `Box<dyn Error + Send + Sync>`.
+    ///     If Err(e) is returned, a Javascript `Error` will be raised.
+    ///     If the error is not caught, it is returned as
+    ///     [ExecutionError::Host](ExecutionError::Host), which holds the
+    ///     original error.
+    ///     An [ExecutionError](ExecutionError) of a nested call is raised
+    ///     unchanged, so exceptions propagate with `?`.
+    ///     Return a [JsThrow](JsThrow) error to raise a `TypeError` or another
+    ///     error class instead.
     ///
     /// ```rust
     /// use quick_js::{Context, JsValue};
@@ -352,11 +616,254 @@ impl Context {
     ) -> Result<(), ExecutionError> {
         self.wrapper.add_callback(name, callback)
     }
+
+    /// Add a global JS function that is backed by a Rust `FnMut` function or
+    /// closure.
+    ///
+    /// This allows callbacks to keep mutable state without a `RefCell` or
+    /// `Mutex`. The requirements are the same as for
+    /// [add_callback](Context::add_callback). Calling the function
+    /// recursively from Javascript raises an exception.
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// let context = Context::new().unwrap();
+    ///
+    /// let mut count = 0;
+    /// context.add_callback_mut("next", move || {
+    ///     count += 1;
+    ///     count
+    /// }).unwrap();
+    ///
+    /// let value = context.eval_as::<i32>(" next(); next(); next() ").unwrap();
+    /// assert_eq!(value, 3);
+    /// ```
+    pub fn add_callback_mut<F>(
+        &self,
+        name: &str,
+        callback: impl CallbackMut<F> + 'static,
+    ) -> Result<(), ExecutionError> {
+        self.wrapper.add_callback_mut(name, callback)
+    }
+
+    /// Remove a global JS function that was added with
+    /// [add_callback](Context::add_callback).
+    ///
+    /// The callback closure is dropped once Javascript no longer references
+    /// the function. Closures that keep a [JsFunction](JsFunction) or
+    /// [JsObject](JsObject) referencing the function are only dropped with
+    /// the context.
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// let context = Context::new().unwrap();
+    ///
+    /// context.add_callback("add", |a: i32, b: i32| a + b).unwrap();
+    /// context.remove_callback("add").unwrap();
+    /// assert_eq!(context.eval_as::<bool>(" typeof add === 'undefined' "), Ok(true));
+    /// ```
+    pub fn remove_callback(&self, name: &str) -> Result<(), ExecutionError> {
+        self.wrapper.remove_callback(name)
+    }
+
+    /// Add a global JS constructor that is backed by a Rust function or
+    /// closure.
+    ///
+    /// The callback has the same requirements as for
+    /// [add_callback](Context::add_callback). It is executed with a newly
+    /// created object as `this`, which can be accessed via
+    /// [CallContext::this_object](CallContext::this_object).
+    /// If the callback returns an object, its properties are assigned to the
+    /// new instance. Other return values are ignored.
+    ///
+    /// The constructor must be called with `new`.
+    ///
+    /// ```rust
+    /// use std::{collections::HashMap, convert::TryInto};
+    /// use quick_js::{CallContext, Context};
+    /// let context = Context::new().unwrap();
+    ///
+    /// context.add_constructor("Point", |x: i32, y: i32| {
+    ///     let mut props = HashMap::new();
+    ///     props.insert("x", x);
+    ///     props.insert("y", y);
+    ///     props
+    /// }).unwrap();
+    ///
+    /// // Methods receive the instance as `this`.
+    /// context.add_callback("pointSum", |ctx: &CallContext| {
+    ///     let this = ctx.this_object().ok_or("not a Point")?;
+    ///     let x: i32 = this.property("x")?.try_into()?;
+    ///     let y: i32 = this.property("y")?.try_into()?;
+    ///     Ok::<_, Box<dyn std::error::Error + Send + Sync>>(x + y)
+    /// }).unwrap();
+    /// context.eval(" Point.prototype.sum = pointSum ").unwrap();
+    ///
+    /// let sum = context.eval_as::<i32>(" new Point(1, 2).sum() ").unwrap();
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn add_constructor<F>(
+        &self,
+        name: &str,
+        callback: impl Callback<F> + 'static,
+    ) -> Result<(), ExecutionError> {
+        self.wrapper.add_constructor(name, callback)
+    }
+
+    /// Register a Rust type as a Javascript class.
+    ///
+    /// The class constructor is added to the global object under
+    /// [JsClass::NAME](JsClass::NAME).
+    /// See [JsClass](JsClass) for an example.
+    pub fn add_class<T: JsClass>(&self) -> Result<(), ExecutionError> {
+        self.wrapper.add_class::<T>()
+    }
+
+    /// Add a global object whose properties are provided by Rust.
+    ///
+    /// See [HostObject](HostObject) for an example.
+    pub fn add_host_object(
+        &self,
+        name: &str,
+        object: impl HostObject,
+    ) -> Result<(), ExecutionError> {
+        self.wrapper.add_host_object(name, object)
+    }
+
+    /// Generate Typescript declarations for the registered globals.
+    ///
+    /// Covers callbacks, constructors, classes and host objects. Types are
+    /// derived from the Rust argument and return types with
+    /// [FromJsArg::ts_type](FromJsArg::ts_type) and
+    /// [IntoJsReturn::ts_type](IntoJsReturn::ts_type), parameters are
+    /// named `arg0`, `arg1`, ... unless they were declared with
+    /// [declare_function](Context::declare_function).
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// let context = Context::new().unwrap();
+    ///
+    /// context.add_callback("repeat", |s: String, n: Option<i32>| s.repeat(n.unwrap_or(1) as usize)).unwrap();
+    ///
+    /// assert_eq!(
+    ///     context.typescript_declarations(),
+    ///     "declare function repeat(arg0: string, arg1?: number | null): string;\n",
+    /// );
+    /// ```
+    pub fn typescript_declarations(&self) -> String {
+        self.wrapper.declarations.lock().unwrap().render()
+    }
+
+    /// Set the Typescript declaration of a global function.
+    ///
+    /// Replaces the signature derived from the callback, for example to
+    /// name the parameters and add documentation.
+    pub fn declare_function(&self, name: &str, signature: Signature) {
+        self.wrapper.declare(|d| d.add_function(name, signature));
+    }
+
+    /// Store user data of type `T` in the context.
+    ///
+    /// Callbacks can access the data via
+    /// [CallContext::data](CallContext::data), without capturing it.
+    /// Data of the same type is replaced. The data is kept when the context
+    /// is reset.
+    ///
+    /// The data is wrapped in a `RefCell`, so callbacks can modify it.
+    ///
+    /// ```rust
+    /// use quick_js::{CallContext, Context};
+    ///
+    /// struct RequestId(u32);
+    /// struct Counter(i32);
+    ///
+    /// let context = Context::new().unwrap();
+    /// context.set_data(RequestId(7));
+    /// context.set_data(Counter(0));
+    /// context.add_callback("requestId", |ctx: &CallContext| {
+    ///     ctx.data::<RequestId>().map(|id| id.borrow().0 as i32)
+    /// }).unwrap();
+    /// context.add_callback("count", |ctx: &CallContext| {
+    ///     let counter = ctx.data::<Counter>().unwrap();
+    ///     let mut counter = counter.borrow_mut();
+    ///     counter.0 += 1;
+    ///     counter.0
+    /// }).unwrap();
+    ///
+    /// assert_eq!(context.eval_as::<i32>(" requestId() "), Ok(7));
+    /// assert_eq!(context.eval_as::<i32>(" count(); count() "), Ok(2));
+    /// assert_eq!(context.data::<Counter>().unwrap().borrow().0, 2);
+    /// ```
+    pub fn set_data<T: 'static>(&self, value: T) {
+        self.wrapper.set_data(value)
+    }
+
+    /// Get the user data of type `T` stored in the context.
+    ///
+    /// The `RefCell` is shared with callbacks, so a borrow must not be held
+    /// while calling into Javascript if a callback also borrows the data.
+    pub fn data<T: 'static>(&self) -> Option<Rc<RefCell<T>>> {
+        self.wrapper.data::<T>()
+    }
+
+    /// Add a Rust closure to the job queue.
+    ///
+    /// The job will be executed the next time pending jobs are processed,
+    /// either explicitly via [run_pending_jobs](Context::run_pending_jobs)
+    /// or implicitly while `eval` or `call_function` wait for a promise.
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// let context = Context::new().unwrap();
+    ///
+    /// context.enqueue_job(|| println!("job executed")).unwrap();
+    /// assert!(context.has_pending_jobs());
+    ///
+    /// assert_eq!(context.run_pending_jobs(None), Ok(1));
+    /// assert!(!context.has_pending_jobs());
+    /// ```
+    pub fn enqueue_job<F>(&self, job: F) -> Result<(), ExecutionError>
+    where
+        F: FnOnce() + 'static,
+    {
+        self.wrapper.enqueue_job(job)
+    }
+
+    /// Check if the job queue contains pending jobs.
+    ///
+    /// Jobs are queued by promise reactions, `queueMicrotask` and
+    /// [enqueue_job](Context::enqueue_job).
+    pub fn has_pending_jobs(&self) -> bool {
+        self.wrapper.has_pending_jobs()
+    }
+
+    /// Execute pending jobs.
+    ///
+    /// Jobs are executed until the queue is empty, or until `limit` jobs have
+    /// been executed if a limit is given. Jobs queued while running are
+    /// executed as well.
+    ///
+    /// Returns the number of executed jobs, or the exception thrown by a job.
+    ///
+    /// ```rust
+    /// use quick_js::Context;
+    /// let context = Context::new().unwrap();
+    ///
+    /// context.eval(" queueMicrotask(() => {}); queueMicrotask(() => {}); ").unwrap();
+    /// assert_eq!(context.run_pending_jobs(Some(1)), Ok(1));
+    /// assert_eq!(context.run_pending_jobs(None), Ok(1));
+    /// ```
+    pub fn run_pending_jobs(&self, limit: Option<usize>) -> Result<usize, ExecutionError> {
+        self.wrapper.run_pending_jobs(limit)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{
+        collections::HashMap,
+        convert::{TryFrom, TryInto},
+    };
 
     use super::*;
 
@@ -379,6 +886,7 @@ mod tests {
     // }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_eval_pass() {
         use std::iter::FromIterator;
 
@@ -386,6 +894,7 @@ mod tests {
 
         let cases = vec![
             ("null", Ok(JsValue::Null)),
+            ("undefined", Ok(JsValue::Undefined)),
             ("true", Ok(JsValue::Bool(true))),
             ("2 > 10", Ok(JsValue::Bool(false))),
             ("1", Ok(JsValue::Int(1))),
@@ -406,19 +915,19 @@ mod tests {
         let obj_cases = vec![
             (
                 r#" {"a": null} "#,
-                Ok(JsValue::Object(HashMap::from_iter(vec![(
+                Ok(JsValue::Object(JsObjectMap::from_iter(vec![(
                     "a".to_string(),
                     JsValue::Null,
                 )]))),
             ),
             (
                 r#" {a: 1, b: true, c: {c1: false}} "#,
-                Ok(JsValue::Object(HashMap::from_iter(vec![
+                Ok(JsValue::Object(JsObjectMap::from_iter(vec![
                     ("a".to_string(), JsValue::Int(1)),
                     ("b".to_string(), JsValue::Bool(true)),
                     (
                         "c".to_string(),
-                        JsValue::Object(HashMap::from_iter(vec![(
+                        JsValue::Object(JsObjectMap::from_iter(vec![(
                             "c1".to_string(),
                             JsValue::Bool(false),
                         )])),
@@ -456,290 +965,2174 @@ mod tests {
     }
 
     #[test]
-    fn test_eval_syntax_error() {
+    fn test_undefined() {
         let c = Context::new().unwrap();
+
+        // Undefined and null survive round trips.
+        c.add_callback("identity", |value: JsValue| value).unwrap();
         assert_eq!(
-            c.eval(
-                r#"
-                !!!!
-            "#
-            ),
-            Err(ExecutionError::Exception(
-                "SyntaxError: unexpected token in expression: \'\'".into()
-            ))
+            c.eval_as::<bool>(" identity(undefined) === undefined && identity(null) === null "),
+            Ok(true),
+        );
+        assert_eq!(
+            c.eval_as::<String>(" JSON.stringify(identity({ a: undefined, b: null })) "),
+            Ok(r#"{"b":null}"#.to_string()),
         );
-    }
 
-    #[test]
-    fn test_eval_exception() {
-        let c = Context::new().unwrap();
+        // Optional arguments accept both, `None` is returned as `null`.
+        c.add_callback("optional", |value: Option<i32>| value)
+            .unwrap();
+        assert_eq!(c.eval(" optional(undefined) "), Ok(JsValue::Null));
+        assert_eq!(c.eval(" optional(null) "), Ok(JsValue::Null));
+        assert_eq!(c.eval(" optional(1) "), Ok(JsValue::Int(1)));
+        assert_eq!(c.eval(" optional() "), Ok(JsValue::Null));
+
+        // So do `Option<T>` conversions.
+        assert_eq!(c.eval_as::<Option<i32>>(" undefined "), Ok(None));
+        assert_eq!(c.eval_as::<Option<i32>>(" null "), Ok(None));
+        assert_eq!(c.eval_as::<Option<i32>>(" 1 "), Ok(Some(1)));
         assert_eq!(
-            c.eval(
-                r#"
-                function f() {
-                    throw new Error("My Error");
-                }
-                f();
-            "#
-            ),
-            Err(ExecutionError::Exception("Error: My Error".into(),))
+            c.eval_as::<Vec<Option<String>>>(" ['a', null, undefined] "),
+            Ok(vec![Some("a".to_string()), None, None]),
         );
+
+        assert!(JsValue::Undefined.is_nullish());
+        assert!(!JsValue::Bool(false).is_nullish());
     }
 
     #[test]
-    fn eval_async() {
+    fn test_typed_arrays() {
         let c = Context::new().unwrap();
 
-        let value = c
-            .eval(
-                r#"
-            new Promise((resolve, _) => {
-                resolve(33);
-            })
-       "#,
-            )
+        // Buffers from Rust.
+        c.add_callback("bytes", |len: i32| {
+            JsValue::ArrayBuffer((0..len as u8).collect())
+        })
+        .unwrap();
+        c.add_callback("floats", || TypedArray::from(vec![0.5f64, 1.5]))
             .unwrap();
-        assert_eq!(value, JsValue::Int(33));
+        assert_eq!(
+            c.eval_as::<i32>(" new Uint8Array(bytes(4)).reduce((a, b) => a + b) "),
+            Ok(6)
+        );
+        assert_eq!(
+            c.eval_as::<bool>(" floats() instanceof Float64Array && floats()[1] === 1.5 "),
+            Ok(true)
+        );
 
-        let res = c.eval(
-            r#"
-            new Promise((_resolve, reject) => {
-                reject("Failed...");
-            })
-       "#,
+        // Buffers from Javascript.
+        assert_eq!(
+            c.eval(" new Uint8Array([1, 2, 3]).buffer "),
+            Ok(JsValue::ArrayBuffer(vec![1, 2, 3]))
         );
         assert_eq!(
-            res,
-            Err(ExecutionError::Exception(JsValue::String(
-                "Failed...".into()
-            )))
+            c.eval(" new Int16Array([1, -2, 3, 4]).subarray(1, 3) "),
+            Ok(TypedArray::Int16(vec![-2, 3]).into())
+        );
+        assert_eq!(
+            c.eval(" new Uint8ClampedArray([300]) "),
+            Ok(TypedArray::Uint8Clamped(vec![255]).into())
+        );
+        assert_eq!(
+            c.eval(" new Float32Array([0.25]) "),
+            Ok(TypedArray::Float32(vec![0.25]).into())
+        );
+        assert_eq!(
+            c.eval(" new BigUint64Array([1n]) "),
+            Ok(TypedArray::BigUint64(vec![1]).into())
+        );
+        assert_eq!(
+            c.eval(" new Uint8Array([1, 2]) ").unwrap().as_bytes(),
+            Some(&[1u8, 2][..])
+        );
+
+        // Round trip.
+        c.add_callback("identity", |value: JsValue| value).unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" identity(new Uint16Array([1, 65535])).join() "),
+            Ok("1,65535".to_string())
         );
     }
 
     #[test]
-    fn test_call() {
+    fn test_map_set_symbol() {
         let c = Context::new().unwrap();
 
         assert_eq!(
-            c.call_function("parseInt", vec!["22"]).unwrap(),
-            JsValue::Int(22),
+            c.eval(" new Map([[1, 'a'], ['b', [2]]]) "),
+            Ok(JsValue::Map(vec![
+                (JsValue::Int(1), "a".into()),
+                ("b".into(), JsValue::Array(vec![JsValue::Int(2)])),
+            ]))
         );
-
+        assert_eq!(
+            c.eval(" new Set([3, 1, 3]) "),
+            Ok(JsValue::Set(vec![JsValue::Int(3), JsValue::Int(1)]))
+        );
+        // Collections, symbols and typed arrays are converted with the
+        // builtin functions, which Javascript code can not replace.
+        let other = Context::new().unwrap();
+        other
+            .add_callback("identity", |value: JsValue| value)
+            .unwrap();
+        other
+            .eval(
+                r"
+                var m = new Map([[1, 2]]), s = new Set([1]), y = Symbol('a');
+                var U = Uint8Array;
+                Array.from = () => 5;
+                Object.getPrototypeOf(new Map().entries()).next = () => ({ done: true });
+                Object.getPrototypeOf(new Set().values()).next = () => ({ done: true });
+                Map.prototype.entries = Map.prototype[Symbol.iterator] = function* () {};
+                Set.prototype.values = Set.prototype[Symbol.iterator] = function* () {};
+                Map.prototype.set = Set.prototype.add = () => {};
+                Object.defineProperty(Symbol.prototype, 'description', { get: () => 'x' });
+                delete globalThis.Map;
+                delete globalThis.Set;
+                delete globalThis.Symbol;
+                delete globalThis.Uint8Array;
+                ",
+            )
+            .unwrap();
+        assert_eq!(
+            other.eval(" m "),
+            Ok(JsValue::Map(vec![(JsValue::Int(1), JsValue::Int(2))]))
+        );
+        assert_eq!(other.eval(" s "), Ok(JsValue::Set(vec![JsValue::Int(1)])));
+        assert_eq!(
+            other.eval(" y "),
+            Ok(JsValue::Symbol(JsSymbol::new(Some("a".into()))))
+        );
+        assert_eq!(other.eval_as::<i32>(" identity(m).size "), Ok(1));
+        assert_eq!(other.eval_as::<i32>(" identity(s).size "), Ok(1));
+        assert_eq!(
+            other.eval_as::<String>(" identity(y).toString() "),
+            Ok("Symbol(a)".to_string())
+        );
+        assert_eq!(
+            other.eval_as::<bool>(" identity(new U([1])) instanceof U "),
+            Ok(true)
+        );
+        assert_eq!(
+            c.eval(" Symbol('a') "),
+            Ok(JsValue::Symbol(JsSymbol::new(Some("a".into()))))
+        );
+        assert_eq!(
+            c.eval(" Symbol.for('b') "),
+            Ok(JsValue::Symbol(JsSymbol::registered("b")))
+        );
+        assert_eq!(
+            c.eval(" Symbol() "),
+            Ok(JsValue::Symbol(JsSymbol::new(None)))
+        );
+
+        // Round trips.
+        c.add_callback("identity", |value: JsValue| value).unwrap();
+        assert_eq!(
+            c.eval_as::<i32>(" identity(new Map([[{}, 2]])).values().next().value "),
+            Ok(2)
+        );
+        assert_eq!(
+            c.eval_as::<bool>(" identity(new Set([1])).has(1) "),
+            Ok(true)
+        );
+        assert_eq!(
+            c.eval_as::<bool>(" identity(Symbol.for('b')) === Symbol.for('b') "),
+            Ok(true)
+        );
+        assert_eq!(
+            c.eval_as::<String>(" identity(Symbol('c')).toString() "),
+            Ok("Symbol(c)".to_string())
+        );
+
+        // Symbol keys are skipped by default.
+        let code = " ({ a: 1, [Symbol('s')]: 2 }) ";
+        let mut expected = JsObjectMap::new();
+        expected.insert("a".to_string(), JsValue::Int(1));
+        assert_eq!(c.eval(code), Ok(JsValue::Object(expected)));
+
+        let c = Context::builder()
+            .symbol_keys(SymbolKeys::Error)
+            .build()
+            .unwrap();
+        assert_eq!(
+            c.eval(code),
+            Err(ExecutionError::Conversion(ValueError::SymbolKey(
+                JsSymbol::new(Some("s".into()))
+            )))
+        );
+    }
+
+    #[test]
+    fn test_value_limits() {
+        let c = Context::new().unwrap();
+
+        let cyclic = Err(ExecutionError::Conversion(ValueError::CyclicValue));
+        assert_eq!(c.eval(" var a = {}; a.self = a; a "), cyclic);
+        assert_eq!(c.eval(" var b = [1]; b.push([b]); b "), cyclic);
+        assert_eq!(c.eval(" var m = new Map(); m.set('m', m); m "), cyclic);
+
+        // Shared references are not cycles.
+        let value = c.eval(" var s = [1]; [s, s] ").unwrap();
+        let s = JsValue::Array(vec![JsValue::Int(1)]);
+        assert_eq!(value, JsValue::Array(vec![s.clone(), s]));
+
+        // Cycles are still rejected by callbacks.
+        c.add_callback("identity", |value: JsValue| value).unwrap();
+        assert!(c.eval(" identity(a) ").is_err());
+
+        let c = Context::builder()
+            .max_value_depth(2)
+            .max_value_elements(3)
+            .build()
+            .unwrap();
+        assert!(c.eval(" [[1]] ").is_ok());
+        assert_eq!(
+            c.eval(" [[[1]]] "),
+            Err(ExecutionError::Conversion(ValueError::MaxDepthExceeded))
+        );
+        assert!(c.eval(" ({ a: 1, b: [2] }) ").is_ok());
+        assert_eq!(
+            c.eval(" ({ a: 1, b: [2, 3] }) "),
+            Err(ExecutionError::Conversion(ValueError::MaxElementsExceeded))
+        );
+
+        // Huge sparse arrays fail before their elements are read.
+        let c = Context::new().unwrap();
+        assert_eq!(
+            c.eval(" new Array(2 ** 31 - 1) "),
+            Err(ExecutionError::Conversion(ValueError::MaxElementsExceeded))
+        );
+        assert_eq!(
+            c.eval_as::<Vec<JsValue>>(" new Array(1_000_001) "),
+            Err(ExecutionError::Conversion(ValueError::MaxElementsExceeded))
+        );
+        assert_eq!(
+            c.eval(" new Array(3) ").unwrap(),
+            JsValue::Array(vec![JsValue::Undefined; 3])
+        );
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn test_object_order() {
+        let c = Context::new().unwrap();
+
+        let value = c.eval(" ({ z: 1, a: 2, m: 3 }) ").unwrap();
+        let keys: Vec<String> = match value {
+            JsValue::Object(map) => map.keys().cloned().collect(),
+            _ => panic!("Expected JsValue::Object"),
+        };
+        assert_eq!(keys, vec!["z", "a", "m"]);
+
+        c.add_callback("identity", |value: JsValue| value).unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" JSON.stringify(identity({ z: 1, a: { y: 2, b: 3 } })) "),
+            Ok(r#"{"z":1,"a":{"y":2,"b":3}}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_js_ref() {
+        let c = Context::new().unwrap();
+
+        let object = c.eval_ref(" ({ a: 1 }) ").unwrap();
+        c.set_global("object", &object).unwrap();
+        c.eval(" object.b = 2 ").unwrap();
+        let mut expected = JsObjectMap::new();
+        expected.insert("a".to_string(), JsValue::Int(1));
+        expected.insert("b".to_string(), JsValue::Int(2));
+        assert_eq!(object.to_value(), Ok(JsValue::Object(expected)));
+
+        c.add_callback("same", |a: JsRef, b: JsRef| {
+            let c = Context::new().unwrap();
+            c.set_global("a", &a).is_err() && a.to_value() == b.to_value()
+        })
+        .unwrap();
+        assert_eq!(c.eval_as::<bool>(" same(object, object) "), Ok(true));
+    }
+
+    #[test]
+    fn test_json() {
+        let c = Context::new().unwrap();
+
+        assert_eq!(
+            c.parse_json(r#" [1, "a", true, null, { "b": 1.5 }] "#),
+            Ok(JsValue::Array(vec![
+                JsValue::Int(1),
+                JsValue::String("a".into()),
+                JsValue::Bool(true),
+                JsValue::Null,
+                JsValue::Object(
+                    vec![("b".to_string(), JsValue::Float(1.5))]
+                        .into_iter()
+                        .collect()
+                ),
+            ]))
+        );
+        match c.parse_json("[1,") {
+            Err(ExecutionError::Exception(e)) => {
+                let message = e.value().as_str().unwrap();
+                assert!(message.starts_with("SyntaxError"), "{}", message)
+            }
+            other => panic!("Expected a SyntaxError, got {:?}", other),
+        }
+
+        let value = c.parse_json_ref(r#"{ "items": [1, 2] }"#).unwrap();
+        c.set_global("payload", &value).unwrap();
+        c.eval(" payload.items.push(3); payload.date = new Date(0) ")
+            .unwrap();
+        assert_eq!(
+            value.json_stringify(None),
+            Ok(r#"{"items":[1,2,3],"date":"1970-01-01T00:00:00.000Z"}"#.into())
+        );
+        assert_eq!(
+            value.json_stringify(Some(1)),
+            Ok("{\n \"items\": [\n  1,\n  2,\n  3\n ],\n \"date\": \"1970-01-01T00:00:00.000Z\"\n}".into())
+        );
+
+        assert_eq!(
+            c.json_stringify(JsValue::Undefined, None),
+            Err(ExecutionError::Conversion(ValueError::UnexpectedType))
+        );
+        let value = c.eval_ref(" var a = []; a.push(a); a ").unwrap();
+        match value.json_stringify(None) {
+            Err(ExecutionError::Exception(e)) => {
+                let message = e.value().as_str().unwrap();
+                assert!(message.starts_with("TypeError"), "{}", message)
+            }
+            other => panic!("Expected a TypeError, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Shape {
+            Empty,
+            Circle(f64),
+            Line(i32, i32),
+            Rect { width: u32, height: u32 },
+        }
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Drawing {
+            name: String,
+            shapes: Vec<Shape>,
+            tags: HashMap<String, Option<bool>>,
+            ids: HashMap<u32, char>,
+            big: u64,
+            #[serde(with = "bytes")]
+            data: Vec<u8>,
+        }
+
+        mod bytes {
+            pub fn serialize<S: serde::Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_bytes(v)
+            }
+
+            pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+                d: D,
+            ) -> Result<Vec<u8>, D::Error> {
+                struct Visitor;
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = Vec<u8>;
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        f.write_str("bytes")
+                    }
+                    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                        Ok(v)
+                    }
+                }
+                d.deserialize_byte_buf(Visitor)
+            }
+        }
+
+        let c = Context::new().unwrap();
+
+        let mut tags = HashMap::new();
+        tags.insert("a".to_string(), Some(true));
+        tags.insert("b".to_string(), None);
+        let mut ids = HashMap::new();
+        ids.insert(7, 'x');
+        let drawing = Drawing {
+            name: "test".into(),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Line(1, 2),
+                Shape::Rect {
+                    width: 3,
+                    height: 4,
+                },
+            ],
+            tags,
+            ids,
+            big: u64::MAX,
+            data: vec![1, 2, 3],
+        };
+
+        let value = c.to_js(&drawing).unwrap();
+        c.set_global("drawing", &value).unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" JSON.stringify(drawing.shapes) "),
+            Ok(r#"["Empty",{"Circle":1.5},{"Line":[1,2]},{"Rect":{"width":3,"height":4}}]"#.into())
+        );
+        assert_eq!(
+            c.eval_as::<bool>(
+                " drawing.ids[7] === 'x' && drawing.big === 2n ** 64n - 1n
+                  && drawing.data instanceof ArrayBuffer "
+            ),
+            Ok(true)
+        );
+        assert_eq!(c.from_js::<Drawing>(&value).unwrap(), drawing);
+
+        // Values created by Javascript.
+        let value = c
+            .eval_ref(
+                " ({ name: 'js', shapes: [{ Circle: 2 }, { Rect: { width: 6 / 2, height: 1 } }],
+                     tags: { c: false }, ids: {}, big: 12, data: new Uint8Array([4]) }) ",
+            )
+            .unwrap();
+        let drawing = c.from_js::<Drawing>(&value).unwrap();
+        assert_eq!(
+            drawing.shapes,
+            vec![
+                Shape::Circle(2.0),
+                Shape::Rect {
+                    width: 3,
+                    height: 1
+                }
+            ]
+        );
+        assert_eq!(drawing.data, vec![4]);
+
+        let value = c.eval_ref(" new Map([['a', 1], ['b', 2]]) ").unwrap();
+        let map = c.from_js::<HashMap<String, i32>>(&value).unwrap();
+        assert_eq!(map.len(), 2);
+        c.eval(" Array.from = () => 5 ").unwrap();
+        let map = c.from_js::<HashMap<String, i32>>(&value).unwrap();
+        assert_eq!(map.len(), 2);
+
+        let value = c.eval_ref(" new Array(2 ** 31 - 1) ").unwrap();
+        assert_eq!(
+            c.from_js::<Vec<Option<i32>>>(&value),
+            Err(ExecutionError::Conversion(ValueError::MaxElementsExceeded))
+        );
+
+        let value = c.eval_ref(" ({ name: 1 }) ").unwrap();
+        assert!(c.from_js::<Drawing>(&value).is_err());
+
+        let value = c.eval_ref(" var a = []; a.push(a); a ").unwrap();
+        assert_eq!(
+            c.from_js::<Vec<Vec<i32>>>(&value),
+            Err(ExecutionError::Conversion(ValueError::CyclicValue))
+        );
+
+        #[derive(Deserialize, Debug)]
+        enum Nested {
+            A(Box<Nested>),
+            B,
+        }
+
+        let value = c.eval_ref(" var o = {}; o.A = o; o ").unwrap();
+        assert_eq!(
+            c.from_js::<Nested>(&value).unwrap_err(),
+            ExecutionError::Conversion(ValueError::CyclicValue)
+        );
+        let value = c.eval_ref(" ({ A: { A: 'B' } }) ").unwrap();
+        assert!(matches!(
+            c.from_js::<Nested>(&value),
+            Ok(Nested::A(nested)) if matches!(*nested, Nested::A(_))
+        ));
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_serde_json() {
+        use serde_json::json;
+
+        let c = Context::new().unwrap();
+
+        let value = json!({
+            "name": "test",
+            "ints": [1, -1, 2147483648u64, 9007199254740991u64],
+            "float": 1.5,
+            "nested": { "null": null, "empty": [] },
+        });
+        let js = JsValue::try_from(value.clone()).unwrap();
+        if let JsValue::Object(object) = &js {
+            assert_eq!(
+                object["ints"],
+                JsValue::Array(vec![
+                    JsValue::Int(1),
+                    JsValue::Int(-1),
+                    JsValue::Float(2147483648.0),
+                    JsValue::Float(9007199254740991.0),
+                ])
+            );
+        } else {
+            panic!("Expected JsValue::Object");
+        }
+        assert_eq!(serde_json::Value::try_from(js.clone()), Ok(value.clone()));
+        #[cfg(feature = "bigint")]
+        {
+            let value = json!([9007199254740993u64, u64::MAX]);
+            let js = JsValue::try_from(value.clone()).unwrap();
+            assert_eq!(
+                js,
+                JsValue::Array(vec![
+                    JsValue::BigInt(9007199254740993i64.into()),
+                    JsValue::BigInt(num_bigint::BigInt::from(u64::MAX).into()),
+                ])
+            );
+            assert_eq!(serde_json::Value::try_from(js), Ok(value));
+        }
+        #[cfg(not(feature = "bigint"))]
+        {
+            // Integers that a float can not represent exactly need BigInts.
+            let value = json!({ "ints": [9007199254740993u64] });
+            assert!(JsValue::try_from(value.clone()).is_err());
+            assert!(serde_json::from_value::<JsValue>(value).is_err());
+        }
+
+        // Serialize and Deserialize for JsValue.
+        assert_eq!(
+            serde_json::from_value::<JsValue>(value.clone()).unwrap(),
+            js
+        );
+        let serialized = serde_json::to_value(&js).unwrap();
+        assert_eq!(serialized["nested"], value["nested"]);
+        assert_eq!(serialized["ints"][2], json!(2147483648u64));
+        let value = c.eval(" 2 ** 40 ").unwrap();
+        assert_eq!(value, JsValue::Float(1099511627776.0));
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            json!(1099511627776u64)
+        );
+
+        // Callbacks take JSON values and return them through `JsValue`.
+        c.add_callback("keys", |value: serde_json::Value| {
+            let keys = value.as_object().map_or(0, |object| object.len());
+            JsValue::try_from(json!({ "count": keys }))
+        })
+        .unwrap();
+        assert_eq!(
+            c.eval_as::<serde_json::Value>(" keys({ a: 1, b: [true] }) "),
+            Ok(json!({ "count": 2 }))
+        );
+
+        let value = c
+            .eval(" [NaN, Infinity, -Infinity, undefined, new Set([1]), new Map([['a', 2]])] ")
+            .unwrap();
+        assert!(serde_json::Value::try_from(value.clone()).is_err());
+        assert_eq!(
+            value.clone().into_json(NonFiniteFloats::Null),
+            Ok(json!([null, null, null, null, [1], [["a", 2]]]))
+        );
+        assert_eq!(
+            value.into_json(NonFiniteFloats::String),
+            Ok(json!([
+                "NaN",
+                "Infinity",
+                "-Infinity",
+                null,
+                [1],
+                [["a", 2]]
+            ]))
+        );
+
+        let value = c.eval(" [Symbol('a')] ").unwrap();
+        assert_eq!(
+            serde_json::Value::try_from(value.clone()),
+            Err(ValueError::UnexpectedType)
+        );
+        assert!(serde_json::to_value(&value).is_err());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() {
+        #[derive(IntoJs, FromJs, PartialEq, Debug)]
+        #[js(rename_all = "camelCase")]
+        struct Config {
+            user_name: String,
+            #[js(rename = "ID")]
+            id: i32,
+            nickname: Option<String>,
+            #[js(default)]
+            retries: i32,
+            #[js(default = "default_mode")]
+            mode: Mode,
+            #[js(skip)]
+            cache: Vec<i32>,
+        }
+
+        fn default_mode() -> Mode {
+            Mode::Fast
+        }
+
+        #[derive(IntoJs, FromJs, PartialEq, Debug)]
+        #[js(rename_all = "lowercase")]
+        enum Mode {
+            Fast,
+            Limit(i32),
+            Pair(i32, bool),
+        }
+
+        #[derive(IntoJs, FromJs, PartialEq, Debug)]
+        #[js(tag = "type")]
+        enum Event {
+            Start,
+            #[js(rename_all = "camelCase")]
+            Move {
+                delta_x: i32,
+            },
+        }
+
+        #[derive(IntoJs, FromJs, PartialEq, Debug)]
+        #[js(tag = "t", content = "c")]
+        enum Message<T> {
+            Ping,
+            Data(T),
+        }
+
+        let c = Context::new().unwrap();
+        // Sorts object keys, which are unordered without `preserve_order`.
+        c.eval(
+            " function json(value) {
+                return JSON.stringify(value, (k, v) => v && typeof v === 'object' && !Array.isArray(v)
+                    ? Object.fromEntries(Object.entries(v).sort()) : v);
+            } ",
+        )
+        .unwrap();
+        let json = |value: JsValue| c.call_function("json", vec![value]).unwrap();
+
+        let config = Config {
+            user_name: "ada".into(),
+            id: 1,
+            nickname: None,
+            retries: 3,
+            mode: Mode::Pair(2, true),
+            cache: vec![1],
+        };
+        assert_eq!(
+            json(config.into()),
+            JsValue::String(
+                r#"{"ID":1,"mode":{"pair":[2,true]},"nickname":null,"retries":3,"userName":"ada"}"#
+                    .into()
+            )
+        );
+
+        let config: Config = c.eval_as(" ({ userName: 'bob', ID: 2 }) ").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                user_name: "bob".into(),
+                id: 2,
+                nickname: None,
+                retries: 0,
+                mode: Mode::Fast,
+                cache: vec![],
+            }
+        );
+        let config: Config = c
+            .eval_as(" ({ userName: 'x', ID: 3, nickname: 'y', mode: { limit: 5 } }) ")
+            .unwrap();
+        assert_eq!(config.nickname, Some("y".into()));
+        assert_eq!(config.mode, Mode::Limit(5));
+        assert_eq!(
+            c.eval_as::<Config>(" ({ ID: 2 }) "),
+            Err(ExecutionError::Conversion(ValueError::Custom(
+                "Missing field `userName`".into()
+            )))
+        );
+        assert_eq!(
+            c.eval_as::<Config>(" ({ userName: 'x', ID: 'y' }) "),
+            Err(ExecutionError::Conversion(ValueError::Custom(
+                "Invalid field `ID`: Could not convert - received unexpected type".into()
+            )))
+        );
+        assert_eq!(c.eval_as::<Mode>(" 'fast' "), Ok(Mode::Fast));
+        assert!(c.eval_as::<Mode>(" 'slow' ").is_err());
+
+        assert_eq!(
+            json(Event::Move { delta_x: 4 }.into()),
+            JsValue::String(r#"{"deltaX":4,"type":"Move"}"#.into())
+        );
+        assert_eq!(
+            c.eval_as::<Event>(" ({ type: 'Start' }) "),
+            Ok(Event::Start)
+        );
+        assert_eq!(
+            c.eval_as::<Event>(" ({ type: 'Move', deltaX: -1 }) "),
+            Ok(Event::Move { delta_x: -1 })
+        );
+
+        assert_eq!(
+            json(Message::Data(vec![1, 2]).into()),
+            JsValue::String(r#"{"c":[1,2],"t":"Data"}"#.into())
+        );
+        assert_eq!(
+            c.eval_as::<Message<String>>(" ({ t: 'Data', c: 'x' }) "),
+            Ok(Message::Data("x".into()))
+        );
+        assert_eq!(
+            c.eval_as::<Message<String>>(" ({ t: 'Ping' }) "),
+            Ok(Message::Ping)
+        );
+
+        assert_eq!(
+            <Config as FromJsArg>::ts_type(),
+            r#"{ userName: string; ID: number; nickname?: string | null; retries?: number; mode?: "fast" | { limit: number } | { pair: [number, boolean] } }"#
+        );
+        assert_eq!(
+            <Config as IntoJsReturn>::ts_type(),
+            r#"{ userName: string; ID: number; nickname: string | null; retries: number; mode: "fast" | { limit: number } | { pair: [number, boolean] } }"#
+        );
+        assert_eq!(
+            <Event as IntoJsReturn>::ts_type(),
+            r#"{ type: "Start" } | { type: "Move"; deltaX: number }"#
+        );
+        assert_eq!(
+            <Message<Vec<i32>> as FromJsArg>::ts_type(),
+            r#"{ t: "Ping" } | { t: "Data"; c: number[] }"#
+        );
+
+        // `JsValue` fields are declared as `any`.
+        #[derive(FromJs, IntoJs)]
+        struct Raw {
+            value: JsValue,
+            list: Vec<JsValue>,
+            extra: Option<JsValue>,
+        }
+
+        assert_eq!(
+            <Raw as FromJsArg>::ts_type(),
+            "{ value: any; list: any[]; extra?: any | null }"
+        );
+        assert_eq!(
+            <Raw as IntoJsReturn>::ts_type(),
+            "{ value: any; list: any[]; extra: any | null }"
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_js_function() {
+        mod math {
+            use crate::{js_function, CallContext};
+
+            /// Subtract two numbers.
+            ///
+            /// Returns `a - b`.
+            #[js_function(name = "minus")]
+            pub fn sub(a: i32, b: i32) -> i32 {
+                a - b
+            }
+
+            #[js_function(options)]
+            pub fn clamp(
+                context: &CallContext,
+                value: i32,
+                #[js(rename = "minimum", default)] min: i32,
+                max: Option<i32>,
+            ) -> Result<i32, String> {
+                if context.this().unwrap() != crate::JsValue::Undefined {
+                    return Err("clamp called as method".into());
+                }
+                Ok(value.max(min).min(max.unwrap_or(i32::MAX)))
+            }
+
+            #[js_function(options)]
+            pub fn describe(
+                name: Option<String>,
+                #[js(default = "default_greeting")] greeting: String,
+            ) -> String {
+                format!("{}, {}", greeting, name.unwrap_or_else(|| "nobody".into()))
+            }
+
+            fn default_greeting() -> String {
+                "hello".into()
+            }
+        }
+
+        let c = Context::new().unwrap();
+        register_functions!(c, [math::sub, math::clamp, math::describe,]).unwrap();
+
+        assert_eq!(math::sub(3, 1), 2);
+        assert_eq!(<math::sub as JsFunctionDef>::NAME, "minus");
+        assert_eq!(
+            <math::sub as JsFunctionDef>::DOC,
+            "Subtract two numbers.\n\nReturns `a - b`."
+        );
+        assert_eq!(c.eval_as::<i32>(" minus(5, 2) "), Ok(3));
+        assert!(c.eval(" minus(5) ").is_err());
+
+        assert_eq!(c.eval_as::<i32>(" clamp({ value: -3 }) "), Ok(0));
+        assert_eq!(c.eval_as::<i32>(" clamp({ value: 10, max: 5 }) "), Ok(5));
+        assert_eq!(
+            c.eval_as::<i32>(" clamp({ value: 1, minimum: 4, max: null }) "),
+            Ok(4)
+        );
+        assert!(c.eval(" clamp({ minimum: 4 }) ").is_err());
+        assert!(c.eval(" clamp(1) ").is_err());
+
+        assert_eq!(
+            c.eval_as::<String>(" describe({ name: 'ada' }) "),
+            Ok("hello, ada".into())
+        );
+        assert_eq!(
+            c.eval_as::<String>(" describe({ greeting: 'hi' }) "),
+            Ok("hi, nobody".into())
+        );
+        assert_eq!(
+            c.eval_as::<String>(" describe() "),
+            Ok("hello, nobody".into())
+        );
+
+        assert_eq!(
+            c.typescript_declarations(),
+            r#"declare function clamp(options: { value: number; minimum?: number; max?: number | null }): number;
+
+declare function describe(options?: { name?: string | null; greeting?: string }): string;
+
+/**
+ * Subtract two numbers.
+ *
+ * Returns `a - b`.
+ */
+declare function minus(a: number, b: number): number;
+"#
+        );
+    }
+
+    #[test]
+    fn test_eval_syntax_error() {
+        let c = Context::new().unwrap();
+        assert_eq!(
+            c.eval(
+                r#"
+                !!!!
+            "#
+            ),
+            Err(ExecutionError::Exception(
+                "SyntaxError: unexpected token in expression: \'\'".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_zero_bytes_and_surrogates() {
+        let c = Context::new().unwrap();
+
+        // Zero bytes in code, strings and property names.
+        assert_eq!(c.eval_as::<i32>(" 'a\0b'.length "), Ok(3));
+        assert_eq!(c.eval(" 'a\0b' "), Ok(JsValue::String("a\0b".into())));
+        // Raw control characters are not valid JSON.
+        assert!(c.parse_json("\"a\0b\"").is_err());
+        assert_eq!(
+            c.parse_json(r#"{ "a\u0000b": "c\u0000" }"#),
+            Ok(JsValue::Object(
+                vec![("a\0b".to_string(), JsValue::String("c\0".into()))]
+                    .into_iter()
+                    .collect()
+            ))
+        );
+        let object = JsValue::Object(
+            vec![("k\0ey".to_string(), JsValue::Int(1))]
+                .into_iter()
+                .collect(),
+        );
+        c.add_callback("roundtrip", |value: JsValue| value).unwrap();
+        assert_eq!(
+            c.call_function("roundtrip", vec![object.clone()]),
+            Ok(object.clone())
+        );
+        c.add_callback("getKey", |object: JsObject| object.property("k\0ey"))
+            .unwrap();
+        assert_eq!(c.call_function("getKey", vec![object]), Ok(JsValue::Int(1)));
+
+        // Lone surrogates are kept.
+        let value = c.eval(r" ['\uD800', 'a\uDC00b', '\uD83D\uDE00'] ").unwrap();
+        let strings = match &value {
+            JsValue::Array(values) => values.clone(),
+            _ => panic!("Expected an array"),
+        };
+        assert!(matches!(&strings[0], JsValue::IllFormedString(s) if s.to_utf16() == [0xD800]));
+        assert!(
+            matches!(&strings[1], JsValue::IllFormedString(s) if s.to_utf16() == [0x61, 0xDC00, 0x62])
+        );
+        assert_eq!(strings[2], JsValue::String("\u{1F600}".into()));
+        c.add_callback("check", |s: JsString| {
+            format!("{} {}", s.is_well_formed(), s)
+        })
+        .unwrap();
+        assert_eq!(
+            c.eval_as::<String>(r" check('x\uD800') "),
+            Ok("false x\u{FFFD}".into())
+        );
+
+        c.add_callback("roundtrip", |values: JsValue| values)
+            .unwrap();
+        assert_eq!(c.call_function("roundtrip", vec![value.clone()]), Ok(value));
+        let s = JsString::from_utf16(&[0x61, 0xD800, 0xD83D, 0xDE00]);
+        assert_eq!(s.to_utf16(), vec![0x61, 0xD800, 0xD83D, 0xDE00]);
+        assert_eq!(s.clone().into_string(), Err(s.clone()));
+        c.add_callback("units", |s: JsString| {
+            s.to_utf16().into_iter().map(i32::from).collect::<Vec<_>>()
+        })
+        .unwrap();
+        assert_eq!(
+            c.call_function("units", vec![JsValue::from(s)]),
+            Ok(vec![0x61, 0xD800, 0xD83D, 0xDE00].into())
+        );
+    }
+
+    #[test]
+    fn test_eval_exception() {
+        let c = Context::new().unwrap();
+        assert_eq!(
+            c.eval(
+                r#"
+                function f() {
+                    throw new Error("My Error");
+                }
+                f();
+            "#
+            ),
+            Err(ExecutionError::Exception("Error: My Error".into(),))
+        );
+    }
+
+    #[test]
+    fn eval_async() {
+        let c = Context::new().unwrap();
+
+        let value = c
+            .eval(
+                r#"
+            new Promise((resolve, _) => {
+                resolve(33);
+            })
+       "#,
+            )
+            .unwrap();
+        assert_eq!(value, JsValue::Int(33));
+
+        let res = c.eval(
+            r#"
+            new Promise((_resolve, reject) => {
+                reject("Failed...");
+            })
+       "#,
+        );
+        assert_eq!(res, Err(ExecutionError::Exception("Failed...".into())));
+    }
+
+    #[test]
+    fn test_call() {
+        let c = Context::new().unwrap();
+
+        assert_eq!(
+            c.call_function("parseInt", vec!["22"]).unwrap(),
+            JsValue::Int(22),
+        );
+
         c.eval(
             r#"
             function add(a, b) {
                 return a + b;
             }
-        "#,
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.call_function("add", vec![5, 7]).unwrap(),
+            JsValue::Int(12),
+        );
+
+        c.eval(
+            r#"
+            function sumArray(arr) {
+                let sum = 0;
+                for (const value of arr) {
+                    sum += value;
+                }
+                return sum;
+            }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.call_function("sumArray", vec![vec![1, 2, 3]]).unwrap(),
+            JsValue::Int(6),
+        );
+
+        c.eval(
+            r#"
+            function addObject(obj) {
+                let sum = 0;
+                for (const key of Object.keys(obj)) {
+                    sum += obj[key];
+                }
+                return sum;
+            }
+        "#,
+        )
+        .unwrap();
+        let mut obj = std::collections::HashMap::<String, i32>::new();
+        obj.insert("a".into(), 10);
+        obj.insert("b".into(), 20);
+        obj.insert("c".into(), 30);
+        assert_eq!(
+            c.call_function("addObject", vec![obj]).unwrap(),
+            JsValue::Int(60),
+        );
+    }
+
+    #[test]
+    fn test_call_large_string() {
+        let c = Context::new().unwrap();
+        c.eval(" function strLen(s) { return s.length; } ").unwrap();
+
+        let s = " ".repeat(200_000);
+        let v = c.call_function("strLen", vec![s]).unwrap();
+        assert_eq!(v, JsValue::Int(200_000));
+    }
+
+    #[test]
+    fn call_async() {
+        let c = Context::new().unwrap();
+
+        c.eval(
+            r#"
+            function asyncOk() {
+                return new Promise((resolve, _) => {
+                    resolve(33);
+                });
+            }
+
+            function asyncErr() {
+                return new Promise((_resolve, reject) => {
+                    reject("Failed...");
+                });
+            }
+        "#,
+        )
+        .unwrap();
+
+        let value = c.call_function("asyncOk", vec![true]).unwrap();
+        assert_eq!(value, JsValue::Int(33));
+
+        let res = c.call_function("asyncErr", vec![true]);
+        assert_eq!(res, Err(ExecutionError::Exception("Failed...".into())));
+    }
+
+    #[test]
+    fn test_callback() {
+        let c = Context::new().unwrap();
+
+        c.add_callback("cb1", |flag: bool| !flag).unwrap();
+        assert_eq!(c.eval("cb1(true)").unwrap(), JsValue::Bool(false),);
+
+        c.add_callback("concat2", |a: String, b: String| format!("{}{}", a, b))
+            .unwrap();
+        assert_eq!(
+            c.eval(r#"concat2("abc", "def")"#).unwrap(),
+            JsValue::String("abcdef".into()),
+        );
+
+        c.add_callback("add2", |a: i32, b: i32| -> i32 { a + b })
+            .unwrap();
+        assert_eq!(c.eval("add2(5, 11)").unwrap(), JsValue::Int(16),);
+    }
+
+    #[test]
+    fn test_callback_argn_variants() {
+        macro_rules! callback_argn_tests {
+            [
+                $(
+                    $len:literal : ( $( $argn:ident : $argv:literal ),* ),
+                )*
+            ] => {
+                $(
+                   {
+                       // Test plain return type.
+                        let name = format!("cb{}", $len);
+                        let c = Context::new().unwrap();
+                        c.add_callback(&name, | $( $argn : i32 ),*| -> i32 {
+                            $( $argn + )* 0
+                        }).unwrap();
+
+                        let code = format!("{}( {} )", name, "1,".repeat($len));
+                        let v = c.eval(&code).unwrap();
+                        assert_eq!(v, JsValue::Int($len));
+
+                        // Test Result<T, E> return type with OK(_) returns.
+                        let name = format!("cbres{}", $len);
+                        c.add_callback(&name, | $( $argn : i32 ),*| -> Result<i32, String> {
+                            Ok($( $argn + )* 0)
+                        }).unwrap();
+
+                        let code = format!("{}( {} )", name, "1,".repeat($len));
+                        let v = c.eval(&code).unwrap();
+                        assert_eq!(v, JsValue::Int($len));
+
+                        // Test Result<T, E> return type with Err(_) returns.
+                        let name = format!("cbreserr{}", $len);
+                        c.add_callback(&name, #[allow(unused_variables)] | $( $argn : i32 ),*| -> Result<i32, String> {
+                            Err("error".into())
+                        }).unwrap();
+
+                        let code = format!("{}( {} )", name, "1,".repeat($len));
+                        let res = c.eval(&code);
+                        assert_eq!(res, Err(ExecutionError::Host("error".into())));
+                   }
+                )*
+            }
+        }
+
+        callback_argn_tests![
+            1: (a : 1),
+        ]
+    }
+
+    #[test]
+    fn test_callback_varargs() {
+        let c = Context::new().unwrap();
+
+        // No return.
+        c.add_callback("cb", |args: Arguments| {
+            let args = args.into_vec();
+            assert_eq!(
+                args,
+                vec![
+                    JsValue::String("hello".into()),
+                    JsValue::Bool(true),
+                    JsValue::from(100),
+                ]
+            );
+        })
+        .unwrap();
+        c.eval(" cb('hello', true, 100) ").unwrap();
+
+        // With return.
+        c.add_callback("cb2", |args: Arguments| -> u32 {
+            let args = args.into_vec();
+            assert_eq!(
+                args,
+                vec![JsValue::from(1), JsValue::from(10), JsValue::from(100),]
+            );
+            111
+        })
+        .unwrap();
+        c.eval(
+            r#"
+           var x = cb2(1, 10, 100);
+           if (x !== 111) {
+            throw new Error('Expected 111, got ' + x);
+           }
+       "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_callback_context() {
+        let c = Context::new().unwrap();
+        c.eval(" var factor = 3; function triple(x) { return x * factor; } ")
+            .unwrap();
+
+        c.add_callback("readGlobal", |ctx: &CallContext| ctx.eval("factor"))
+            .unwrap();
+        assert_eq!(c.eval(" readGlobal() ").unwrap(), JsValue::Int(3));
+
+        c.add_callback("callGlobal", |ctx: &CallContext, x: i32| {
+            ctx.call_function("triple", vec![x])
+        })
+        .unwrap();
+        assert_eq!(c.eval(" callGlobal(5) ").unwrap(), JsValue::Int(15));
+
+        c.add_callback("varargs", |ctx: &CallContext, args: Arguments| {
+            ctx.call_function("triple", args.into_vec())
+        })
+        .unwrap();
+        assert_eq!(c.eval(" varargs(2) ").unwrap(), JsValue::Int(6));
+
+        // Errors from nested calls propagate as exceptions.
+        c.add_callback("failing", |ctx: &CallContext| {
+            ctx.eval(" throw new Error('nested') ")
+        })
+        .unwrap();
+        let err_msg = c.eval(" failing() ").unwrap_err().to_string();
+        assert!(err_msg.contains("nested"));
+    }
+
+    #[test]
+    fn test_callback_function_argument() {
+        let c = Context::new().unwrap();
+
+        c.add_callback("map", |values: Vec<i32>, f: JsFunction| {
+            values
+                .into_iter()
+                .map(|value| f.call(vec![value]))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .unwrap();
+        assert_eq!(
+            c.eval(" map([1, 2, 3], x => x + 1) ").unwrap(),
+            vec![2, 3, 4].into(),
+        );
+
+        // Non-function values are rejected.
+        assert!(c.eval(" map([1], 1) ").is_err());
+    }
+
+    #[test]
+    fn test_function_outlives_context() {
+        use std::{rc::Rc, sync::Mutex};
+
+        let c = Context::new().unwrap();
+        let stored = Rc::new(Mutex::new(None));
+
+        let s = stored.clone();
+        c.add_callback("store", move |f: JsFunction| {
+            s.lock().unwrap().replace(f).is_none()
+        })
+        .unwrap();
+        c.eval(" store(() => 42) ").unwrap();
+
+        let f = stored.lock().unwrap().take().unwrap();
+        assert_eq!(f.call(Vec::<JsValue>::new()).unwrap(), JsValue::Int(42));
+
+        drop(c);
+        assert!(f.call(Vec::<JsValue>::new()).is_err());
+    }
+
+    #[test]
+    fn test_callback_host_error() {
+        #[derive(Debug, PartialEq)]
+        struct Timeout(u32);
+
+        impl fmt::Display for Timeout {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "timed out after {}ms", self.0)
+            }
+        }
+
+        impl error::Error for Timeout {}
+
+        let c = Context::new().unwrap();
+        c.add_callback(
+            "fetch",
+            |ms: i32| -> Result<i32, Box<dyn error::Error + Send + Sync>> {
+                Err(Box::new(Timeout(ms as u32)))
+            },
+        )
+        .unwrap();
+
+        // Uncaught errors are returned unchanged.
+        match c.eval(" fetch(100) ") {
+            Err(ExecutionError::Host(e)) => {
+                assert_eq!(e.downcast_ref::<Timeout>(), Some(&Timeout(100)))
+            }
+            other => panic!("Expected a host error, got {:?}", other),
+        }
+
+        // Javascript sees an Error, which may be rethrown.
+        assert_eq!(
+            c.eval(" try { fetch(5) } catch (e) { [e instanceof Error, e.message].join() } "),
+            Ok("true,timed out after 5ms".into()),
+        );
+        match c.eval(" try { fetch(5) } catch (e) { throw e } ") {
+            Err(ExecutionError::Host(e)) => assert!(e.is::<Timeout>()),
+            other => panic!("Expected a host error, got {:?}", other),
+        }
+
+        // Any error type is kept as the original value.
+        c.add_callback("wait", |ms: i32| -> Result<i32, Timeout> {
+            Err(Timeout(ms as u32))
+        })
+        .unwrap();
+        c.add_callback(
+            "parse",
+            |s: String| -> Result<i32, Box<dyn error::Error + Send + Sync>> {
+                Ok(s.parse::<i32>()?)
+            },
         )
         .unwrap();
+        match c.eval(" wait(10) ") {
+            Err(ExecutionError::Host(e)) => {
+                assert_eq!(e.downcast_ref::<Timeout>(), Some(&Timeout(10)))
+            }
+            other => panic!("Expected a host error, got {:?}", other),
+        }
         assert_eq!(
-            c.call_function("add", vec![5, 7]).unwrap(),
-            JsValue::Int(12),
+            c.eval(" try { parse('x') } catch (e) { e.message } "),
+            Ok("invalid digit found in string".into()),
         );
+    }
 
-        c.eval(
-            r#"
-            function sumArray(arr) {
-                let sum = 0;
-                for (const value of arr) {
-                    sum += value;
-                }
-                return sum;
+    #[test]
+    fn test_callback_primitive_conversion() {
+        use crate::bindings::VALUE_CONVERSIONS;
+
+        let c = Context::new().unwrap();
+        let conversions = |code: &str| {
+            let before = VALUE_CONVERSIONS.with(|count| count.get());
+            c.eval(code).unwrap();
+            VALUE_CONVERSIONS.with(|count| count.get()) - before
+        };
+        c.add_callback("add", |a: i32, b: f64| a as f64 + b)
+            .unwrap();
+        c.add_callback("greet", |name: String, loud: Option<bool>| {
+            if loud == Some(true) {
+                Some(name.to_uppercase())
+            } else {
+                None
             }
-        "#,
+        })
+        .unwrap();
+        c.add_callback("echo", |value: JsValue| value).unwrap();
+
+        // Primitives are read and created without a JsValue.
+        let baseline = conversions(" for (let i = 0; i < 100; i++) {} ");
+        assert_eq!(
+            conversions(" for (let i = 0; i < 100; i++) { add(i, 0.5); greet('a', i % 2 == 0) } "),
+            baseline,
+        );
+        assert_eq!(c.eval_as::<String>(" greet('a', true) "), Ok("A".into()));
+        assert_eq!(c.eval_as::<f64>(" add(1, 0.5) "), Ok(1.5));
+
+        // Other values are converted through a JsValue.
+        assert_eq!(
+            conversions(" for (let i = 0; i < 100; i++) { echo(i) } "),
+            baseline + 200,
+        );
+    }
+
+    #[test]
+    fn test_callback_nested_exception() {
+        let c = Context::new().unwrap();
+        c.add_callback("run", |ctx: &CallContext, code: String| ctx.eval(&code))
+            .unwrap();
+
+        // Exceptions of nested calls are rethrown as the original value.
+        assert_eq!(
+            c.eval_as::<bool>(
+                " try { run('throw new RangeError(\"boom\")') } catch (e) { e instanceof RangeError } "
+            ),
+            Ok(true),
+        );
+        assert_eq!(
+            c.eval(" run('throw 1') "),
+            Err(ExecutionError::Exception("1".into())),
+        );
+
+        // Other exceptions with the same message are raised as their value.
+        c.add_callback(
+            "rethrow",
+            |ctx: &CallContext| -> Result<i32, ExecutionError> {
+                let _thrown = ctx.eval(" throw new RangeError('boom') ");
+                Err(ExecutionError::Exception("RangeError: boom".into()))
+            },
         )
         .unwrap();
         assert_eq!(
-            c.call_function("sumArray", vec![vec![1, 2, 3]]).unwrap(),
-            JsValue::Int(6),
+            c.eval_as::<String>(" try { rethrow() } catch (e) { typeof e } "),
+            Ok("string".into()),
+        );
+    }
+
+    #[test]
+    fn test_js_throw() {
+        let c = Context::new().unwrap();
+        c.add_callback(
+            "fail",
+            |kind: String, message: String| -> Result<bool, JsThrow> {
+                Err(match kind.as_str() {
+                    "type" => JsThrow::type_error(message),
+                    "range" => JsThrow::range_error(message),
+                    "error" => JsThrow::error(message),
+                    other => JsThrow::custom(other, message).property("code", 42),
+                })
+            },
+        )
+        .unwrap();
+
+        let check = |code: &str| {
+            c.eval_as::<String>(&format!(
+                " try {{ {} }} catch (e) {{ [e instanceof Error, e.name, e.message, e.code].join() }} ",
+                code
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            check("fail('type', 'bad type')"),
+            "true,TypeError,bad type,"
+        );
+        assert_eq!(
+            check("fail('range', 'too big')"),
+            "true,RangeError,too big,"
+        );
+        assert_eq!(check("fail('error', 'failed')"), "true,Error,failed,");
+        assert_eq!(check("fail('MyError', 'custom')"), "true,MyError,custom,42");
+
+        // Custom classes are instantiated.
+        c.eval(" globalThis.MyError = class extends Error { get name() { return 'Mine' } } ")
+            .unwrap();
+        assert_eq!(check("fail('MyError', 'custom')"), "true,Mine,custom,42");
+        assert_eq!(
+            c.eval_as::<bool>(" try { fail('MyError', '') } catch (e) { e instanceof MyError } "),
+            Ok(true),
+        );
+
+        // Errors have a stack and long messages are not truncated.
+        let long = "x".repeat(1000);
+        assert_eq!(
+            c.eval_as::<String>(&format!(
+                " try {{ fail('type', '{}') }} catch (e) {{ e.message + typeof e.stack }} ",
+                long
+            )),
+            Ok(format!("{}string", long)),
+        );
+
+        // Uncaught errors are returned as exceptions.
+        assert_eq!(
+            c.eval(" fail('type', 'uncaught') "),
+            Err(ExecutionError::Exception("TypeError: uncaught".into())),
+        );
+    }
+
+    #[test]
+    fn test_callback_panic() {
+        let c = Context::new().unwrap();
+        c.add_callback("fail", |value: i32| -> i32 {
+            panic!("bad value {}", value)
+        })
+        .unwrap();
+        assert_eq!(
+            c.eval(" fail(1) "),
+            Err(ExecutionError::Exception(
+                "Internal error: Callback panicked: bad value 1".into()
+            )),
+        );
+
+        let c = Context::builder().resume_panics(true).build().unwrap();
+        c.add_callback("fail", |value: i32| -> i32 {
+            panic!("bad value {}", value)
+        })
+        .unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            c.eval(" try { fail(2) } catch (e) {} while (true) {} ")
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "bad value 2");
+
+        // The context can be used after the panic.
+        assert_eq!(c.eval(" 1 + 1 "), Ok(JsValue::Int(2)));
+    }
+
+    #[test]
+    fn test_callback_primitive_args() {
+        let c = Context::new().unwrap();
+        c.add_callback("scale", |value: f64, factor: i32, round: bool| {
+            let value = value * f64::from(factor);
+            if round {
+                value.round()
+            } else {
+                value
+            }
+        })
+        .unwrap();
+
+        assert_eq!(c.eval(" scale(1.25, 3, false) "), Ok(JsValue::Float(3.75)));
+        assert_eq!(c.eval(" scale(1.25, 3, true) "), Ok(JsValue::Float(4.0)));
+
+        // Primitives are converted like with `TryFrom<JsValue>`.
+        let expected = Err(ExecutionError::Exception(
+            "Could not convert - received unexpected type".into(),
+        ));
+        assert_eq!(c.eval(" scale(1.25, 1.5, true) "), expected);
+        assert_eq!(c.eval(" scale(1, 3, true) "), expected);
+        assert_eq!(c.eval(" scale(1.25, 3, 1) "), expected);
+    }
+
+    #[test]
+    fn test_callback_this() {
+        let c = Context::new().unwrap();
+
+        c.add_callback("getName", |ctx: &CallContext| {
+            ctx.this_object()
+                .ok_or("no this")
+                .and_then(|this| this.property("name").map_err(|_| "no name"))
+        })
+        .unwrap();
+        c.add_callback("rename", |ctx: &CallContext, name: String| {
+            let this = ctx.this_object().ok_or("no this")?;
+            this.set_property("name", name.clone())
+                .map(|_| name)
+                .map_err(|_| "could not set")
+        })
+        .unwrap();
+
+        assert_eq!(
+            c.eval(" let o = { name: 'a', getName, rename }; o.getName() "),
+            Ok("a".into()),
+        );
+        c.eval(" o.rename('b') ").unwrap();
+        assert_eq!(c.eval(" o.name "), Ok("b".into()));
+
+        assert_eq!(
+            c.eval(" getName() "),
+            Err(ExecutionError::Host("no this".into()))
+        );
+    }
+
+    #[test]
+    fn test_constructor() {
+        let c = Context::new().unwrap();
+
+        c.add_constructor("Thing", |ctx: &CallContext, value: i32| {
+            let this = ctx.this_object().unwrap();
+            this.set_property("value", value).is_ok()
+        })
+        .unwrap();
+        c.add_constructor("Pair", |a: i32, b: i32| {
+            let mut props = HashMap::new();
+            props.insert("a", a);
+            props.insert("b", b);
+            props
+        })
+        .unwrap();
+        c.add_callback("double", |ctx: &CallContext| {
+            let value: i32 = ctx
+                .this_object()
+                .unwrap()
+                .property("value")
+                .unwrap()
+                .try_into()
+                .unwrap();
+            value * 2
+        })
+        .unwrap();
+
+        assert_eq!(
+            c.eval(" let t = new Thing(21); [t instanceof Thing, t.value] "),
+            Ok(vec![JsValue::Bool(true), JsValue::Int(21)].into()),
+        );
+        assert_eq!(
+            c.eval_as::<i32>(" Thing.prototype.double = double; t.double() "),
+            Ok(42),
+        );
+        assert_eq!(
+            c.eval(" class Sub extends Thing {}; new Sub(1) instanceof Sub "),
+            Ok(JsValue::Bool(true)),
+        );
+        assert_eq!(
+            c.eval_as::<i32>(" let p = new Pair(1, 2); p.a + p.b "),
+            Ok(3)
+        );
+
+        assert!(matches!(
+            c.eval(" Thing(1) "),
+            Err(ExecutionError::Exception(_))
+        ));
+    }
+
+    #[test]
+    fn test_class() {
+        use std::{rc::Rc, sync::Mutex};
+
+        struct Account {
+            balance: i32,
+            dropped: Rc<Mutex<bool>>,
+        }
+
+        impl Drop for Account {
+            fn drop(&mut self) {
+                *self.dropped.lock().unwrap() = true;
+            }
+        }
+
+        thread_local! {
+            static DROPPED: Rc<Mutex<bool>> = Rc::new(Mutex::new(false));
+        }
+
+        impl JsClass for Account {
+            const NAME: &'static str = "Account";
+
+            fn define(class: &mut ClassBuilder<Self>) {
+                class
+                    .constructor(|balance: i32| {
+                        if balance < 0 {
+                            return Err("negative balance");
+                        }
+                        Ok(Account {
+                            balance,
+                            dropped: DROPPED.with(|d| d.clone()),
+                        })
+                    })
+                    .method("deposit", |this: &mut Account, amount: i32| {
+                        this.balance += amount;
+                        this.balance
+                    })
+                    .method("describe", |this: &Account, ctx: &CallContext| {
+                        let prefix: String = ctx.eval_as("'balance: '").unwrap();
+                        format!("{}{}", prefix, this.balance)
+                    })
+                    .getter("balance", |this: &Account| this.balance)
+                    .property(
+                        "limit",
+                        |this: &Account| this.balance * 2,
+                        |this: &mut Account, limit: i32| {
+                            if limit % 2 != 0 {
+                                return Err("odd limit");
+                            }
+                            this.balance = limit / 2;
+                            Ok(())
+                        },
+                    );
+            }
+        }
+
+        let c = Context::new().unwrap();
+        c.add_class::<Account>().unwrap();
+        c.add_callback("balanceOf", |account: JsInstance<Account>| {
+            account.borrow().balance
+        })
+        .unwrap();
+
+        assert_eq!(
+            c.eval(" let a = new Account(10); [a instanceof Account, a.deposit(5), a.balance] "),
+            Ok(vec![JsValue::Bool(true), JsValue::Int(15), JsValue::Int(15)].into()),
+        );
+        assert_eq!(
+            c.eval_as::<String>(" a.describe() "),
+            Ok("balance: 15".into())
+        );
+        assert_eq!(c.eval_as::<i32>(" a.limit = 40; a.balance "), Ok(20));
+        assert_eq!(
+            c.eval(" a.limit = 3 "),
+            Err(ExecutionError::Host("odd limit".into()))
+        );
+        assert_eq!(c.eval_as::<i32>(" balanceOf(a) "), Ok(20));
+        assert_eq!(
+            c.eval(" new Account(-1) "),
+            Err(ExecutionError::Host("negative balance".into()))
+        );
+        assert!(c.eval(" balanceOf({}) ").is_err());
+        assert!(c.eval(" Account.prototype.deposit.call({}, 1) ").is_err());
+        assert_eq!(c.eval(" Object.keys(a) "), Ok(JsValue::Array(Vec::new())));
+
+        assert!(!DROPPED.with(|d| *d.lock().unwrap()));
+        drop(c);
+        assert!(DROPPED.with(|d| *d.lock().unwrap()));
+    }
+
+    #[test]
+    fn test_host_object() {
+        use std::{cell::RefCell, rc::Rc};
+
+        struct Table {
+            rows: Rc<RefCell<Vec<HashMap<String, i32>>>>,
+        }
+
+        struct Row {
+            rows: Rc<RefCell<Vec<HashMap<String, i32>>>>,
+            index: usize,
+        }
+
+        impl HostObject for Table {
+            fn get(&self, name: &str) -> Option<HostValue> {
+                if name == "length" {
+                    return Some((self.rows.borrow().len() as i32).into());
+                }
+                let index = name.parse::<usize>().ok()?;
+                if index >= self.rows.borrow().len() {
+                    return None;
+                }
+                Some(HostValue::object(Row {
+                    rows: self.rows.clone(),
+                    index,
+                }))
+            }
+        }
+
+        impl HostObject for Row {
+            fn get(&self, name: &str) -> Option<HostValue> {
+                self.rows.borrow()[self.index]
+                    .get(name)
+                    .map(|value| (*value).into())
+            }
+
+            fn set(&mut self, name: &str, value: JsValue) -> Result<(), String> {
+                let value = i32::try_from(value).map_err(|e| e.to_string())?;
+                self.rows.borrow_mut()[self.index].insert(name.to_string(), value);
+                Ok(())
+            }
+
+            fn delete(&mut self, name: &str) -> bool {
+                self.rows.borrow_mut()[self.index].remove(name);
+                true
+            }
+
+            fn own_keys(&self) -> Vec<String> {
+                let mut keys: Vec<_> = self.rows.borrow()[self.index].keys().cloned().collect();
+                keys.sort();
+                keys
+            }
+        }
+
+        let mut row = HashMap::new();
+        row.insert("a".to_string(), 1);
+        row.insert("b".to_string(), 2);
+        let rows = Rc::new(RefCell::new(vec![row]));
+
+        let c = Context::new().unwrap();
+        c.add_host_object("table", Table { rows: rows.clone() })
+            .unwrap();
+
+        assert_eq!(c.eval_as::<i32>(" table.length "), Ok(1));
+        assert_eq!(c.eval_as::<i32>(" table[0].a + table[0].b "), Ok(3));
+        assert_eq!(c.eval(" table[1] "), Ok(JsValue::Undefined));
+        // Nested host objects are created on every read.
+        assert_eq!(c.eval_as::<bool>(" table[0] === table[0] "), Ok(false));
+        assert_eq!(
+            c.eval(" ['a' in table[0], 'c' in table[0]] "),
+            Ok(vec![true, false].into())
+        );
+        assert_eq!(c.eval(" Object.keys(table[0]) "), Ok(vec!["a", "b"].into()));
+        assert_eq!(
+            c.eval_as::<String>(" JSON.stringify(table[0]) "),
+            Ok(r#"{"a":1,"b":2}"#.to_string())
+        );
+        assert_eq!(
+            c.eval_as::<bool>(" typeof table.toString === 'function' "),
+            Ok(true)
+        );
+
+        c.eval(" table[0].c = 3; delete table[0].a ").unwrap();
+        assert_eq!(rows.borrow()[0].get("c"), Some(&3));
+        assert_eq!(rows.borrow()[0].get("a"), None);
+
+        assert!(c.eval(" table[0].c = 'x' ").is_err());
+        assert_eq!(
+            c.eval(" table.length = 5 "),
+            Err(ExecutionError::Exception(
+                "Cannot set property length of host object".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_typescript_declarations() {
+        struct Counter {
+            count: i32,
+        }
+
+        impl JsClass for Counter {
+            const NAME: &'static str = "Counter";
+
+            fn define(class: &mut ClassBuilder<Self>) {
+                class
+                    .constructor(|start: i32| Counter { count: start })
+                    .method("add", |this: &mut Counter, values: Rest<i32>| {
+                        this.count += values.iter().sum::<i32>();
+                        this.count
+                    })
+                    .getter("label", |this: &Counter| format!("{}", this.count))
+                    .property(
+                        "count",
+                        |this: &Counter| this.count,
+                        |this: &mut Counter, count: i32| this.count = count,
+                    );
+            }
+        }
+
+        struct Even(i32);
+
+        impl TryFrom<JsValue> for Even {
+            type Error = ValueError;
+
+            fn try_from(value: JsValue) -> Result<Self, ValueError> {
+                i32::try_from(value).map(Even)
+            }
+        }
+
+        impl FromJsArg for Even {}
+
+        struct Empty;
+
+        impl HostObject for Empty {
+            fn get(&self, _name: &str) -> Option<HostValue> {
+                None
+            }
+        }
+
+        let c = Context::new().unwrap();
+        c.add_callback("each", |_: Vec<String>, _: JsFunction, _: Option<bool>| {
+            true
+        })
+        .unwrap();
+        c.add_callback("lookup", |_: &CallContext, _: HashMap<String, f64>| {
+            Ok::<_, String>(vec![Some(1)])
+        })
+        .unwrap();
+        c.add_callback("half", |even: Even, _: JsInstance<Counter>| even.0 / 2)
+            .unwrap();
+        c.add_callback("first", |values: Vec<JsValue>| values.into_iter().next())
+            .unwrap();
+        assert_eq!(c.eval_as::<i32>(" first([3, 4]) "), Ok(3));
+        c.add_callback("removed", || 1).unwrap();
+        c.remove_callback("removed").unwrap();
+        c.add_callback("log", |_: Arguments| ()).unwrap();
+        c.add_constructor("Point", |_: i32, _: Option<i32>, _: i32| JsValue::Null)
+            .unwrap();
+        c.add_class::<Counter>().unwrap();
+        c.add_host_object("empty", Empty).unwrap();
+        c.declare_function(
+            "lookup",
+            Signature {
+                doc: Some("Find values.".into()),
+                ..Signature::new(vec![("string".into(), false, false)], "number".into())
+            },
+        );
+
+        assert_eq!(
+            c.typescript_declarations(),
+            r#"declare class Counter {
+    constructor(arg0: number);
+    add(...arg0: number[]): number;
+    readonly label: string;
+    count: number;
+}
+
+declare class Point {
+    constructor(arg0: number, arg1: number | null, arg2: number);
+    [key: string]: any;
+}
+
+declare function each(arg0: string[], arg1: Function, arg2?: boolean | null): boolean;
+
+declare const empty: { [key: string]: any };
+
+declare function first(arg0: any[]): any | null;
+
+declare function half(arg0: any, arg1: Counter): number;
+
+declare function log(...args: any[]): any;
+
+/**
+ * Find values.
+ */
+declare function lookup(arg0: string): number;
+"#
+        );
+
+        let c = c.reset().unwrap();
+        assert_eq!(c.typescript_declarations(), "");
+    }
+
+    #[test]
+    fn test_callback_invalid_argcount() {
+        let c = Context::new().unwrap();
+
+        c.add_callback("cb", |a: i32, b: i32| a + b).unwrap();
+
+        assert_eq!(
+            c.eval(" cb(5) "),
+            Err(ExecutionError::Exception(
+                "Invalid argument count: Expected 2, got 1".into()
+            )),
         );
+    }
 
-        c.eval(
-            r#"
-            function addObject(obj) {
-                let sum = 0;
-                for (const key of Object.keys(obj)) {
-                    sum += obj[key];
-                }
-                return sum;
-            }
-        "#,
-        )
+    #[test]
+    fn test_callback_freed() {
+        use std::rc::Rc;
+
+        let c = Context::new().unwrap();
+        let data = Rc::new(42);
+
+        let d = data.clone();
+        c.add_callback("cb", move || *d).unwrap();
+        let d = data.clone();
+        c.add_callback("kept", move || *d).unwrap();
+        assert_eq!(Rc::strong_count(&data), 3);
+
+        // Removed callbacks stay alive while they are referenced.
+        c.eval(" var saved = cb ").unwrap();
+        c.remove_callback("cb").unwrap();
+        assert_eq!(c.eval_as::<bool>(" typeof cb === 'undefined' "), Ok(true));
+        assert_eq!(c.eval_as::<i32>(" saved() "), Ok(42));
+        assert_eq!(Rc::strong_count(&data), 3);
+
+        c.eval(" saved = undefined ").unwrap();
+        assert_eq!(Rc::strong_count(&data), 2);
+
+        // Callbacks are also freed when JS overwrites them.
+        c.eval(" kept = null ").unwrap();
+        assert_eq!(Rc::strong_count(&data), 1);
+
+        assert!(c.remove_callback("Math").is_err());
+        assert!(c.remove_callback("missing").is_err());
+
+        // A cycle through a closure is not visible to the garbage collector,
+        // so it is only freed with the context.
+        let d = data.clone();
+        let mut handler = None;
+        c.add_callback_mut("setHandler", move |f: JsFunction| {
+            let _ = &d;
+            handler.replace(f).is_none()
+        })
         .unwrap();
-        let mut obj = std::collections::HashMap::<String, i32>::new();
-        obj.insert("a".into(), 10);
-        obj.insert("b".into(), 20);
-        obj.insert("c".into(), 30);
-        assert_eq!(
-            c.call_function("addObject", vec![obj]).unwrap(),
-            JsValue::Int(60),
-        );
+        c.eval(" (() => { const set = setHandler; set(() => set); })() ")
+            .unwrap();
+        c.remove_callback("setHandler").unwrap();
+        assert_eq!(Rc::strong_count(&data), 2);
+        drop(c);
+        assert_eq!(Rc::strong_count(&data), 1);
     }
 
     #[test]
-    fn test_call_large_string() {
+    fn test_callback_function_object() {
         let c = Context::new().unwrap();
-        c.eval(" function strLen(s) { return s.length; } ").unwrap();
+        c.add_callback("add", |a: i32, b: i32| a + b).unwrap();
 
-        let s = " ".repeat(200_000);
-        let v = c.call_function("strLen", vec![s]).unwrap();
-        assert_eq!(v, JsValue::Int(200_000));
+        assert_eq!(
+            c.eval(" [typeof add, add.length, add instanceof Function] "),
+            Ok(vec![
+                JsValue::String("function".into()),
+                JsValue::Int(2),
+                JsValue::Bool(true)
+            ]
+            .into())
+        );
+        assert_eq!(c.eval_as::<i32>(" add.call(null, 1, 2) "), Ok(3));
+        assert_eq!(c.eval_as::<i32>(" add.bind(null, 1)(2) "), Ok(3));
+        assert!(c.eval(" new add(1, 2) ").is_err());
     }
 
     #[test]
-    fn call_async() {
+    fn test_callback_mut() {
         let c = Context::new().unwrap();
 
-        c.eval(
-            r#"
-            function asyncOk() {
-                return new Promise((resolve, _) => {
-                    resolve(33);
-                });
+        let mut log = Vec::new();
+        c.add_callback_mut("log", move |ctx: &CallContext, message: String| {
+            log.push(message);
+            if log.len() == 3 {
+                // Recursive calls are rejected.
+                assert!(ctx.eval(" log('nested') ").is_err());
             }
+            log.len() as i32
+        })
+        .unwrap();
 
-            function asyncErr() {
-                return new Promise((_resolve, reject) => {
-                    reject("Failed...");
-                });
-            }
-        "#,
-        )
+        assert_eq!(c.eval_as::<i32>(" log('a'); log('b') "), Ok(2));
+        assert_eq!(c.eval_as::<i32>(" log('c') "), Ok(3));
+        assert_eq!(c.eval_as::<i32>(" log('d') "), Ok(4));
+    }
+
+    #[test]
+    fn test_context_data() {
+        struct Counter(i32);
+
+        let c = Context::new().unwrap();
+        assert!(c.data::<Counter>().is_none());
+
+        c.set_data(Counter(1));
+        c.set_data("name".to_string());
+        c.add_callback("describe", |ctx: &CallContext| {
+            let counter = ctx.data::<Counter>().unwrap();
+            let name = ctx.data::<String>().unwrap();
+            let name = name.borrow();
+            let mut counter = counter.borrow_mut();
+            counter.0 += 1;
+            format!("{} {}", name, counter.0)
+        })
         .unwrap();
 
-        let value = c.call_function("asyncOk", vec![true]).unwrap();
-        assert_eq!(value, JsValue::Int(33));
+        assert_eq!(c.eval_as::<String>(" describe() "), Ok("name 2".into()));
+        c.set_data(Counter(2));
+        assert_eq!(c.eval_as::<String>(" describe() "), Ok("name 3".into()));
+        assert_eq!(c.data::<Counter>().unwrap().borrow().0, 3);
 
-        let res = c.call_function("asyncErr", vec![true]);
-        assert_eq!(
-            res,
-            Err(ExecutionError::Exception(JsValue::String(
-                "Failed...".into()
-            )))
-        );
+        c.data::<Counter>().unwrap().borrow_mut().0 = 10;
+        let c = c.reset().unwrap();
+        assert_eq!(c.data::<Counter>().unwrap().borrow().0, 10);
     }
 
     #[test]
-    fn test_callback() {
+    fn test_callback_many_args() {
         let c = Context::new().unwrap();
 
-        c.add_callback("cb1", |flag: bool| !flag).unwrap();
-        assert_eq!(c.eval("cb1(true)").unwrap(), JsValue::Bool(false),);
+        c.add_callback(
+            "sum",
+            |a1: i32,
+             a2: i32,
+             a3: i32,
+             a4: i32,
+             a5: i32,
+             a6: i32,
+             a7: i32,
+             a8: i32,
+             a9: i32,
+             a10: i32,
+             a11: i32,
+             a12: i32| { a1 + a2 + a3 + a4 + a5 + a6 + a7 + a8 + a9 + a10 + a11 + a12 },
+        )
+        .unwrap();
 
-        c.add_callback("concat2", |a: String, b: String| format!("{}{}", a, b))
-            .unwrap();
         assert_eq!(
-            c.eval(r#"concat2("abc", "def")"#).unwrap(),
-            JsValue::String("abcdef".into()),
+            c.eval_as::<i32>(" sum(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12) "),
+            Ok(78)
         );
-
-        c.add_callback("add2", |a: i32, b: i32| -> i32 { a + b })
-            .unwrap();
-        assert_eq!(c.eval("add2(5, 11)").unwrap(), JsValue::Int(16),);
+        assert_eq!(c.eval_as::<i32>(" sum.length "), Ok(12));
     }
 
     #[test]
-    fn test_callback_argn_variants() {
-        macro_rules! callback_argn_tests {
-            [
-                $(
-                    $len:literal : ( $( $argn:ident : $argv:literal ),* ),
-                )*
-            ] => {
-                $(
-                   {
-                       // Test plain return type.
-                        let name = format!("cb{}", $len);
-                        let c = Context::new().unwrap();
-                        c.add_callback(&name, | $( $argn : i32 ),*| -> i32 {
-                            $( $argn + )* 0
-                        }).unwrap();
+    fn test_callback_optional_args() {
+        let c = Context::new().unwrap();
 
-                        let code = format!("{}( {} )", name, "1,".repeat($len));
-                        let v = c.eval(&code).unwrap();
-                        assert_eq!(v, JsValue::Int($len));
+        c.add_callback(
+            "greet",
+            |name: String, greeting: Option<String>, options: Option<HashMap<String, bool>>| {
+                let greeting = greeting.unwrap_or_else(|| "Hello".to_string());
+                let shout = options
+                    .and_then(|options| options.get("shout").cloned())
+                    .unwrap_or(false);
+                let text = format!("{}, {}", greeting, name);
+                if shout {
+                    text.to_uppercase()
+                } else {
+                    text
+                }
+            },
+        )
+        .unwrap();
 
-                        // Test Result<T, E> return type with OK(_) returns.
-                        let name = format!("cbres{}", $len);
-                        c.add_callback(&name, | $( $argn : i32 ),*| -> Result<i32, String> {
-                            Ok($( $argn + )* 0)
-                        }).unwrap();
+        assert_eq!(c.eval_as::<String>(" greet('a') "), Ok("Hello, a".into()));
+        assert_eq!(
+            c.eval_as::<String>(" greet('a', 'Hi') "),
+            Ok("Hi, a".into())
+        );
+        assert_eq!(
+            c.eval_as::<String>(" greet('a', undefined, { shout: true }) "),
+            Ok("HELLO, A".into())
+        );
+        assert_eq!(c.eval_as::<i32>(" greet.length "), Ok(1));
+        assert_eq!(
+            c.eval(" greet() "),
+            Err(ExecutionError::Exception(
+                "Invalid argument count: Expected 1 to 3, got 0".into()
+            )),
+        );
+        assert_eq!(
+            c.eval(" greet('a', 'b', {}, 1) "),
+            Err(ExecutionError::Exception(
+                "Invalid argument count: Expected 1 to 3, got 4".into()
+            )),
+        );
 
-                        let code = format!("{}( {} )", name, "1,".repeat($len));
-                        let v = c.eval(&code).unwrap();
-                        assert_eq!(v, JsValue::Int($len));
+        // Types that accept `undefined` are only optional if they say so.
+        struct Flag(bool);
 
-                        // Test Result<T, E> return type with Err(_) returns.
-                        let name = format!("cbreserr{}", $len);
-                        c.add_callback(&name, #[allow(unused_variables)] | $( $argn : i32 ),*| -> Result<i32, String> {
-                            Err("error".into())
-                        }).unwrap();
+        impl TryFrom<JsValue> for Flag {
+            type Error = ValueError;
 
-                        let code = format!("{}( {} )", name, "1,".repeat($len));
-                        let res = c.eval(&code);
-                        assert_eq!(res, Err(ExecutionError::Exception("error".into())));
-                   }
-                )*
+            fn try_from(value: JsValue) -> Result<Self, ValueError> {
+                Ok(Flag(value == JsValue::Bool(true)))
             }
         }
 
-        callback_argn_tests![
-            1: (a : 1),
-        ]
+        impl FromJsArg for Flag {}
+
+        c.add_callback("flag", |flag: Flag| flag.0).unwrap();
+        assert_eq!(c.eval_as::<i32>(" flag.length "), Ok(1));
+        assert_eq!(c.eval_as::<bool>(" flag(undefined) "), Ok(false));
+        assert_eq!(
+            c.eval(" flag() "),
+            Err(ExecutionError::Exception(
+                "Invalid argument count: Expected 1, got 0".into()
+            )),
+        );
     }
 
     #[test]
-    fn test_callback_varargs() {
+    fn test_callback_rest_args() {
         let c = Context::new().unwrap();
 
-        // No return.
-        c.add_callback("cb", |args: Arguments| {
-            let args = args.into_vec();
-            assert_eq!(
-                args,
-                vec![
-                    JsValue::String("hello".into()),
-                    JsValue::Bool(true),
-                    JsValue::from(100),
-                ]
-            );
+        c.add_callback("join", |separator: String, parts: Rest<String>| {
+            parts.join(&separator)
         })
         .unwrap();
-        c.eval(" cb('hello', true, 100) ").unwrap();
 
-        // With return.
-        c.add_callback("cb2", |args: Arguments| -> u32 {
-            let args = args.into_vec();
-            assert_eq!(
-                args,
-                vec![JsValue::from(1), JsValue::from(10), JsValue::from(100),]
-            );
-            111
-        })
-        .unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" join('-', 'a', 'b', 'c') "),
+            Ok("a-b-c".into())
+        );
+        assert_eq!(c.eval_as::<String>(" join('-') "), Ok("".into()));
+        assert_eq!(
+            c.eval(" join() "),
+            Err(ExecutionError::Exception(
+                "Invalid argument count: Expected at least 1, got 0".into()
+            )),
+        );
+        assert!(c.eval(" join('-', 1) ").is_err());
+    }
+
+    #[test]
+    fn test_queue_microtask() {
+        let c = Context::new().unwrap();
+
         c.eval(
             r#"
-           var x = cb2(1, 10, 100);
-           if (x !== 111) {
-            throw new Error('Expected 111, got ' + x);
-           }
-       "#,
+            var order = [];
+            queueMicrotask(() => order.push(2));
+            order.push(1);
+        "#,
         )
         .unwrap();
+        assert!(c.has_pending_jobs());
+        assert_eq!(c.eval("order").unwrap(), vec![1].into());
+
+        assert_eq!(c.run_pending_jobs(None), Ok(1));
+        assert!(!c.has_pending_jobs());
+        assert_eq!(c.eval("order").unwrap(), vec![1, 2].into());
+
+        let err_msg = c.eval(" queueMicrotask(1) ").unwrap_err().to_string();
+        assert!(err_msg.contains("TypeError"));
     }
 
     #[test]
-    fn test_callback_invalid_argcount() {
-        let c = Context::new().unwrap();
+    fn test_run_pending_jobs() {
+        use std::sync::{Arc, Mutex};
 
-        c.add_callback("cb", |a: i32, b: i32| a + b).unwrap();
+        let c = Context::new().unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
 
+        for index in 0..3 {
+            let calls = calls.clone();
+            c.enqueue_job(move || calls.lock().unwrap().push(index))
+                .unwrap();
+        }
+        assert_eq!(c.run_pending_jobs(Some(2)), Ok(2));
+        assert_eq!(*calls.lock().unwrap(), vec![0, 1]);
+        assert_eq!(c.run_pending_jobs(Some(2)), Ok(1));
+        assert_eq!(*calls.lock().unwrap(), vec![0, 1, 2]);
+        assert_eq!(c.run_pending_jobs(None), Ok(0));
+
+        // Exceptions thrown by jobs are returned.
+        c.eval(" queueMicrotask(() => { throw new Error('job failed'); }) ")
+            .unwrap();
         assert_eq!(
-            c.eval(" cb(5) "),
-            Err(ExecutionError::Exception(
-                "Invalid argument count: Expected 2, got 1".into()
-            )),
+            c.run_pending_jobs(None),
+            Err(ExecutionError::Exception("Error: job failed".into()))
         );
     }
 
@@ -752,6 +3145,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn memory_limit_callback_error() {
+        let c = Context::builder().memory_limit(1_000_000).build().unwrap();
+        c.add_callback("fail", || -> Result<i32, ExecutionError> {
+            Err(ExecutionError::Exception("abc".repeat(1_000_000).into()))
+        })
+        .unwrap();
+
+        // The error can not be converted, so the conversion error is thrown.
+        let message = c
+            .eval_as::<String>(" try { fail() } catch (e) { String(e) } ")
+            .unwrap();
+        assert!(
+            message.contains("Could not create string in runtime"),
+            "{}",
+            message
+        );
+    }
+
     #[test]
     fn context_reset() {
         let c = Context::new().unwrap();
@@ -797,6 +3209,106 @@ mod tests {
         assert_eq!(v, "lala".into());
     }
 
+    #[test]
+    fn test_date() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let c = Context::new().unwrap();
+
+        let value = c.eval(" new Date(-1500) ").unwrap();
+        assert_eq!(value, JsValue::Date(JsDate::from_timestamp_millis(-1500)));
+        assert_eq!(
+            SystemTime::try_from(value),
+            Ok(UNIX_EPOCH - Duration::from_millis(1500))
+        );
+
+        let time = UNIX_EPOCH + Duration::from_micros(1_600_000_123_456_789);
+        c.add_callback("time", move || time).unwrap();
+        assert_eq!(
+            c.eval_as::<f64>(" time().getTime() "),
+            Ok(1_600_000_123_456.0)
+        );
+        c.add_callback("seconds", |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH).unwrap().as_secs() as i32
+        })
+        .unwrap();
+        assert_eq!(c.eval_as::<i32>(" seconds(new Date(2500)) "), Ok(2));
+
+        // Invalid dates are received as invalid `JsDate`s, not as timestamps.
+        let value = c.eval(" new Date(NaN) ").unwrap();
+        assert_eq!(value, JsValue::Date(JsDate::invalid()));
+        assert_eq!(SystemTime::try_from(value), Err(ValueError::InvalidDate));
+        assert!(c.eval(" seconds(new Date('x')) ").is_err());
+        assert_eq!(
+            JsValue::Date(JsDate::from_timestamp_millis(8_640_000_000_000_001)),
+            JsValue::Date(JsDate::invalid())
+        );
+
+        c.eval(
+            " function iso(date) { return isNaN(date) ? 'Invalid Date' : date.toISOString(); } ",
+        )
+        .unwrap();
+        let dates = vec![
+            JsDate::from_timestamp_millis(0),
+            JsDate::from_timestamp_millis(951_782_400_000),
+            JsDate::from_timestamp_millis(-62_198_755_200_000),
+            JsDate::from_timestamp_millis(8_640_000_000_000_000),
+            JsDate::invalid(),
+        ];
+        for date in dates {
+            assert_eq!(
+                c.call_function("iso", vec![date]),
+                Ok(JsValue::String(date.to_string()))
+            );
+        }
+        assert_eq!(JsDate::invalid().to_string(), "Invalid Date");
+
+        // Dates are converted with the builtin `Date` and `getTime`.
+        let value = c
+            .eval(" var d = new Date(3000); d.getTime = 1; d ")
+            .unwrap();
+        assert_eq!(value, JsValue::Date(JsDate::from_timestamp_millis(3000)));
+        assert_eq!(
+            c.eval(" Object.create(Date.prototype) "),
+            Err(ExecutionError::Conversion(ValueError::Internal(
+                "Could not convert 'Date' instance to timestamp".into()
+            )))
+        );
+        assert_eq!(c.eval_as::<i32>(" 1 "), Ok(1));
+        c.eval(" delete globalThis.Date; ").unwrap();
+        let mut object = JsObjectMap::new();
+        object.insert("a".into(), JsValue::Int(1));
+        assert_eq!(c.eval(" ({a: 1}) "), Ok(JsValue::Object(object)));
+        assert_eq!(c.eval_as::<i32>(" seconds(time()) "), Ok(1_600_000_123));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_date() {
+        let c = Context::new().unwrap();
+
+        let primitive = time::Date::from_calendar_date(2020, time::Month::February, 29)
+            .unwrap()
+            .with_hms_milli(12, 30, 15, 250)
+            .unwrap();
+        let date = primitive.assume_utc();
+        c.add_callback("date", move || date).unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" date().toISOString() "),
+            Ok("2020-02-29T12:30:15.250Z".into())
+        );
+
+        let value = c
+            .eval(" new Date(Date.UTC(2020, 1, 29, 12, 30, 15, 250)) ")
+            .unwrap();
+        assert_eq!(time::OffsetDateTime::try_from(value.clone()), Ok(date));
+        assert_eq!(time::PrimitiveDateTime::try_from(value), Ok(primitive));
+        assert_eq!(
+            time::OffsetDateTime::try_from(c.eval(" new Date(NaN) ").unwrap()),
+            Err(ValueError::InvalidDate)
+        );
+    }
+
     #[cfg(feature = "chrono")]
     #[test]
     fn chrono_serialize() {
@@ -815,7 +3327,7 @@ mod tests {
         let now_millis = now.timestamp_millis();
 
         let timestamp = c
-            .call_function("dateToTimestamp", vec![JsValue::Date(now.clone())])
+            .call_function("dateToTimestamp", vec![JsValue::from(now)])
             .unwrap();
 
         assert_eq!(timestamp, JsValue::Float(now_millis as f64));
@@ -829,9 +3341,27 @@ mod tests {
         let c = build_context();
 
         let value = c.eval(" new Date(1234567555) ").unwrap();
-        let datetime = chrono::Utc.timestamp_millis(1234567555);
+        let datetime = chrono::Utc.timestamp_millis_opt(1234567555).unwrap();
+
+        assert_eq!(value, JsValue::from(datetime));
+        assert_eq!(
+            chrono::DateTime::<chrono::FixedOffset>::try_from(value.clone()),
+            Ok(datetime.into())
+        );
+        assert_eq!(
+            chrono::NaiveDateTime::try_from(value.clone()),
+            Ok(datetime.naive_utc())
+        );
+        match value {
+            JsValue::Date(date) => assert_eq!(chrono::DateTime::try_from(date), Ok(datetime)),
+            _ => panic!("expected a date"),
+        }
 
-        assert_eq!(value, JsValue::Date(datetime));
+        let value = c.eval(" new Date(NaN) ").unwrap();
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::try_from(value),
+            Err(ValueError::InvalidDate)
+        );
     }
 
     #[cfg(feature = "chrono")]
@@ -841,15 +3371,21 @@ mod tests {
 
         c.eval(" function identity(x) { return x; } ").unwrap();
         let d = chrono::Utc::now();
-        let td = JsValue::Date(d.clone());
+        let td = JsValue::from(d);
         let td2 = c.call_function("identity", vec![td.clone()]).unwrap();
-        let d2 = if let JsValue::Date(x) = td2 {
-            x
-        } else {
-            panic!("expected date")
-        };
+        let d2 = chrono::DateTime::<chrono::Utc>::try_from(td2).unwrap();
 
         assert_eq!(d.timestamp_millis(), d2.timestamp_millis());
+
+        let naive = chrono::NaiveDate::from_ymd_opt(2020, 2, 29)
+            .unwrap()
+            .and_hms_milli_opt(12, 30, 15, 250)
+            .unwrap();
+        c.add_callback("naive", move || naive).unwrap();
+        assert_eq!(
+            c.eval_as::<String>(" naive().toISOString() "),
+            Ok("2020-02-29T12:30:15.250Z".into())
+        );
     }
 
     #[cfg(feature = "bigint")]
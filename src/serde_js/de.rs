@@ -0,0 +1,666 @@
+use std::convert::TryFrom;
+
+use libquickjs_sys as q;
+use serde::de::{
+    self, value::SeqDeserializer, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::bindings::{
+    buffer_class_id, collection_to_array, deserialize_buffer, deserialize_value, free_value,
+    DeserializeState, OwnedValueRef, TAG_BIG_INT, TAG_BOOL, TAG_FLOAT64, TAG_INT, TAG_NULL,
+    TAG_OBJECT, TAG_STRING, TAG_SYMBOL, TAG_UNDEFINED,
+};
+use crate::{JsValue, SymbolKeys, TypedArray, ValueError};
+
+/// Read a string, which may contain \0 bytes.
+fn read_string(context: *mut q::JSContext, value: q::JSValue) -> Result<String, ValueError> {
+    let mut len = 0;
+    let ptr = unsafe { q::JS_ToCStringLen2(context, &mut len, value, 0) };
+    if ptr.is_null() {
+        unsafe { free_value(context, q::JS_GetException(context)) };
+        return Err(ValueError::Internal(
+            "Could not convert string: got a null pointer".into(),
+        ));
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    let string = std::str::from_utf8(bytes)
+        .map(str::to_string)
+        .map_err(ValueError::InvalidString);
+    unsafe { q::JS_FreeCString(context, ptr) };
+    string
+}
+
+fn array_length(context: *mut q::JSContext, array: q::JSValue) -> Result<u32, ValueError> {
+    let length = unsafe { q::JS_GetPropertyStr(context, array, b"length\0".as_ptr() as _) };
+    let length = OwnedValueRef::checked(context, length, "get array length")?;
+    let mut len: i64 = 0;
+    if unsafe { q::JS_ToInt64(context, &mut len, length.raw()) } < 0 {
+        return Err(ValueError::Internal(
+            "Could not determine array length".into(),
+        ));
+    }
+    u32::try_from(len).map_err(|_| ValueError::Internal("Invalid array length".into()))
+}
+
+fn array_element<'a>(
+    context: *mut q::JSContext,
+    array: q::JSValue,
+    index: u32,
+) -> Result<OwnedValueRef<'a>, ValueError> {
+    let value = unsafe { q::JS_GetPropertyUint32(context, array, index) };
+    OwnedValueRef::checked(context, value, "get array element")
+}
+
+/// Convert the entries of a `Map` or the values of a `Set` to an array.
+fn collection_array<'a>(
+    context: *mut q::JSContext,
+    collection: q::JSValue,
+    class_id: q::JSClassID,
+) -> Result<OwnedValueRef<'a>, ValueError> {
+    let array = collection_to_array(context, &collection, class_id)?;
+    OwnedValueRef::checked(context, array, "convert collection to array")
+}
+
+/// Deserializes quickjs values into Rust values.
+///
+/// The value is borrowed, it is not freed by the deserializer.
+pub(super) struct Deserializer<'a> {
+    context: *mut q::JSContext,
+    value: q::JSValue,
+    state: &'a mut DeserializeState,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(
+        context: *mut q::JSContext,
+        value: q::JSValue,
+        state: &'a mut DeserializeState,
+    ) -> Self {
+        Self {
+            context,
+            value,
+            state,
+        }
+    }
+
+    fn is_nullish(&self) -> bool {
+        self.value.tag == TAG_NULL || self.value.tag == TAG_UNDEFINED
+    }
+
+    /// BigInts are read as `i64` or `u64`.
+    fn deserialize_bigint<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        let digits = read_string(self.context, self.value)?;
+        if let Ok(value) = digits.parse::<i64>() {
+            visitor.visit_i64(value)
+        } else if let Ok(value) = digits.parse::<u64>() {
+            visitor.visit_u64(value)
+        } else {
+            Err(ValueError::Custom(format!(
+                "BigInt {} does not fit into 64 bits",
+                digits
+            )))
+        }
+    }
+
+    /// An enum variant with content is an object with a single key.
+    fn deserialize_variant_object<'de, V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        let mut access = ObjectAccess::new(self.context, self.value, self.state)?;
+        let key = access.next_key::<String>()?;
+        let value = access.value.take();
+        let (key, value) = match (key, value, access.index == access.count) {
+            (Some(key), Some(value), true) => (key, value),
+            _ => {
+                return Err(de::Error::invalid_value(
+                    de::Unexpected::Map,
+                    &"an object with a single key",
+                ))
+            }
+        };
+        visitor.visit_enum(Variant {
+            context: access.context,
+            key,
+            value,
+            state: access.state,
+        })
+    }
+
+    fn deserialize_object<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        let Deserializer {
+            context,
+            value,
+            state,
+        } = self;
+
+        if unsafe { q::JS_IsArray(context, value) } > 0 {
+            let len = array_length(context, value)?;
+            visit_array(context, value, len, state, visitor)
+        } else if let Some(class_id) = buffer_class_id(&value) {
+            match deserialize_buffer(context, &value, class_id)? {
+                JsValue::ArrayBuffer(bytes)
+                | JsValue::TypedArray(TypedArray::Uint8(bytes))
+                | JsValue::TypedArray(TypedArray::Uint8Clamped(bytes)) => {
+                    visitor.visit_byte_buf(bytes)
+                }
+                JsValue::TypedArray(array) => visit_typed_array(array, visitor),
+                _ => Err(ValueError::UnexpectedType),
+            }
+        } else if !unsafe { q::JS_GetOpaque(value, q::JS_CLASS_MAP) }.is_null() {
+            let entries = collection_array(context, value, q::JS_CLASS_MAP)?;
+            let len = array_length(context, entries.raw())?;
+            visitor.visit_map(EntriesAccess {
+                context,
+                entries: entries.raw(),
+                index: 0,
+                len,
+                entry: None,
+                state,
+            })
+        } else if !unsafe { q::JS_GetOpaque(value, q::JS_CLASS_SET) }.is_null() {
+            let values = collection_array(context, value, q::JS_CLASS_SET)?;
+            let len = array_length(context, values.raw())?;
+            visit_array(context, values.raw(), len, state, visitor)
+        } else {
+            visitor.visit_map(ObjectAccess::new(context, value, state)?)
+        }
+    }
+
+    /// Integral floats are accepted for integers.
+    fn deserialize_integer<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.value.tag == TAG_FLOAT64 {
+            let value = unsafe { self.value.u.float64 };
+            if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+                return visitor.visit_i64(value as i64);
+            } else if value.fract() == 0.0 && value >= 0.0 && value < u64::MAX as f64 {
+                return visitor.visit_u64(value as u64);
+            }
+        }
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+fn visit_array<'de, V: Visitor<'de>>(
+    context: *mut q::JSContext,
+    array: q::JSValue,
+    len: u32,
+    state: &mut DeserializeState,
+    visitor: V,
+) -> Result<V::Value, ValueError> {
+    state.check_length(len as usize)?;
+    let mut access = ArrayAccess {
+        context,
+        array,
+        index: 0,
+        len,
+        state,
+    };
+    let value = visitor.visit_seq(&mut access)?;
+    if access.index < access.len {
+        return Err(de::Error::invalid_length(
+            len as usize,
+            &"fewer elements in array",
+        ));
+    }
+    Ok(value)
+}
+
+fn visit_typed_array<'de, V: Visitor<'de>>(
+    array: TypedArray,
+    visitor: V,
+) -> Result<V::Value, ValueError> {
+    macro_rules! visit {
+        ($values:expr) => {{
+            let mut seq = SeqDeserializer::<_, ValueError>::new($values.into_iter());
+            let value = visitor.visit_seq(&mut seq)?;
+            seq.end()?;
+            Ok(value)
+        }};
+    }
+
+    match array {
+        TypedArray::Int8(v) => visit!(v),
+        TypedArray::Uint8(v) | TypedArray::Uint8Clamped(v) => visit!(v),
+        TypedArray::Int16(v) => visit!(v),
+        TypedArray::Uint16(v) => visit!(v),
+        TypedArray::Int32(v) => visit!(v),
+        TypedArray::Uint32(v) => visit!(v),
+        TypedArray::BigInt64(v) => visit!(v),
+        TypedArray::BigUint64(v) => visit!(v),
+        TypedArray::Float32(v) => visit!(v),
+        TypedArray::Float64(v) => visit!(v),
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Deserializer<'a> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        let value = self.value;
+        match value.tag {
+            TAG_INT => visitor.visit_i32(unsafe { value.u.int32 }),
+            TAG_BOOL => visitor.visit_bool(unsafe { value.u.int32 } != 0),
+            TAG_NULL | TAG_UNDEFINED => visitor.visit_unit(),
+            TAG_FLOAT64 => visitor.visit_f64(unsafe { value.u.float64 }),
+            TAG_STRING => visitor.visit_string(read_string(self.context, value)?),
+            TAG_BIG_INT => self.deserialize_bigint(visitor),
+            TAG_OBJECT => {
+                self.state.enter(&value)?;
+                let state = &mut *self.state;
+                let result =
+                    Deserializer::new(self.context, value, state).deserialize_object(visitor);
+                self.state.leave();
+                result
+            }
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.is_nullish() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.is_nullish() {
+            visitor.visit_unit()
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Enums are either a string with the variant name or an object with the
+    /// variant name as its only key.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        match self.value.tag {
+            TAG_STRING => {
+                let variant: de::value::StringDeserializer<ValueError> =
+                    read_string(self.context, self.value)?.into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            TAG_OBJECT => {
+                self.state.enter(&self.value)?;
+                let state = &mut *self.state;
+                let result = Deserializer::new(self.context, self.value, state)
+                    .deserialize_variant_object(visitor);
+                self.state.leave();
+                result
+            }
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_integer(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+/// Accesses the elements of an array.
+struct ArrayAccess<'a> {
+    context: *mut q::JSContext,
+    array: q::JSValue,
+    index: u32,
+    len: u32,
+    state: &'a mut DeserializeState,
+}
+
+impl<'de, 'a> SeqAccess<'de> for ArrayAccess<'a> {
+    type Error = ValueError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ValueError> {
+        if self.index >= self.len {
+            return Ok(None);
+        }
+        self.state.count_element()?;
+        let element = array_element(self.context, self.array, self.index)?;
+        self.index += 1;
+        seed.deserialize(Deserializer::new(self.context, element.raw(), self.state))
+            .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.index) as usize)
+    }
+}
+
+/// Accesses the entries of a `Map`, converted to an array of pairs.
+struct EntriesAccess<'a> {
+    context: *mut q::JSContext,
+    entries: q::JSValue,
+    index: u32,
+    len: u32,
+    /// The entry whose key was read last.
+    entry: Option<OwnedValueRef<'a>>,
+    state: &'a mut DeserializeState,
+}
+
+impl<'de, 'a> MapAccess<'de> for EntriesAccess<'a> {
+    type Error = ValueError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ValueError> {
+        if self.index >= self.len {
+            return Ok(None);
+        }
+        self.state.count_element()?;
+        let entry = array_element(self.context, self.entries, self.index)?;
+        self.index += 1;
+        let key = array_element(self.context, entry.raw(), 0)?;
+        self.entry = Some(entry);
+        seed.deserialize(Deserializer::new(self.context, key.raw(), self.state))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ValueError> {
+        let entry = self
+            .entry
+            .take()
+            .ok_or_else(|| ValueError::Internal("Map value without a key".into()))?;
+        let value = array_element(self.context, entry.raw(), 1)?;
+        seed.deserialize(Deserializer::new(self.context, value.raw(), self.state))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.index) as usize)
+    }
+}
+
+/// Accesses the enumerable own properties of an object.
+struct ObjectAccess<'a> {
+    context: *mut q::JSContext,
+    object: q::JSValue,
+    properties: *mut q::JSPropertyEnum,
+    count: u32,
+    index: u32,
+    /// The value of the property whose key was read last.
+    value: Option<OwnedValueRef<'a>>,
+    state: &'a mut DeserializeState,
+}
+
+impl<'a> ObjectAccess<'a> {
+    fn new(
+        context: *mut q::JSContext,
+        object: q::JSValue,
+        state: &'a mut DeserializeState,
+    ) -> Result<Self, ValueError> {
+        // Symbol keys are only enumerated to reject them.
+        let mut flags = q::JS_GPN_STRING_MASK | q::JS_GPN_ENUM_ONLY;
+        if state.symbol_keys == SymbolKeys::Error {
+            flags |= q::JS_GPN_SYMBOL_MASK;
+        }
+
+        let mut properties = std::ptr::null_mut();
+        let mut count = 0;
+        let ret = unsafe {
+            q::JS_GetOwnPropertyNames(context, &mut properties, &mut count, object, flags as i32)
+        };
+        if ret != 0 {
+            return Err(ValueError::Internal(
+                "Could not get object properties".into(),
+            ));
+        }
+        Ok(Self {
+            context,
+            object,
+            properties,
+            count,
+            index: 0,
+            value: None,
+            state,
+        })
+    }
+}
+
+impl<'a> Drop for ObjectAccess<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            for index in 0..self.count {
+                q::JS_FreeAtom(self.context, (*self.properties.add(index as usize)).atom);
+            }
+            q::js_free(self.context, self.properties as *mut std::ffi::c_void);
+        }
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for ObjectAccess<'a> {
+    type Error = ValueError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ValueError> {
+        if self.index >= self.count {
+            return Ok(None);
+        }
+        let atom = unsafe { (*self.properties.add(self.index as usize)).atom };
+        self.index += 1;
+
+        let key = unsafe { q::JS_AtomToValue(self.context, atom) };
+        let key = OwnedValueRef::checked(self.context, key, "get object property name")?;
+        if key.raw().tag == TAG_SYMBOL {
+            return match deserialize_value(self.context, &key.raw())? {
+                JsValue::Symbol(symbol) => Err(ValueError::SymbolKey(symbol)),
+                _ => Err(ValueError::UnexpectedType),
+            };
+        }
+        let key = read_string(self.context, key.raw())?;
+
+        self.state.count_element()?;
+        let value =
+            unsafe { q::JS_GetPropertyInternal(self.context, self.object, atom, self.object, 0) };
+        self.value = Some(OwnedValueRef::checked(
+            self.context,
+            value,
+            "get object property",
+        )?);
+        seed.deserialize(KeyDeserializer { key }).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ValueError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| ValueError::Internal("Object value without a key".into()))?;
+        seed.deserialize(Deserializer::new(self.context, value.raw(), self.state))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.count - self.index) as usize)
+    }
+}
+
+/// An enum variant that is represented as an object with a single key.
+struct Variant<'a> {
+    context: *mut q::JSContext,
+    key: String,
+    value: OwnedValueRef<'a>,
+    state: &'a mut DeserializeState,
+}
+
+impl<'a> Variant<'a> {
+    fn deserializer(&mut self) -> Deserializer<'_> {
+        Deserializer::new(self.context, self.value.raw(), self.state)
+    }
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for Variant<'a> {
+    type Error = ValueError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), ValueError> {
+        let key: de::value::StrDeserializer<ValueError> = self.key.as_str().into_deserializer();
+        let variant = seed.deserialize(key)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for Variant<'a> {
+    type Error = ValueError;
+
+    fn unit_variant(mut self) -> Result<(), ValueError> {
+        de::Deserialize::deserialize(self.deserializer())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        mut self,
+        seed: T,
+    ) -> Result<T::Value, ValueError> {
+        seed.deserialize(self.deserializer())
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        mut self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        de::Deserializer::deserialize_seq(self.deserializer(), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        mut self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        de::Deserializer::deserialize_map(self.deserializer(), visitor)
+    }
+}
+
+/// Deserializes property names, which are parsed for numeric keys.
+struct KeyDeserializer {
+    key: String,
+}
+
+macro_rules! deserialize_parsed {
+    ( $( $method:ident => $visit:ident, )* ) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                match self.key.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => visitor.visit_string(self.key),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_string(self.key)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        let variant: de::value::StringDeserializer<ValueError> = self.key.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
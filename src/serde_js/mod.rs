@@ -0,0 +1,45 @@
+//! Conversion between Rust types and quickjs values with serde.
+//!
+//! Values are converted directly, without building a `JsValue` first.
+//! The data model matches `serde_json`: structs and maps become objects,
+//! sequences and tuples become arrays and enums are externally tagged.
+
+mod de;
+mod ser;
+
+use std::fmt;
+
+use libquickjs_sys as q;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::bindings::DeserializeState;
+use crate::ValueError;
+
+/// Serialize a Rust value into a new quickjs value.
+pub(crate) fn to_js<T: Serialize + ?Sized>(
+    context: *mut q::JSContext,
+    value: &T,
+) -> Result<q::JSValue, ValueError> {
+    value.serialize(ser::Serializer::new(context))
+}
+
+/// Deserialize a quickjs value into a Rust value.
+pub(crate) fn from_js<T: DeserializeOwned>(
+    context: *mut q::JSContext,
+    value: &q::JSValue,
+) -> Result<T, ValueError> {
+    let mut state = DeserializeState::new(context);
+    T::deserialize(de::Deserializer::new(context, *value, &mut state))
+}
+
+impl serde::ser::Error for ValueError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ValueError::Custom(msg.to_string())
+    }
+}
+
+impl serde::de::Error for ValueError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ValueError::Custom(msg.to_string())
+    }
+}
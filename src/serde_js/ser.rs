@@ -0,0 +1,627 @@
+use std::{convert::TryFrom, marker::PhantomData, os::raw::c_char};
+
+use libquickjs_sys as q;
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::bindings::{
+    free_value, js_null_value, new_array_buffer, ContextWrapper, OwnedValueRef, TAG_BOOL,
+    TAG_FLOAT64, TAG_INT,
+};
+use crate::ValueError;
+
+/// The largest integer that a Javascript number can represent exactly.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+fn int(value: i32) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: value },
+        tag: TAG_INT,
+    }
+}
+
+fn float(value: f64) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { float64: value },
+        tag: TAG_FLOAT64,
+    }
+}
+
+fn boolean(value: bool) -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion {
+            int32: value as i32,
+        },
+        tag: TAG_BOOL,
+    }
+}
+
+fn new_object<'a>(context: *mut q::JSContext) -> Result<OwnedValueRef<'a>, ValueError> {
+    OwnedValueRef::checked(
+        context,
+        unsafe { q::JS_NewObject(context) },
+        "create object",
+    )
+}
+
+/// Clear the pending exception and return an error.
+fn failed(context: *mut q::JSContext, what: &str) -> ValueError {
+    unsafe { free_value(context, q::JS_GetException(context)) };
+    ValueError::Internal(format!("Could not {}", what))
+}
+
+/// Define a property of an object, taking ownership of the value.
+fn define_property(
+    context: *mut q::JSContext,
+    object: q::JSValue,
+    key: &str,
+    value: q::JSValue,
+) -> Result<(), ValueError> {
+    // Atoms are created from the exact length, so keys may contain \0.
+    let ret = unsafe {
+        let atom = q::JS_NewAtomLen(context, key.as_ptr() as *const c_char, key.len() as _);
+        let ret = q::JS_DefinePropertyValue(context, object, atom, value, q::JS_PROP_C_W_E as i32);
+        q::JS_FreeAtom(context, atom);
+        ret
+    };
+    if ret < 0 {
+        return Err(failed(context, "add property to object"));
+    }
+    Ok(())
+}
+
+/// Wrap the value of an enum variant in an object with the variant name as
+/// its only key.
+fn wrap_variant(
+    context: *mut q::JSContext,
+    variant: &str,
+    value: q::JSValue,
+) -> Result<q::JSValue, ValueError> {
+    let object = match new_object(context) {
+        Ok(object) => object,
+        Err(e) => {
+            unsafe { free_value(context, value) };
+            return Err(e);
+        }
+    };
+    define_property(context, object.raw(), variant, value)?;
+    Ok(object.into_inner())
+}
+
+/// Serializes Rust values into quickjs values.
+pub(super) struct Serializer<'a> {
+    context: *mut q::JSContext,
+    /// Arrays and objects that are being built are owned by the context.
+    wrapper: PhantomData<&'a ContextWrapper>,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(context: *mut q::JSContext) -> Self {
+        Self {
+            context,
+            wrapper: PhantomData,
+        }
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    type SerializeSeq = SerializeArray<'a>;
+    type SerializeTuple = SerializeArray<'a>;
+    type SerializeTupleStruct = SerializeArray<'a>;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray<'a>>;
+    type SerializeMap = SerializeObject<'a>;
+    type SerializeStruct = SerializeObject<'a>;
+    type SerializeStructVariant = SerializeVariant<SerializeObject<'a>>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(int(v))
+    }
+
+    /// Integers outside of the safe range of numbers become a `BigInt`.
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        if let Ok(v) = i32::try_from(v) {
+            Ok(int(v))
+        } else if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v) {
+            Ok(float(v as f64))
+        } else {
+            let value = unsafe { q::JS_NewBigInt64(self.context, v) };
+            OwnedValueRef::checked(self.context, value, "create BigInt")
+                .map(OwnedValueRef::into_inner)
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        if v <= MAX_SAFE_INTEGER as u64 {
+            self.serialize_i64(v as i64)
+        } else {
+            let value = unsafe { q::JS_NewBigUint64(self.context, v) };
+            OwnedValueRef::checked(self.context, value, "create BigInt")
+                .map(OwnedValueRef::into_inner)
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        let value =
+            unsafe { q::JS_NewStringLen(self.context, v.as_ptr() as *const c_char, v.len() as _) };
+        OwnedValueRef::checked(self.context, value, "create string").map(OwnedValueRef::into_inner)
+    }
+
+    /// Bytes become an `ArrayBuffer`.
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        new_array_buffer(self.context, v.to_vec())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(js_null_value())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(js_null_value())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(js_null_value())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        let context = self.context;
+        let value = value.serialize(self)?;
+        wrap_variant(context, variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        SerializeArray::new(self.context)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: SerializeArray::new(self.context)?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        SerializeObject::new(self.context)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        SerializeObject::new(self.context)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: SerializeObject::new(self.context)?,
+        })
+    }
+}
+
+/// Serializes sequences and tuples into an array.
+pub(super) struct SerializeArray<'a> {
+    context: *mut q::JSContext,
+    array: OwnedValueRef<'a>,
+    index: u32,
+}
+
+impl<'a> SerializeArray<'a> {
+    fn new(context: *mut q::JSContext) -> Result<Self, ValueError> {
+        let array = unsafe { q::JS_NewArray(context) };
+        Ok(Self {
+            context,
+            array: OwnedValueRef::checked(context, array, "create array")?,
+            index: 0,
+        })
+    }
+}
+
+impl<'a> ser::SerializeSeq for SerializeArray<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let value = value.serialize(Serializer::new(self.context))?;
+        let ret = unsafe {
+            q::JS_DefinePropertyValueUint32(
+                self.context,
+                self.array.raw(),
+                self.index,
+                value,
+                q::JS_PROP_C_W_E as i32,
+            )
+        };
+        if ret < 0 {
+            return Err(failed(self.context, "append element to array"));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.array.into_inner())
+    }
+}
+
+impl<'a> ser::SerializeTuple for SerializeArray<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SerializeArray<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Serializes maps and structs into an object.
+pub(super) struct SerializeObject<'a> {
+    context: *mut q::JSContext,
+    object: OwnedValueRef<'a>,
+    key: Option<String>,
+}
+
+impl<'a> SerializeObject<'a> {
+    fn new(context: *mut q::JSContext) -> Result<Self, ValueError> {
+        Ok(Self {
+            context,
+            object: new_object(context)?,
+            key: None,
+        })
+    }
+}
+
+impl<'a> ser::SerializeMap for SerializeObject<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ValueError::Internal("Map value without a key".into()))?;
+        let value = value.serialize(Serializer::new(self.context))?;
+        define_property(self.context, self.object.raw(), &key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.object.into_inner())
+    }
+}
+
+impl<'a> ser::SerializeStruct for SerializeObject<'a> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let value = value.serialize(Serializer::new(self.context))?;
+        define_property(self.context, self.object.raw(), key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.object.into_inner())
+    }
+}
+
+/// Serializes tuple and struct variants, which are wrapped in an object with
+/// the variant name as key.
+pub(super) struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<'a> ser::SerializeTupleVariant for SerializeVariant<SerializeArray<'a>> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        let context = self.inner.context;
+        let value = ser::SerializeSeq::end(self.inner)?;
+        wrap_variant(context, self.variant, value)
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for SerializeVariant<SerializeObject<'a>> {
+    type Ok = q::JSValue;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        let context = self.inner.context;
+        let value = ser::SerializeStruct::end(self.inner)?;
+        wrap_variant(context, self.variant, value)
+    }
+}
+
+fn key_error() -> ValueError {
+    ValueError::Custom("Object keys must be strings, numbers or booleans".into())
+}
+
+/// Serializes map keys into property names.
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = ValueError;
+
+    type SerializeSeq = Impossible<String, ValueError>;
+    type SerializeTuple = Impossible<String, ValueError>;
+    type SerializeTupleStruct = Impossible<String, ValueError>;
+    type SerializeTupleVariant = Impossible<String, ValueError>;
+    type SerializeMap = Impossible<String, ValueError>;
+    type SerializeStruct = Impossible<String, ValueError>;
+    type SerializeStructVariant = Impossible<String, ValueError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, ValueError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, ValueError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, ValueError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, ValueError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, ValueError> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, ValueError> {
+        Err(key_error())
+    }
+}
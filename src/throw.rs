@@ -0,0 +1,260 @@
+use std::{error, ffi::CString, fmt, os::raw::c_char};
+
+use libquickjs_sys as q;
+
+use crate::bindings::{free_value, serialize_value};
+use crate::value::JsValue;
+
+#[derive(PartialEq, Clone, Debug)]
+enum ErrorClass {
+    Error,
+    TypeError,
+    RangeError,
+    ReferenceError,
+    SyntaxError,
+    InternalError,
+    Custom(String),
+}
+
+/// A Javascript error that can be returned from callbacks.
+///
+/// Unlike other callback errors, which are raised as plain `Error` objects,
+/// a `JsThrow` is raised as an instance of the given error class, with a
+/// stack trace and optional extra properties.
+/// If it is not caught, it is returned as
+/// [ExecutionError::Exception](crate::ExecutionError::Exception).
+///
+/// ```rust
+/// use quick_js::{Context, JsThrow};
+///
+/// let context = Context::new().unwrap();
+/// context.add_callback("sqrt", |value: f64| {
+///     if value < 0.0 {
+///         Err(JsThrow::range_error("value must not be negative"))
+///     } else {
+///         Ok(value.sqrt())
+///     }
+/// }).unwrap();
+///
+/// let value = context.eval_as::<String>("
+///     try { sqrt(-2.5) } catch (e) { `${e instanceof RangeError}: ${e.message}` }
+/// ").unwrap();
+/// assert_eq!(value, "true: value must not be negative");
+/// ```
+#[derive(PartialEq, Clone, Debug)]
+pub struct JsThrow {
+    class: ErrorClass,
+    message: String,
+    properties: Vec<(String, JsValue)>,
+}
+
+impl JsThrow {
+    fn new(class: ErrorClass, message: impl Into<String>) -> Self {
+        Self {
+            class,
+            message: message.into(),
+            properties: Vec::new(),
+        }
+    }
+
+    /// Create an `Error`.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::Error, message)
+    }
+
+    /// Create a `TypeError`.
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::TypeError, message)
+    }
+
+    /// Create a `RangeError`.
+    pub fn range_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::RangeError, message)
+    }
+
+    /// Create a `ReferenceError`.
+    pub fn reference_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::ReferenceError, message)
+    }
+
+    /// Create a `SyntaxError`.
+    pub fn syntax_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::SyntaxError, message)
+    }
+
+    /// Create an `InternalError`.
+    pub fn internal_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::InternalError, message)
+    }
+
+    /// Create an instance of a custom error class.
+    ///
+    /// The class is looked up as a global constructor, which is called with
+    /// the message as its only argument.
+    /// If there is no such constructor, an `Error` with the class as its
+    /// `name` is created instead.
+    ///
+    /// ```rust
+    /// use quick_js::{Context, JsThrow};
+    ///
+    /// let context = Context::new().unwrap();
+    /// context.eval(" globalThis.ValidationError = class extends Error {} ").unwrap();
+    /// context.add_callback("validate", |email: String| {
+    ///     if email.contains('@') {
+    ///         Ok(true)
+    ///     } else {
+    ///         Err(JsThrow::custom("ValidationError", "invalid email").property("field", "email"))
+    ///     }
+    /// }).unwrap();
+    ///
+    /// let value = context.eval_as::<String>("
+    ///     try { validate('nobody') } catch (e) { e instanceof ValidationError && e.field }
+    /// ").unwrap();
+    /// assert_eq!(value, "email");
+    /// ```
+    pub fn custom(class: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::Custom(class.into()), message)
+    }
+
+    /// Add a property to the error object.
+    pub fn property(mut self, name: impl Into<String>, value: impl Into<JsValue>) -> Self {
+        self.properties.push((name.into(), value.into()));
+        self
+    }
+
+    /// The name of the error class.
+    pub fn name(&self) -> &str {
+        match &self.class {
+            ErrorClass::Error => "Error",
+            ErrorClass::TypeError => "TypeError",
+            ErrorClass::RangeError => "RangeError",
+            ErrorClass::ReferenceError => "ReferenceError",
+            ErrorClass::SyntaxError => "SyntaxError",
+            ErrorClass::InternalError => "InternalError",
+            ErrorClass::Custom(name) => name,
+        }
+    }
+
+    /// The error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Raise the error as a Javascript exception.
+    ///
+    /// Returns the exception value that must be returned from the C function.
+    pub(crate) unsafe fn throw(self, context: *mut q::JSContext) -> q::JSValue {
+        let object = match self.new_object(context) {
+            Some(object) => object,
+            // The constructor raised an exception.
+            None => return exception(),
+        };
+
+        if object.tag == q::JS_TAG_OBJECT as i64 {
+            for (name, value) in self.properties {
+                let value = match serialize_value(context, value) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                if let Ok(name) = CString::new(name) {
+                    q::JS_SetPropertyStr(context, object, name.as_ptr(), value);
+                } else {
+                    free_value(context, value);
+                }
+            }
+        }
+        q::JS_Throw(context, object)
+    }
+
+    /// Create the error object.
+    ///
+    /// Returns `None` if an exception was raised instead.
+    unsafe fn new_object(&self, context: *mut q::JSContext) -> Option<q::JSValue> {
+        let throw: unsafe extern "C" fn(*mut q::JSContext, *const c_char, ...) -> q::JSValue =
+            match &self.class {
+                ErrorClass::TypeError => q::JS_ThrowTypeError,
+                ErrorClass::RangeError => q::JS_ThrowRangeError,
+                ErrorClass::ReferenceError => q::JS_ThrowReferenceError,
+                ErrorClass::SyntaxError => q::JS_ThrowSyntaxError,
+                ErrorClass::InternalError => q::JS_ThrowInternalError,
+                ErrorClass::Error => return self.construct(context, "Error"),
+                ErrorClass::Custom(name) => return self.construct(context, name),
+            };
+
+        // The builtin errors add a stack trace, but truncate the message, so
+        // the message is set afterwards.
+        throw(context, b"\0".as_ptr() as *const c_char);
+        let object = q::JS_GetException(context);
+        self.set_message(context, object);
+        Some(object)
+    }
+
+    /// Create the error object by calling the global constructor `name`.
+    unsafe fn construct(&self, context: *mut q::JSContext, name: &str) -> Option<q::JSValue> {
+        let global = q::JS_GetGlobalObject(context);
+        let mut constructor = match CString::new(name) {
+            Ok(name) => q::JS_GetPropertyStr(context, global, name.as_ptr()),
+            Err(_) => js_undefined(),
+        };
+        let found = constructor.tag != q::JS_TAG_EXCEPTION as i64
+            && q::JS_IsConstructor(context, constructor) != 0;
+        if !found {
+            free_value(context, constructor);
+            constructor = q::JS_GetPropertyStr(context, global, b"Error\0".as_ptr() as _);
+        }
+        free_value(context, global);
+
+        let mut message = serialize_value(context, JsValue::String(self.message.clone()))
+            .unwrap_or_else(|_| js_undefined());
+        let object = q::JS_CallConstructor(context, constructor, 1, &mut message);
+        free_value(context, message);
+        free_value(context, constructor);
+        if object.tag == q::JS_TAG_EXCEPTION as i64 {
+            return None;
+        }
+
+        if !found {
+            if let Ok(value) = serialize_value(context, JsValue::String(name.to_string())) {
+                q::JS_DefinePropertyValueStr(
+                    context,
+                    object,
+                    b"name\0".as_ptr() as _,
+                    value,
+                    (q::JS_PROP_WRITABLE | q::JS_PROP_CONFIGURABLE) as _,
+                );
+            }
+        }
+        Some(object)
+    }
+
+    unsafe fn set_message(&self, context: *mut q::JSContext, object: q::JSValue) {
+        if object.tag != q::JS_TAG_OBJECT as i64 {
+            return;
+        }
+        if let Ok(message) = serialize_value(context, JsValue::String(self.message.clone())) {
+            q::JS_SetPropertyStr(context, object, b"message\0".as_ptr() as _, message);
+        }
+    }
+}
+
+impl fmt::Display for JsThrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), self.message)
+    }
+}
+
+impl error::Error for JsThrow {}
+
+fn js_undefined() -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: 0 },
+        tag: q::JS_TAG_UNDEFINED as i64,
+    }
+}
+
+fn exception() -> q::JSValue {
+    q::JSValue {
+        u: q::JSValueUnion { int32: 0 },
+        tag: q::JS_TAG_EXCEPTION as i64,
+    }
+}
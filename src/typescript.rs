@@ -0,0 +1,247 @@
+//! Typescript declarations for the globals registered in a context.
+//!
+//! Types are derived from the Rust argument and return types of callbacks,
+//! with [FromJsArg::ts_type](crate::FromJsArg::ts_type) and
+//! [IntoJsReturn::ts_type](crate::IntoJsReturn::ts_type).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A parameter of a [Signature](Signature).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    /// The parameter name.
+    pub name: String,
+    /// The Typescript type.
+    pub ty: String,
+    /// Whether the parameter may be omitted.
+    pub optional: bool,
+    /// Whether the parameter collects all remaining arguments.
+    pub rest: bool,
+}
+
+impl Param {
+    /// Create a required parameter.
+    pub fn new(name: impl Into<String>, ty: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ty: ty.into(),
+            optional: false,
+            rest: false,
+        }
+    }
+}
+
+/// The Typescript signature of a callback.
+///
+/// See [Context::typescript_declarations](crate::Context::typescript_declarations).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    /// The parameters.
+    pub params: Vec<Param>,
+    /// The Typescript return type.
+    pub returns: String,
+    /// Documentation, emitted as a JSDoc comment.
+    pub doc: Option<String>,
+}
+
+impl Signature {
+    /// Create a signature. Parameters are named `arg0`, `arg1`, ...
+    pub(crate) fn new(types: Vec<(String, bool, bool)>, returns: String) -> Self {
+        let params = types
+            .into_iter()
+            .enumerate()
+            .map(|(index, (ty, optional, rest))| Param {
+                name: format!("arg{}", index),
+                ty,
+                optional,
+                rest,
+            })
+            .collect();
+        Self {
+            params,
+            returns,
+            doc: None,
+        }
+    }
+
+    /// Render the parameter list, without parentheses.
+    fn params(&self) -> String {
+        // Only trailing parameters can be optional.
+        let required = self
+            .params
+            .iter()
+            .rposition(|param| !param.optional && !param.rest)
+            .map_or(0, |index| index + 1);
+        let params = self.params.iter().enumerate().map(|(index, param)| {
+            if param.rest {
+                format!("...{}: {}", param.name, array_type(&param.ty))
+            } else if param.optional && index >= required {
+                format!("{}?: {}", param.name, param.ty)
+            } else {
+                format!("{}: {}", param.name, param.ty)
+            }
+        });
+        params.collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl Default for Signature {
+    /// `(...args: any[]) => any`
+    fn default() -> Self {
+        Self {
+            params: vec![Param {
+                rest: true,
+                ..Param::new("args", "any")
+            }],
+            returns: "any".into(),
+            doc: None,
+        }
+    }
+}
+
+/// The declaration of a [JsClass](crate::JsClass).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ClassDeclaration {
+    pub constructor: Option<Signature>,
+    pub methods: Vec<(String, Signature)>,
+    /// Properties with their type and whether they are read-only.
+    pub properties: Vec<(String, String, bool)>,
+}
+
+#[derive(Clone, Debug)]
+enum Declaration {
+    Function(Signature),
+    Constructor(Signature),
+    Class(ClassDeclaration),
+    Object,
+}
+
+/// The declarations of all registered globals.
+#[derive(Default)]
+pub(crate) struct Declarations {
+    globals: BTreeMap<String, Declaration>,
+}
+
+impl Declarations {
+    pub fn add_function(&mut self, name: &str, signature: Signature) {
+        self.globals
+            .insert(name.to_string(), Declaration::Function(signature));
+    }
+
+    pub fn add_constructor(&mut self, name: &str, signature: Signature) {
+        self.globals
+            .insert(name.to_string(), Declaration::Constructor(signature));
+    }
+
+    pub fn add_class(&mut self, name: &str, class: ClassDeclaration) {
+        self.globals
+            .insert(name.to_string(), Declaration::Class(class));
+    }
+
+    pub fn add_object(&mut self, name: &str) {
+        self.globals.insert(name.to_string(), Declaration::Object);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.globals.remove(name);
+    }
+
+    /// Render the contents of a `.d.ts` file.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, declaration) in &self.globals {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            match declaration {
+                Declaration::Function(signature) => {
+                    write_doc(&mut out, signature.doc.as_deref(), "");
+                    writeln!(
+                        out,
+                        "declare function {}({}): {};",
+                        name,
+                        signature.params(),
+                        signature.returns
+                    )
+                    .unwrap();
+                }
+                Declaration::Constructor(signature) => {
+                    write_doc(&mut out, signature.doc.as_deref(), "");
+                    writeln!(out, "declare class {} {{", name).unwrap();
+                    writeln!(out, "    constructor({});", signature.params()).unwrap();
+                    out.push_str("    [key: string]: any;\n}\n");
+                }
+                Declaration::Class(class) => {
+                    writeln!(out, "declare class {} {{", name).unwrap();
+                    match &class.constructor {
+                        Some(signature) => {
+                            write_doc(&mut out, signature.doc.as_deref(), "    ");
+                            writeln!(out, "    constructor({});", signature.params()).unwrap();
+                        }
+                        None => out.push_str("    private constructor();\n"),
+                    }
+                    for (name, signature) in &class.methods {
+                        write_doc(&mut out, signature.doc.as_deref(), "    ");
+                        writeln!(
+                            out,
+                            "    {}({}): {};",
+                            property_name(name),
+                            signature.params(),
+                            signature.returns
+                        )
+                        .unwrap();
+                    }
+                    for (name, ty, readonly) in &class.properties {
+                        let readonly = if *readonly { "readonly " } else { "" };
+                        writeln!(out, "    {}{}: {};", readonly, property_name(name), ty).unwrap();
+                    }
+                    out.push_str("}\n");
+                }
+                Declaration::Object => {
+                    writeln!(out, "declare const {}: {{ [key: string]: any }};", name).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+fn write_doc(out: &mut String, doc: Option<&str>, indent: &str) {
+    let doc = match doc {
+        Some(doc) if !doc.trim().is_empty() => doc.trim(),
+        _ => return,
+    };
+    writeln!(out, "{}/**", indent).unwrap();
+    for line in doc.lines() {
+        let line = line.replace("*/", "*\\/");
+        if line.is_empty() {
+            writeln!(out, "{} *", indent).unwrap();
+        } else {
+            writeln!(out, "{} * {}", indent, line).unwrap();
+        }
+    }
+    writeln!(out, "{} */", indent).unwrap();
+}
+
+/// Quote property names that are not identifiers.
+pub(crate) fn property_name(name: &str) -> String {
+    let is_identifier = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_string()
+    } else {
+        format!("{:?}", name)
+    }
+}
+
+pub(crate) fn array_type(ty: &str) -> String {
+    if ty.contains(' ') {
+        format!("({})[]", ty)
+    } else {
+        format!("{}[]", ty)
+    }
+}
@@ -0,0 +1,331 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{JsValue, ValueError};
+
+/// The largest distance from the epoch of a valid date, in milliseconds.
+const MAX_TIMESTAMP_MILLIS: i64 = 8_640_000_000_000_000;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// A Javascript `Date`.
+///
+/// A date is a number of milliseconds since the Unix epoch, or invalid, like
+/// `new Date(NaN)`. Invalid dates convert to `NaN` timestamps in Javascript
+/// and fail to convert to Rust date types with
+/// [ValueError::InvalidDate](ValueError::InvalidDate).
+///
+/// Dates convert to and from `SystemTime`, and with the `chrono` feature to
+/// and from `chrono::DateTime<Utc>`, `chrono::DateTime<FixedOffset>` and
+/// `chrono::NaiveDateTime`, which is interpreted as UTC. With the `time`
+/// feature, dates convert to and from `time::OffsetDateTime` and
+/// `time::PrimitiveDateTime`, which is also interpreted as UTC.
+///
+/// ```rust
+/// use quick_js::{Context, JsDate, ValueError};
+/// use std::convert::TryFrom;
+/// use std::time::{Duration, SystemTime, UNIX_EPOCH};
+///
+/// let context = Context::new().unwrap();
+///
+/// let value = context.eval(" new Date(1500) ").unwrap();
+/// let time = SystemTime::try_from(value).unwrap();
+/// assert_eq!(time, UNIX_EPOCH + Duration::from_millis(1500));
+///
+/// let value = context.eval(" new Date(NaN) ").unwrap();
+/// assert_eq!(JsDate::try_from(value.clone()), Ok(JsDate::invalid()));
+/// assert_eq!(SystemTime::try_from(value), Err(ValueError::InvalidDate));
+/// ```
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct JsDate {
+    millis: Option<i64>,
+}
+
+impl JsDate {
+    /// A date from milliseconds since the Unix epoch.
+    ///
+    /// Timestamps beyond 8.64e15 milliseconds from the epoch are out of the
+    /// range of Javascript dates, and result in an invalid date.
+    pub fn from_timestamp_millis(millis: i64) -> Self {
+        if millis.abs() <= MAX_TIMESTAMP_MILLIS {
+            Self {
+                millis: Some(millis),
+            }
+        } else {
+            Self::invalid()
+        }
+    }
+
+    /// An invalid date, like `new Date(NaN)`.
+    pub fn invalid() -> Self {
+        Self { millis: None }
+    }
+
+    /// Milliseconds since the Unix epoch, or `None` for an invalid date.
+    pub fn timestamp_millis(&self) -> Option<i64> {
+        self.millis
+    }
+
+    /// Check if the date is valid.
+    pub fn is_valid(&self) -> bool {
+        self.millis.is_some()
+    }
+
+    /// A date from the result of `Date.prototype.getTime`.
+    pub(crate) fn from_time_value(value: f64) -> Self {
+        if value.is_finite() {
+            Self::from_timestamp_millis(value as i64)
+        } else {
+            Self::invalid()
+        }
+    }
+
+    /// The value for the `Date` constructor, `NaN` for an invalid date.
+    pub(crate) fn time_value(&self) -> f64 {
+        self.millis.map_or(f64::NAN, |millis| millis as f64)
+    }
+
+    fn try_millis(&self) -> Result<i64, ValueError> {
+        self.millis.ok_or(ValueError::InvalidDate)
+    }
+}
+
+/// Formats like `Date.prototype.toISOString`, or as `Invalid Date`.
+impl fmt::Display for JsDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = match self.millis {
+            Some(millis) => millis,
+            None => return f.write_str("Invalid Date"),
+        };
+        let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+        let time = millis.rem_euclid(MILLIS_PER_DAY);
+        if (0..=9999).contains(&year) {
+            write!(f, "{:04}", year)?;
+        } else {
+            write!(f, "{:+07}", year)?;
+        }
+        write!(
+            f,
+            "-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            month,
+            day,
+            time / 3_600_000,
+            time / 60_000 % 60,
+            time / 1000 % 60,
+            time % 1000
+        )
+    }
+}
+
+/// The proleptic Gregorian date of a number of days since the Unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl From<JsDate> for JsValue {
+    fn from(value: JsDate) -> Self {
+        JsValue::Date(value)
+    }
+}
+
+impl TryFrom<JsValue> for JsDate {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::Date(date) => Ok(date),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+/// Get the timestamp of a valid date.
+fn date_millis(value: JsValue) -> Result<i64, ValueError> {
+    JsDate::try_from(value)?.try_millis()
+}
+
+/// Sub-millisecond precision is truncated towards the past. Times out of the
+/// range of Javascript dates result in an invalid date.
+impl From<SystemTime> for JsDate {
+    fn from(value: SystemTime) -> Self {
+        let millis = match value.duration_since(UNIX_EPOCH) {
+            Ok(duration) => i64::try_from(duration.as_millis()).ok(),
+            Err(e) => {
+                let duration = e.duration();
+                let mut millis = duration.as_millis();
+                if duration.subsec_nanos() % 1_000_000 != 0 {
+                    millis += 1;
+                }
+                i64::try_from(millis).ok().map(|millis| -millis)
+            }
+        };
+        millis.map_or_else(Self::invalid, Self::from_timestamp_millis)
+    }
+}
+
+impl From<SystemTime> for JsValue {
+    fn from(value: SystemTime) -> Self {
+        JsValue::Date(value.into())
+    }
+}
+
+impl TryFrom<JsValue> for SystemTime {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let millis = date_millis(value)?;
+        let duration = Duration::from_millis(millis.unsigned_abs());
+        let time = if millis < 0 {
+            UNIX_EPOCH.checked_sub(duration)
+        } else {
+            UNIX_EPOCH.checked_add(duration)
+        };
+        time.ok_or_else(|| ValueError::Custom("Date is out of range for SystemTime".into()))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for JsDate {
+    fn from(value: chrono::DateTime<Tz>) -> Self {
+        Self::from_timestamp_millis(value.timestamp_millis())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for JsValue {
+    fn from(value: chrono::DateTime<Tz>) -> Self {
+        JsValue::Date(value.into())
+    }
+}
+
+/// The payload of [JsValue::Date](JsValue::Date) before it became a `JsDate`.
+#[cfg(feature = "chrono")]
+impl TryFrom<JsDate> for chrono::DateTime<chrono::Utc> {
+    type Error = ValueError;
+
+    fn try_from(value: JsDate) -> Result<Self, Self::Error> {
+        use chrono::TimeZone;
+
+        chrono::Utc
+            .timestamp_millis_opt(value.try_millis()?)
+            .single()
+            .ok_or_else(|| ValueError::Custom("Date is out of range for chrono".into()))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<JsValue> for chrono::DateTime<chrono::Utc> {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        Self::try_from(JsDate::try_from(value)?)
+    }
+}
+
+/// The offset is always UTC.
+#[cfg(feature = "chrono")]
+impl TryFrom<JsValue> for chrono::DateTime<chrono::FixedOffset> {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        chrono::DateTime::<chrono::Utc>::try_from(value).map(Into::into)
+    }
+}
+
+/// The date time is interpreted as UTC.
+#[cfg(feature = "chrono")]
+impl From<chrono::NaiveDateTime> for JsDate {
+    fn from(value: chrono::NaiveDateTime) -> Self {
+        use chrono::TimeZone;
+
+        chrono::Utc.from_utc_datetime(&value).into()
+    }
+}
+
+/// The date time is interpreted as UTC.
+#[cfg(feature = "chrono")]
+impl From<chrono::NaiveDateTime> for JsValue {
+    fn from(value: chrono::NaiveDateTime) -> Self {
+        JsValue::Date(value.into())
+    }
+}
+
+/// The date time is in UTC.
+#[cfg(feature = "chrono")]
+impl TryFrom<JsValue> for chrono::NaiveDateTime {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        chrono::DateTime::<chrono::Utc>::try_from(value).map(|date| date.naive_utc())
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for JsDate {
+    fn from(value: time::OffsetDateTime) -> Self {
+        let millis = value.unix_timestamp_nanos().div_euclid(1_000_000);
+        i64::try_from(millis).map_or_else(|_| Self::invalid(), Self::from_timestamp_millis)
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for JsValue {
+    fn from(value: time::OffsetDateTime) -> Self {
+        JsValue::Date(value.into())
+    }
+}
+
+/// The offset is always UTC.
+#[cfg(feature = "time")]
+impl TryFrom<JsValue> for time::OffsetDateTime {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let millis = date_millis(value)?;
+        time::OffsetDateTime::from_unix_timestamp_nanos(i128::from(millis) * 1_000_000)
+            .map_err(|e| ValueError::Custom(e.to_string()))
+    }
+}
+
+/// The date time is interpreted as UTC.
+#[cfg(feature = "time")]
+impl From<time::PrimitiveDateTime> for JsDate {
+    fn from(value: time::PrimitiveDateTime) -> Self {
+        value.assume_utc().into()
+    }
+}
+
+/// The date time is interpreted as UTC.
+#[cfg(feature = "time")]
+impl From<time::PrimitiveDateTime> for JsValue {
+    fn from(value: time::PrimitiveDateTime) -> Self {
+        JsValue::Date(value.into())
+    }
+}
+
+/// The date time is in UTC.
+#[cfg(feature = "time")]
+impl TryFrom<JsValue> for time::PrimitiveDateTime {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        time::OffsetDateTime::try_from(value)
+            .map(|date| time::PrimitiveDateTime::new(date.date(), date.time()))
+    }
+}
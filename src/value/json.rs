@@ -0,0 +1,233 @@
+//! Conversion between `JsValue` and `serde_json::Value`.
+
+use std::convert::TryFrom;
+
+use serde_json::{Map, Number, Value};
+
+use super::{integer_value, safe_integer, JsValue, TypedArray, ValueError};
+
+/// How `NaN` and infinite numbers are converted to JSON, which cannot
+/// represent them.
+///
+/// See [JsValue::into_json](JsValue::into_json).
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum NonFiniteFloats {
+    /// Conversion fails with [ValueError::Custom](ValueError::Custom).
+    #[default]
+    Error,
+    /// Non-finite numbers become `null`, like with `JSON.stringify`.
+    Null,
+    /// Non-finite numbers become the strings `"NaN"`, `"Infinity"` and
+    /// `"-Infinity"`.
+    String,
+}
+
+/// Numbers become `Int` if they fit, otherwise `Float`, or `BigInt` with the
+/// `bigint` feature if a `Float` would lose precision.
+///
+/// Fails instead of losing precision: without the `bigint` feature for
+/// integers that a `Float` can not represent exactly, like
+/// `9007199254740993`, and for numbers that `f64` can not represent, which
+/// only occur with serde_json's `arbitrary_precision` feature.
+///
+/// ```rust
+/// use quick_js::JsValue;
+/// use serde_json::json;
+/// use std::convert::TryFrom;
+///
+/// assert_eq!(JsValue::try_from(json!(1)), Ok(JsValue::Int(1)));
+/// # #[cfg(not(feature = "bigint"))]
+/// assert!(JsValue::try_from(json!(9007199254740993u64)).is_err());
+/// ```
+impl TryFrom<Value> for JsValue {
+    type Error = ValueError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let value = match value {
+            Value::Null => JsValue::Null,
+            Value::Bool(value) => JsValue::Bool(value),
+            Value::Number(number) => {
+                if let Some(int) = number.as_i64() {
+                    integer_value(int.into())?
+                } else if let Some(int) = number.as_u64() {
+                    integer_value(int.into())?
+                } else if let Some(float) = number.as_f64() {
+                    JsValue::Float(float)
+                } else {
+                    return Err(ValueError::Custom(format!(
+                        "{} can not be represented as a number",
+                        number
+                    )));
+                }
+            }
+            Value::String(value) => JsValue::String(value),
+            Value::Array(values) => JsValue::Array(
+                values
+                    .into_iter()
+                    .map(JsValue::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(object) => JsValue::Object(
+                object
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, JsValue::try_from(value)?)))
+                    .collect::<Result<_, ValueError>>()?,
+            ),
+        };
+        Ok(value)
+    }
+}
+
+/// Converts with [NonFiniteFloats::Error](NonFiniteFloats::Error), see
+/// [JsValue::into_json](JsValue::into_json).
+impl TryFrom<JsValue> for Value {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        value.into_json(NonFiniteFloats::default())
+    }
+}
+
+impl JsValue {
+    /// Convert to a `serde_json::Value`.
+    ///
+    /// Integral numbers become JSON integers, like with `JSON.stringify`.
+    /// `undefined` becomes `null`. `Map`s become arrays of `[key, value]`
+    /// pairs, `Set`s, `ArrayBuffer`s and typed arrays become arrays, and dates
+    /// become ISO 8601 strings, or `null` if they are invalid. `BigInt`s
+    /// become numbers if they fit into an `i64` or `u64`. Lone surrogates in
+    /// strings are replaced with U+FFFD.
+    ///
+    /// Fails for symbols, larger `BigInt`s, and for `NaN` and infinite numbers
+    /// unless allowed by `non_finite`.
+    ///
+    /// Requires the `serde_json` feature.
+    ///
+    /// ```rust
+    /// use quick_js::{Context, NonFiniteFloats};
+    /// use serde_json::json;
+    ///
+    /// let context = Context::new().unwrap();
+    /// let value = context.eval("({ a: [2 ** 40, 2.5, 0 / 0], b: undefined })").unwrap();
+    ///
+    /// assert!(value.clone().into_json(NonFiniteFloats::Error).is_err());
+    /// assert_eq!(
+    ///     value.into_json(NonFiniteFloats::Null).unwrap(),
+    ///     json!({ "a": [1099511627776u64, 2.5, null], "b": null }),
+    /// );
+    /// ```
+    pub fn into_json(self, non_finite: NonFiniteFloats) -> Result<Value, ValueError> {
+        let value = match self {
+            JsValue::Null | JsValue::Undefined => Value::Null,
+            JsValue::Bool(value) => Value::Bool(value),
+            JsValue::Int(value) => Value::Number(value.into()),
+            JsValue::Float(value) => float(value, non_finite)?,
+            JsValue::String(value) => Value::String(value),
+            JsValue::IllFormedString(value) => Value::String(value.to_string_lossy().into_owned()),
+            JsValue::Array(values) | JsValue::Set(values) => array(values, non_finite)?,
+            JsValue::Object(object) => {
+                let mut map = Map::new();
+                for (key, value) in object {
+                    map.insert(key, value.into_json(non_finite)?);
+                }
+                Value::Object(map)
+            }
+            JsValue::ArrayBuffer(bytes) => {
+                Value::Array(bytes.into_iter().map(Into::into).collect())
+            }
+            JsValue::TypedArray(array) => typed_array(array, non_finite)?,
+            JsValue::Map(entries) => Value::Array(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        Ok(Value::Array(vec![
+                            key.into_json(non_finite)?,
+                            value.into_json(non_finite)?,
+                        ]))
+                    })
+                    .collect::<Result<_, ValueError>>()?,
+            ),
+            JsValue::Symbol(_) => return Err(ValueError::UnexpectedType),
+            JsValue::Date(date) if date.is_valid() => Value::String(date.to_string()),
+            JsValue::Date(_) => Value::Null,
+            #[cfg(feature = "bigint")]
+            JsValue::BigInt(bigint) => {
+                use num_traits::ToPrimitive;
+
+                let bigint = bigint.into_bigint();
+                if let Some(int) = bigint.to_i64() {
+                    Value::Number(int.into())
+                } else if let Some(int) = bigint.to_u64() {
+                    Value::Number(int.into())
+                } else {
+                    return Err(ValueError::Custom(format!(
+                        "BigInt {} does not fit into a JSON number",
+                        bigint
+                    )));
+                }
+            }
+            JsValue::__NonExhaustive => unreachable!(),
+        };
+        Ok(value)
+    }
+}
+
+fn float(value: f64, non_finite: NonFiniteFloats) -> Result<Value, ValueError> {
+    if let Some(int) = safe_integer(value) {
+        return Ok(Value::Number(int.into()));
+    }
+    if let Some(number) = Number::from_f64(value) {
+        return Ok(Value::Number(number));
+    }
+    match non_finite {
+        NonFiniteFloats::Error => Err(ValueError::Custom(format!(
+            "{} can not be represented in JSON",
+            value
+        ))),
+        NonFiniteFloats::Null => Ok(Value::Null),
+        NonFiniteFloats::String if value.is_nan() => Ok(Value::String("NaN".into())),
+        NonFiniteFloats::String if value > 0.0 => Ok(Value::String("Infinity".into())),
+        NonFiniteFloats::String => Ok(Value::String("-Infinity".into())),
+    }
+}
+
+fn array(values: Vec<JsValue>, non_finite: NonFiniteFloats) -> Result<Value, ValueError> {
+    values
+        .into_iter()
+        .map(|value| value.into_json(non_finite))
+        .collect::<Result<_, _>>()
+        .map(Value::Array)
+}
+
+fn typed_array(array: TypedArray, non_finite: NonFiniteFloats) -> Result<Value, ValueError> {
+    fn numbers<T: Into<Number>>(values: Vec<T>) -> Value {
+        Value::Array(
+            values
+                .into_iter()
+                .map(|v| Value::Number(v.into()))
+                .collect(),
+        )
+    }
+
+    let value = match array {
+        TypedArray::Int8(v) => numbers(v),
+        TypedArray::Uint8(v) | TypedArray::Uint8Clamped(v) => numbers(v),
+        TypedArray::Int16(v) => numbers(v),
+        TypedArray::Uint16(v) => numbers(v),
+        TypedArray::Int32(v) => numbers(v),
+        TypedArray::Uint32(v) => numbers(v),
+        TypedArray::BigInt64(v) => numbers(v),
+        TypedArray::BigUint64(v) => numbers(v),
+        TypedArray::Float32(v) => Value::Array(
+            v.into_iter()
+                .map(|v| float(v.into(), non_finite))
+                .collect::<Result<_, _>>()?,
+        ),
+        TypedArray::Float64(v) => Value::Array(
+            v.into_iter()
+                .map(|v| float(v, non_finite))
+                .collect::<Result<_, _>>()?,
+        ),
+    };
+    Ok(value)
+}
@@ -1,27 +1,58 @@
 #[cfg(feature = "bigint")]
 pub(crate) mod bigint;
+mod date;
+#[cfg(feature = "serde_json")]
+mod json;
+pub mod object;
+#[cfg(feature = "serde")]
+mod serialize;
+mod string;
+mod typed_array;
 
 use std::convert::{TryFrom, TryInto};
 use std::{collections::HashMap, error, fmt};
 
 #[cfg(feature = "bigint")]
 pub use bigint::BigInt;
+pub use date::JsDate;
+#[cfg(feature = "serde_json")]
+pub use json::NonFiniteFloats;
+pub use object::JsObjectMap;
+pub use string::JsString;
+pub use typed_array::TypedArray;
 
 /// A value that can be (de)serialized to/from the quickjs runtime.
 #[derive(PartialEq, Clone, Debug)]
 #[allow(missing_docs)]
 pub enum JsValue {
     Null,
+    Undefined,
     Bool(bool),
     Int(i32),
     Float(f64),
     String(String),
+    /// A string that is not valid Unicode because it contains lone
+    /// surrogates, see [JsString](JsString).
+    ///
+    /// Well-formed strings are always received as `String`.
+    IllFormedString(JsString),
     Array(Vec<JsValue>),
-    Object(HashMap<String, JsValue>),
-    /// chrono::Datetime<Utc> / JS Date integration.
-    /// Only available with the optional `chrono` feature.
-    #[cfg(feature = "chrono")]
-    Date(chrono::DateTime<chrono::Utc>),
+    Object(JsObjectMap),
+    /// The contents of an `ArrayBuffer`.
+    ///
+    /// Buffers created from Rust take ownership of the vector, so the data is
+    /// not copied.
+    ArrayBuffer(Vec<u8>),
+    /// A typed array, like a `Uint8Array`.
+    TypedArray(TypedArray),
+    /// A `Map`, with entries in insertion order.
+    Map(Vec<(JsValue, JsValue)>),
+    /// A `Set`, with values in insertion order.
+    Set(Vec<JsValue>),
+    /// A symbol.
+    Symbol(JsSymbol),
+    /// A `Date`, which may be invalid, see [JsDate](JsDate).
+    Date(JsDate),
     /// num_bigint::BigInt / JS BigInt integration
     /// Only available with the optional `bigint` feature
     #[cfg(feature = "bigint")]
@@ -31,6 +62,11 @@ pub enum JsValue {
 }
 
 impl JsValue {
+    /// Check if the value is `null` or `undefined`.
+    pub fn is_nullish(&self) -> bool {
+        matches!(self, JsValue::Null | JsValue::Undefined)
+    }
+
     /// Cast value to a str.
     ///
     /// Returns `Some(&str)` if value is a `JsValue::String`, None otherwise.
@@ -41,6 +77,16 @@ impl JsValue {
         }
     }
 
+    /// Get the bytes of an `ArrayBuffer`, `Uint8Array` or `Uint8ClampedArray`.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            JsValue::ArrayBuffer(bytes)
+            | JsValue::TypedArray(TypedArray::Uint8(bytes))
+            | JsValue::TypedArray(TypedArray::Uint8Clamped(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
     /// Convert to `String`.
     pub fn into_string(self) -> Option<String> {
         match self {
@@ -50,6 +96,60 @@ impl JsValue {
     }
 }
 
+/// A description of a Javascript symbol.
+///
+/// Symbols are unique, so converting a symbol to Javascript creates a new
+/// symbol with the same description.
+/// Registered symbols, created with `Symbol.for`, are looked up in the
+/// registry instead, so they keep their identity.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct JsSymbol {
+    description: Option<String>,
+    registered: bool,
+}
+
+impl JsSymbol {
+    /// A new unique symbol, like `Symbol(description)`.
+    pub fn new(description: Option<String>) -> Self {
+        Self {
+            description,
+            registered: false,
+        }
+    }
+
+    /// A symbol from the global registry, like `Symbol.for(key)`.
+    pub fn registered(key: impl Into<String>) -> Self {
+        Self {
+            description: Some(key.into()),
+            registered: true,
+        }
+    }
+
+    /// The description of the symbol, or the key of a registered symbol.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Check if the symbol is from the global registry.
+    pub fn is_registered(&self) -> bool {
+        self.registered
+    }
+}
+
+/// How symbol-keyed properties are handled when converting objects to
+/// [JsValue::Object](JsValue::Object), which only has string keys.
+///
+/// See [ContextBuilder::symbol_keys](crate::ContextBuilder::symbol_keys).
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum SymbolKeys {
+    /// Symbol-keyed properties are skipped.
+    #[default]
+    Skip,
+    /// Objects with symbol-keyed properties fail to convert with
+    /// [ValueError::SymbolKey](ValueError::SymbolKey).
+    Error,
+}
+
 macro_rules! value_impl_from {
     (
         (
@@ -189,12 +289,97 @@ where
     }
 }
 
+impl<T> TryFrom<JsValue> for Vec<T>
+where
+    T: TryFrom<JsValue>,
+{
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::Array(values) => values
+                .into_iter()
+                .map(|v| v.try_into().map_err(|_| ValueError::UnexpectedType))
+                .collect(),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+/// `undefined` and `null` convert to `None`.
+impl<T> TryFrom<JsValue> for Option<T>
+where
+    T: TryFrom<JsValue, Error = ValueError>,
+{
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        if value.is_nullish() {
+            Ok(None)
+        } else {
+            T::try_from(value).map(Some)
+        }
+    }
+}
+
+/// Convert an integer without losing precision.
+///
+/// Returns an `Int` if the integer fits, otherwise a `Float` if that
+/// represents it exactly, and otherwise a `BigInt` with the `bigint` feature.
+/// Fails if the integer can not be represented without the `bigint` feature.
+#[cfg(any(feature = "serde", feature = "serde_json"))]
+pub(crate) fn integer_value(value: i128) -> Result<JsValue, ValueError> {
+    if let Ok(int) = i32::try_from(value) {
+        return Ok(JsValue::Int(int));
+    }
+    let float = value as f64;
+    if float as i128 == value {
+        return Ok(JsValue::Float(float));
+    }
+    #[cfg(feature = "bigint")]
+    {
+        Ok(JsValue::BigInt(num_bigint::BigInt::from(value).into()))
+    }
+    #[cfg(not(feature = "bigint"))]
+    {
+        Err(inexact_integer(value))
+    }
+}
+
+/// The error for an integer that a `Float` would not represent exactly.
+#[cfg(all(
+    any(feature = "serde", feature = "serde_json"),
+    not(feature = "bigint")
+))]
+pub(crate) fn inexact_integer(value: impl fmt::Display) -> ValueError {
+    ValueError::Custom(format!(
+        "{} can not be represented without the bigint feature",
+        value
+    ))
+}
+
+/// The integer value of a float, if it is integral and can be converted to
+/// and from a float without losing precision.
+#[cfg(any(feature = "serde", feature = "serde_json"))]
+pub(crate) fn safe_integer(value: f64) -> Option<i64> {
+    const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+    if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER {
+        Some(value as i64)
+    } else {
+        None
+    }
+}
+
 impl<'a> From<&'a str> for JsValue {
     fn from(val: &'a str) -> Self {
         JsValue::String(val.into())
     }
 }
 
+/// `None` is converted to `null`.
+///
+/// In the other direction, optional callback arguments accept both `null`
+/// and `undefined` as `None`.
 impl<T> From<Option<T>> for JsValue
 where
     T: Into<JsValue>,
@@ -219,6 +404,39 @@ where
     }
 }
 
+#[cfg(feature = "preserve_order")]
+impl<K, V> From<indexmap::IndexMap<K, V>> for JsValue
+where
+    K: Into<String>,
+    V: Into<JsValue>,
+{
+    fn from(map: indexmap::IndexMap<K, V>) -> Self {
+        let new_map = map.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+        JsValue::Object(new_map)
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<V> TryFrom<JsValue> for indexmap::IndexMap<String, V>
+where
+    V: TryFrom<JsValue>,
+{
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::Object(object) => object
+                .into_iter()
+                .map(|(k, v)| match v.try_into() {
+                    Ok(v) => Ok((k, v)),
+                    Err(_) => Err(ValueError::UnexpectedType),
+                })
+                .collect(),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
 impl<V> TryFrom<JsValue> for HashMap<String, V>
 where
     V: TryFrom<JsValue>,
@@ -241,6 +459,7 @@ where
 
 /// Error during value conversion.
 #[derive(PartialEq, Eq, Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum ValueError {
     /// Invalid non-utf8 string.
     InvalidString(std::str::Utf8Error),
@@ -250,6 +469,22 @@ pub enum ValueError {
     Internal(String),
     /// Received an unexpected type that could not be converted.
     UnexpectedType,
+    /// Received an object with a symbol-keyed property, see
+    /// [SymbolKeys](SymbolKeys).
+    SymbolKey(JsSymbol),
+    /// Received an invalid `Date`, like `new Date(NaN)`, where a valid date
+    /// was expected.
+    InvalidDate,
+    /// Received a value that contains itself.
+    CyclicValue,
+    /// Received a value that is nested too deeply, see
+    /// [ContextBuilder::max_value_depth](crate::ContextBuilder::max_value_depth).
+    MaxDepthExceeded,
+    /// Received a value with too many elements, see
+    /// [ContextBuilder::max_value_elements](crate::ContextBuilder::max_value_elements).
+    MaxElementsExceeded,
+    /// A custom error, for example from a `serde` implementation.
+    Custom(String),
     #[doc(hidden)]
     __NonExhaustive,
 }
@@ -274,6 +509,16 @@ impl fmt::Display for ValueError {
             StringWithZeroBytes(_) => write!(f, "String contains \\0 bytes",),
             Internal(e) => write!(f, "Value conversion failed - internal error: {}", e),
             UnexpectedType => write!(f, "Could not convert - received unexpected type"),
+            SymbolKey(symbol) => write!(
+                f,
+                "Could not convert - object has a symbol key: Symbol({})",
+                symbol.description().unwrap_or_default()
+            ),
+            InvalidDate => write!(f, "Could not convert - received an invalid Date"),
+            CyclicValue => write!(f, "Could not convert - value contains a cycle"),
+            MaxDepthExceeded => write!(f, "Could not convert - value is nested too deeply"),
+            MaxElementsExceeded => write!(f, "Could not convert - value has too many elements"),
+            Custom(e) => write!(f, "Value conversion failed: {}", e),
             __NonExhaustive => unreachable!(),
         }
     }
@@ -0,0 +1,294 @@
+//! The map type of [JsValue::Object](crate::JsValue::Object).
+
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::iter::FromIterator;
+use std::ops::{Index, IndexMut};
+
+use super::{JsValue, ValueError};
+
+#[cfg(feature = "preserve_order")]
+type MapImpl = indexmap::IndexMap<String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type IterImpl<'a> = indexmap::map::Iter<'a, String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type IterMutImpl<'a> = indexmap::map::IterMut<'a, String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type IntoIterImpl = indexmap::map::IntoIter<String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type KeysImpl<'a> = indexmap::map::Keys<'a, String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type ValuesImpl<'a> = indexmap::map::Values<'a, String, JsValue>;
+#[cfg(feature = "preserve_order")]
+type ValuesMutImpl<'a> = indexmap::map::ValuesMut<'a, String, JsValue>;
+
+#[cfg(not(feature = "preserve_order"))]
+type MapImpl = std::collections::HashMap<String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type IterImpl<'a> = std::collections::hash_map::Iter<'a, String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type IterMutImpl<'a> = std::collections::hash_map::IterMut<'a, String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type IntoIterImpl = std::collections::hash_map::IntoIter<String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type KeysImpl<'a> = std::collections::hash_map::Keys<'a, String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type ValuesImpl<'a> = std::collections::hash_map::Values<'a, String, JsValue>;
+#[cfg(not(feature = "preserve_order"))]
+type ValuesMutImpl<'a> = std::collections::hash_map::ValuesMut<'a, String, JsValue>;
+
+/// The properties of a Javascript object, see
+/// [JsValue::Object](crate::JsValue::Object).
+///
+/// By default, properties are stored in a `HashMap`. With the
+/// `preserve_order` feature, they are stored in an `IndexMap`, which keeps
+/// the property order of Javascript objects, both when receiving objects
+/// from Javascript and when creating them. The API is the same either way.
+///
+/// ```rust
+/// use quick_js::{Context, JsObjectMap, JsValue};
+/// use std::convert::TryFrom;
+///
+/// let context = Context::new().unwrap();
+///
+/// let value = context.eval(" ({ a: 1, b: 2 }) ").unwrap();
+/// let mut object = JsObjectMap::try_from(value).unwrap();
+/// assert_eq!(object["a"], JsValue::Int(1));
+///
+/// object.insert("c".into(), JsValue::Int(3));
+/// assert_eq!(object.len(), 3);
+/// ```
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct JsObjectMap {
+    map: MapImpl,
+}
+
+impl JsObjectMap {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty map with room for the given number of properties.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: MapImpl::with_capacity(capacity),
+        }
+    }
+
+    /// Remove all properties.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Get the value of a property.
+    pub fn get<Q>(&self, key: &Q) -> Option<&JsValue>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Get a mutable reference to the value of a property.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut JsValue>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// Check if the map contains a property.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Insert a property, and return the previous value.
+    ///
+    /// With the `preserve_order` feature, a new property is added at the
+    /// end, and an existing property keeps its position.
+    pub fn insert(&mut self, key: String, value: JsValue) -> Option<JsValue> {
+        self.map.insert(key, value)
+    }
+
+    /// Remove a property, and return its value.
+    ///
+    /// With the `preserve_order` feature, the order of the remaining
+    /// properties is kept.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<JsValue>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        #[cfg(feature = "preserve_order")]
+        return self.map.shift_remove(key);
+        #[cfg(not(feature = "preserve_order"))]
+        return self.map.remove(key);
+    }
+
+    /// The number of properties.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map has no properties.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterate over the properties.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.map.iter())
+    }
+
+    /// Iterate over the properties, with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        IterMut(self.map.iter_mut())
+    }
+
+    /// Iterate over the property names.
+    pub fn keys(&self) -> Keys<'_> {
+        Keys(self.map.keys())
+    }
+
+    /// Iterate over the values.
+    pub fn values(&self) -> Values<'_> {
+        Values(self.map.values())
+    }
+
+    /// Iterate over mutable references to the values.
+    pub fn values_mut(&mut self) -> ValuesMut<'_> {
+        ValuesMut(self.map.values_mut())
+    }
+}
+
+impl From<JsObjectMap> for JsValue {
+    fn from(object: JsObjectMap) -> Self {
+        JsValue::Object(object)
+    }
+}
+
+impl TryFrom<JsValue> for JsObjectMap {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::Object(object) => Ok(object),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
+
+/// Panics if the property does not exist.
+impl<Q> Index<&Q> for JsObjectMap
+where
+    String: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = JsValue;
+
+    fn index(&self, key: &Q) -> &JsValue {
+        self.map.get(key).expect("no such property")
+    }
+}
+
+/// Panics if the property does not exist.
+impl<Q> IndexMut<&Q> for JsObjectMap
+where
+    String: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    fn index_mut(&mut self, key: &Q) -> &mut JsValue {
+        self.map.get_mut(key).expect("no such property")
+    }
+}
+
+impl FromIterator<(String, JsValue)> for JsObjectMap {
+    fn from_iter<I: IntoIterator<Item = (String, JsValue)>>(iter: I) -> Self {
+        Self {
+            map: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(String, JsValue)> for JsObjectMap {
+    fn extend<I: IntoIterator<Item = (String, JsValue)>>(&mut self, iter: I) {
+        self.map.extend(iter)
+    }
+}
+
+impl IntoIterator for JsObjectMap {
+    type Item = (String, JsValue);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter(self.map.into_iter())
+    }
+}
+
+impl<'a> IntoIterator for &'a JsObjectMap {
+    type Item = (&'a String, &'a JsValue);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut JsObjectMap {
+    type Item = (&'a String, &'a mut JsValue);
+    type IntoIter = IterMut<'a>;
+
+    fn into_iter(self) -> IterMut<'a> {
+        self.iter_mut()
+    }
+}
+
+macro_rules! delegate_iterator {
+    ( $( $(#[$attr:meta])* $name:ident $( <$a:lifetime> )? ( $inner:ty ) => $item:ty; )* ) => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug)]
+            pub struct $name $( <$a> )? ($inner);
+
+            impl $( <$a> )? Iterator for $name $( <$a> )? {
+                type Item = $item;
+
+                fn next(&mut self) -> Option<Self::Item> {
+                    self.0.next()
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    self.0.size_hint()
+                }
+            }
+
+            impl $( <$a> )? ExactSizeIterator for $name $( <$a> )? {
+                fn len(&self) -> usize {
+                    self.0.len()
+                }
+            }
+        )*
+    };
+}
+
+delegate_iterator! {
+    /// An iterator over the properties of a [JsObjectMap](JsObjectMap).
+    Iter<'a>(IterImpl<'a>) => (&'a String, &'a JsValue);
+    /// A mutable iterator over the properties of a [JsObjectMap](JsObjectMap).
+    IterMut<'a>(IterMutImpl<'a>) => (&'a String, &'a mut JsValue);
+    /// An owning iterator over the properties of a [JsObjectMap](JsObjectMap).
+    IntoIter(IntoIterImpl) => (String, JsValue);
+    /// An iterator over the property names of a [JsObjectMap](JsObjectMap).
+    Keys<'a>(KeysImpl<'a>) => &'a String;
+    /// An iterator over the values of a [JsObjectMap](JsObjectMap).
+    Values<'a>(ValuesImpl<'a>) => &'a JsValue;
+    /// A mutable iterator over the values of a [JsObjectMap](JsObjectMap).
+    ValuesMut<'a>(ValuesMutImpl<'a>) => &'a mut JsValue;
+}
@@ -0,0 +1,182 @@
+//! `Serialize` and `Deserialize` for `JsValue`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use super::{integer_value, safe_integer, JsObjectMap, JsValue, TypedArray};
+
+/// Serializes like `JSON.stringify`, so integral numbers become integers,
+/// except that `Map`s become arrays of
+/// `[key, value]` pairs, `Set`s and typed arrays become arrays and `BigInt`s
+/// become integers. `undefined` and invalid dates become a unit value, valid
+/// dates become ISO 8601 strings, lone surrogates in strings are replaced
+/// with U+FFFD and symbols fail to serialize.
+impl Serialize for JsValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            JsValue::Null | JsValue::Undefined => serializer.serialize_unit(),
+            JsValue::Bool(value) => serializer.serialize_bool(*value),
+            JsValue::Int(value) => serializer.serialize_i32(*value),
+            JsValue::Float(value) => match safe_integer(*value) {
+                Some(int) => serializer.serialize_i64(int),
+                None => serializer.serialize_f64(*value),
+            },
+            JsValue::String(value) => serializer.serialize_str(value),
+            JsValue::IllFormedString(value) => serializer.serialize_str(&value.to_string_lossy()),
+            JsValue::Array(values) | JsValue::Set(values) => serializer.collect_seq(values),
+            JsValue::Object(object) => {
+                let mut map = serializer.serialize_map(Some(object.len()))?;
+                for (key, value) in object {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            JsValue::ArrayBuffer(bytes) => serializer.serialize_bytes(bytes),
+            JsValue::TypedArray(array) => array.serialize(serializer),
+            JsValue::Map(entries) => {
+                let mut seq = serializer.serialize_seq(Some(entries.len()))?;
+                for (key, value) in entries {
+                    seq.serialize_element(&(key, value))?;
+                }
+                seq.end()
+            }
+            JsValue::Symbol(_) => Err(ser::Error::custom("Symbols can not be serialized")),
+            JsValue::Date(date) if date.is_valid() => serializer.collect_str(date),
+            JsValue::Date(_) => serializer.serialize_unit(),
+            #[cfg(feature = "bigint")]
+            JsValue::BigInt(bigint) => {
+                use num_traits::ToPrimitive;
+
+                let bigint = bigint.clone().into_bigint();
+                match bigint.to_i128() {
+                    Some(int) => serializer.serialize_i128(int),
+                    None => serializer.collect_str(&bigint),
+                }
+            }
+            JsValue::__NonExhaustive => unreachable!(),
+        }
+    }
+}
+
+impl Serialize for TypedArray {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TypedArray::Int8(v) => serializer.collect_seq(v),
+            TypedArray::Uint8(v) | TypedArray::Uint8Clamped(v) => serializer.collect_seq(v),
+            TypedArray::Int16(v) => serializer.collect_seq(v),
+            TypedArray::Uint16(v) => serializer.collect_seq(v),
+            TypedArray::Int32(v) => serializer.collect_seq(v),
+            TypedArray::Uint32(v) => serializer.collect_seq(v),
+            TypedArray::BigInt64(v) => serializer.collect_seq(v),
+            TypedArray::BigUint64(v) => serializer.collect_seq(v),
+            TypedArray::Float32(v) => serializer.collect_seq(v),
+            TypedArray::Float64(v) => serializer.collect_seq(v),
+        }
+    }
+}
+
+/// Deserializes any self-describing format, like `serde_json::Value`.
+///
+/// Integers become `Int` if they fit, otherwise `Float`, or `BigInt` with the
+/// `bigint` feature if a `Float` would lose precision. Without the `bigint`
+/// feature, such integers fail to deserialize. Bytes become an
+/// `ArrayBuffer`, and missing and unit values become `Null`.
+impl<'de> Deserialize<'de> for JsValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = JsValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a Javascript value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<JsValue, E> {
+        Ok(JsValue::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<JsValue, E> {
+        integer_value(value.into()).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<JsValue, E> {
+        integer_value(value.into()).map_err(E::custom)
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> Result<JsValue, E> {
+        integer_value(value).map_err(E::custom)
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<JsValue, E> {
+        match i128::try_from(value) {
+            Ok(value) => integer_value(value).map_err(E::custom),
+            #[cfg(feature = "bigint")]
+            Err(_) => Ok(JsValue::BigInt(num_bigint::BigInt::from(value).into())),
+            #[cfg(not(feature = "bigint"))]
+            Err(_) => Err(E::custom(super::inexact_integer(value))),
+        }
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<JsValue, E> {
+        Ok(JsValue::Float(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<JsValue, E> {
+        Ok(JsValue::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<JsValue, E> {
+        Ok(JsValue::String(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<JsValue, E> {
+        Ok(JsValue::ArrayBuffer(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<JsValue, E> {
+        Ok(JsValue::ArrayBuffer(value))
+    }
+
+    fn visit_none<E>(self) -> Result<JsValue, E> {
+        Ok(JsValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<JsValue, D::Error> {
+        JsValue::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<JsValue, E> {
+        Ok(JsValue::Null)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<JsValue, D::Error> {
+        JsValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsValue, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(JsValue::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsValue, A::Error> {
+        let mut object = JsObjectMap::default();
+        while let Some((key, value)) = map.next_entry::<String, JsValue>()? {
+            object.insert(key, value);
+        }
+        Ok(JsValue::Object(object))
+    }
+}
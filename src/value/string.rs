@@ -0,0 +1,167 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{JsValue, ValueError};
+
+/// A Javascript string, which may contain lone surrogates.
+///
+/// Javascript strings are sequences of UTF-16 code units that don't have to
+/// be valid Unicode, so they can't always be represented as a Rust `String`.
+/// Strings that are not well-formed are received as
+/// [JsValue::IllFormedString](JsValue::IllFormedString).
+///
+/// The string is stored as [WTF-8](https://simonsapin.github.io/wtf-8/),
+/// which is UTF-8 that may also encode lone surrogates, so well-formed
+/// strings are converted to and from `String` without copying.
+///
+/// ```rust
+/// use quick_js::{Context, JsString};
+/// use std::convert::TryFrom;
+///
+/// let context = Context::new().unwrap();
+///
+/// let value = context.eval(r" 'a\uD800' ").unwrap();
+/// let string = JsString::try_from(value).unwrap();
+/// assert_eq!(string.to_utf16(), vec![0x61, 0xD800]);
+/// assert_eq!(string.to_string_lossy(), "a\u{FFFD}");
+///
+/// // The string is unchanged when it is passed back to Javascript.
+/// context.add_callback("echo", |s: JsString| s).unwrap();
+/// assert_eq!(context.eval_as::<bool>(r" echo('\uDC00b') === '\uDC00b' "), Ok(true));
+/// ```
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct JsString {
+    wtf8: Vec<u8>,
+}
+
+impl JsString {
+    /// Create a string from WTF-8 bytes returned by quickjs.
+    ///
+    /// Surrogate pairs must be encoded as a single code point.
+    pub(crate) fn from_wtf8(wtf8: Vec<u8>) -> Self {
+        Self { wtf8 }
+    }
+
+    /// Create a string from UTF-16 code units, which may contain lone
+    /// surrogates.
+    pub fn from_utf16(units: &[u16]) -> Self {
+        let mut wtf8 = Vec::with_capacity(units.len());
+        for c in std::char::decode_utf16(units.iter().copied()) {
+            match c {
+                Ok(c) => wtf8.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                Err(e) => {
+                    let unit = e.unpaired_surrogate();
+                    wtf8.extend_from_slice(&[
+                        0xE0 | (unit >> 12) as u8,
+                        0x80 | ((unit >> 6) & 0x3F) as u8,
+                        0x80 | (unit & 0x3F) as u8,
+                    ]);
+                }
+            }
+        }
+        Self { wtf8 }
+    }
+
+    /// The UTF-16 code units of the string.
+    pub fn to_utf16(&self) -> Vec<u16> {
+        let mut units = Vec::with_capacity(self.wtf8.len());
+        let mut bytes = self.wtf8.iter().copied();
+        while let Some(first) = bytes.next() {
+            let (len, mut code) = match first {
+                0x00..=0x7F => (0, u32::from(first)),
+                0xC0..=0xDF => (1, u32::from(first & 0x1F)),
+                0xE0..=0xEF => (2, u32::from(first & 0x0F)),
+                _ => (3, u32::from(first & 0x07)),
+            };
+            for byte in bytes.by_ref().take(len) {
+                code = (code << 6) | u32::from(byte & 0x3F);
+            }
+            if code >= 0x10000 {
+                code -= 0x10000;
+                units.push(0xD800 | (code >> 10) as u16);
+                units.push(0xDC00 | (code & 0x3FF) as u16);
+            } else {
+                units.push(code as u16);
+            }
+        }
+        units
+    }
+
+    /// The WTF-8 bytes of the string.
+    pub fn as_wtf8(&self) -> &[u8] {
+        &self.wtf8
+    }
+
+    /// Check if the string is valid Unicode, like
+    /// `String.prototype.isWellFormed`.
+    pub fn is_well_formed(&self) -> bool {
+        self.as_str().is_some()
+    }
+
+    /// Get the string as a `&str`, if it is well-formed.
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.wtf8).ok()
+    }
+
+    /// Convert to a `String`, replacing lone surrogates with U+FFFD, like
+    /// `String.prototype.toWellFormed`.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        match self.as_str() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned(String::from_utf16_lossy(&self.to_utf16())),
+        }
+    }
+
+    /// Convert to a `String`, or return the string unchanged if it is not
+    /// well-formed.
+    pub fn into_string(self) -> Result<String, Self> {
+        String::from_utf8(self.wtf8).map_err(|e| Self {
+            wtf8: e.into_bytes(),
+        })
+    }
+}
+
+impl fmt::Display for JsString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl From<String> for JsString {
+    fn from(value: String) -> Self {
+        Self {
+            wtf8: value.into_bytes(),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for JsString {
+    fn from(value: &'a str) -> Self {
+        Self {
+            wtf8: value.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Well-formed strings become [JsValue::String](JsValue::String).
+impl From<JsString> for JsValue {
+    fn from(value: JsString) -> Self {
+        match value.into_string() {
+            Ok(s) => JsValue::String(s),
+            Err(s) => JsValue::IllFormedString(s),
+        }
+    }
+}
+
+impl TryFrom<JsValue> for JsString {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::String(s) => Ok(s.into()),
+            JsValue::IllFormedString(s) => Ok(s),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::convert::TryFrom;
+
+use super::{JsValue, ValueError};
+
+/// The contents of a Javascript typed array, like a `Uint8Array`.
+///
+/// Typed arrays created from Rust take ownership of the vector, so the data
+/// is not copied.
+/// Typed arrays received from Javascript are copied, since the buffer may be
+/// shared with other arrays.
+#[derive(PartialEq, Clone, Debug)]
+#[allow(missing_docs)]
+pub enum TypedArray {
+    Int8(Vec<i8>),
+    Uint8(Vec<u8>),
+    Uint8Clamped(Vec<u8>),
+    Int16(Vec<i16>),
+    Uint16(Vec<u16>),
+    Int32(Vec<i32>),
+    Uint32(Vec<u32>),
+    BigInt64(Vec<i64>),
+    BigUint64(Vec<u64>),
+    Float32(Vec<f32>),
+    Float64(Vec<f64>),
+}
+
+impl TypedArray {
+    /// The name of the Javascript constructor of the array.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TypedArray::Int8(_) => "Int8Array",
+            TypedArray::Uint8(_) => "Uint8Array",
+            TypedArray::Uint8Clamped(_) => "Uint8ClampedArray",
+            TypedArray::Int16(_) => "Int16Array",
+            TypedArray::Uint16(_) => "Uint16Array",
+            TypedArray::Int32(_) => "Int32Array",
+            TypedArray::Uint32(_) => "Uint32Array",
+            TypedArray::BigInt64(_) => "BigInt64Array",
+            TypedArray::BigUint64(_) => "BigUint64Array",
+            TypedArray::Float32(_) => "Float32Array",
+            TypedArray::Float64(_) => "Float64Array",
+        }
+    }
+
+    /// The number of elements.
+    pub fn len(&self) -> usize {
+        match self {
+            TypedArray::Int8(v) => v.len(),
+            TypedArray::Uint8(v) | TypedArray::Uint8Clamped(v) => v.len(),
+            TypedArray::Int16(v) => v.len(),
+            TypedArray::Uint16(v) => v.len(),
+            TypedArray::Int32(v) => v.len(),
+            TypedArray::Uint32(v) => v.len(),
+            TypedArray::BigInt64(v) => v.len(),
+            TypedArray::BigUint64(v) => v.len(),
+            TypedArray::Float32(v) => v.len(),
+            TypedArray::Float64(v) => v.len(),
+        }
+    }
+
+    /// Check if the array is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+macro_rules! typed_array_impl_from {
+    ( $( $t:ty => $var:ident, )* ) => {
+        $(
+            impl From<Vec<$t>> for TypedArray {
+                fn from(value: Vec<$t>) -> Self {
+                    TypedArray::$var(value)
+                }
+            }
+
+            impl TryFrom<TypedArray> for Vec<$t> {
+                type Error = ValueError;
+
+                fn try_from(value: TypedArray) -> Result<Self, Self::Error> {
+                    match value {
+                        TypedArray::$var(inner) => Ok(inner),
+                        _ => Err(ValueError::UnexpectedType),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+typed_array_impl_from! {
+    i8 => Int8,
+    u8 => Uint8,
+    i16 => Int16,
+    u16 => Uint16,
+    i32 => Int32,
+    u32 => Uint32,
+    i64 => BigInt64,
+    u64 => BigUint64,
+    f32 => Float32,
+    f64 => Float64,
+}
+
+impl From<TypedArray> for JsValue {
+    fn from(value: TypedArray) -> Self {
+        JsValue::TypedArray(value)
+    }
+}
+
+impl TryFrom<JsValue> for TypedArray {
+    type Error = ValueError;
+
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+            JsValue::TypedArray(array) => Ok(array),
+            _ => Err(ValueError::UnexpectedType),
+        }
+    }
+}